@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE subscriptions ADD COLUMN source TEXT NOT NULL DEFAULT 'signup';
+ALTER TABLE subscriptions ADD COLUMN consent_attestation TEXT NULL;
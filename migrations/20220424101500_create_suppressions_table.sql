@@ -0,0 +1,8 @@
+-- Add migration script here
+-- addresses that must never be (re-)imported. only a hash of the address is kept,
+-- so that erased subscribers do not leave their email behind.
+CREATE TABLE suppressions(
+    email_hash TEXT NOT NULL PRIMARY KEY,
+    reason TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE audit_log(
+    id uuid NOT NULL PRIMARY KEY,
+    action TEXT NOT NULL,
+    subject_hash TEXT NOT NULL,
+    actor TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
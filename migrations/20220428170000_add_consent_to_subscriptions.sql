@@ -0,0 +1,8 @@
+-- Add migration script here
+ALTER TABLE subscriptions ADD COLUMN consent_at timestamptz NULL;
+ALTER TABLE subscriptions ADD COLUMN consent_ip TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN consent_user_agent TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN signup_form TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN signup_form_version TEXT NULL;
+ALTER TABLE subscriptions ADD COLUMN confirmed_at timestamptz NULL;
+ALTER TABLE subscriptions ADD COLUMN confirmed_ip TEXT NULL;
@@ -0,0 +1,29 @@
+-- Add migration script here
+CREATE TABLE newsletter_issues(
+    id uuid NOT NULL PRIMARY KEY,
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    track_opens BOOLEAN NOT NULL DEFAULT TRUE,
+    published_at timestamptz NOT NULL
+);
+
+CREATE TABLE issue_deliveries(
+    id uuid NOT NULL PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (id),
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id),
+    token TEXT NOT NULL UNIQUE,
+    sent_at timestamptz NULL,
+    first_opened_at timestamptz NULL,
+    open_count INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE delivery_opens(
+    delivery_id uuid NOT NULL
+        REFERENCES issue_deliveries (id),
+    opened_at timestamptz NOT NULL,
+    user_agent TEXT NULL
+);
+CREATE INDEX delivery_opens_delivery_id_idx ON delivery_opens (delivery_id);
@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TABLE link_clicks(
+    delivery_id uuid NOT NULL
+        REFERENCES issue_deliveries (id),
+    url TEXT NOT NULL,
+    clicked_at timestamptz NOT NULL,
+    user_agent TEXT NULL
+);
+CREATE INDEX link_clicks_delivery_id_idx ON link_clicks (delivery_id);
@@ -0,0 +1,5 @@
+-- Add migration script here
+ALTER TABLE issue_deliveries ADD COLUMN failed_at timestamptz NULL;
+ALTER TABLE issue_deliveries ADD COLUMN bounced_at timestamptz NULL;
+ALTER TABLE issue_deliveries ADD COLUMN complained_at timestamptz NULL;
+ALTER TABLE issue_deliveries ADD COLUMN unsubscribed_at timestamptz NULL;
@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE subscriptions ADD COLUMN last_engaged_at timestamptz NULL;
+ALTER TABLE subscriptions ADD COLUMN reengagement_sent_at timestamptz NULL;
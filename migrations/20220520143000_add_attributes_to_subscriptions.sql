@@ -0,0 +1,8 @@
+-- Add migration script here
+CREATE TABLE attribute_definitions(
+    name TEXT NOT NULL PRIMARY KEY,
+    kind TEXT NOT NULL CHECK (kind IN ('string', 'number', 'bool', 'date')),
+    created_at timestamptz NOT NULL
+);
+
+ALTER TABLE subscriptions ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,16 @@
+-- Add migration script here
+CREATE TABLE subscriber_tags(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    tag TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    PRIMARY KEY (subscriber_id, tag)
+);
+
+CREATE INDEX subscriber_tags_tag_idx ON subscriber_tags (tag);
+
+CREATE TABLE segments(
+    name TEXT NOT NULL PRIMARY KEY,
+    expression TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL
+);
@@ -0,0 +1,13 @@
+-- Add migration script here
+ALTER TABLE subscriptions ADD COLUMN frequency TEXT NOT NULL DEFAULT 'every_issue'
+    CHECK (frequency IN ('every_issue', 'weekly_digest'));
+ALTER TABLE subscriptions ADD COLUMN paused_until timestamptz;
+
+-- deliveries to weekly digest subscribers wait here until the next digest
+ALTER TABLE issue_deliveries ADD COLUMN digest BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE topics(
+    tag TEXT NOT NULL PRIMARY KEY,
+    label TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
@@ -0,0 +1,10 @@
+-- Add migration script here
+CREATE TABLE email_changes (
+    token TEXT NOT NULL PRIMARY KEY,
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    new_email TEXT NOT NULL,
+    requested_at timestamptz NOT NULL
+);
+
+CREATE INDEX email_changes_subscriber_id_idx ON email_changes (subscriber_id);
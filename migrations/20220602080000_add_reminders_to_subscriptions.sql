@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE subscriptions ADD COLUMN reminder_count INT NOT NULL DEFAULT 0;
+ALTER TABLE subscriptions ADD COLUMN last_reminded_at timestamptz;
@@ -0,0 +1,34 @@
+-- Add migration script here
+CREATE TABLE sequences (
+    name TEXT NOT NULL PRIMARY KEY,
+    active BOOLEAN NOT NULL DEFAULT true,
+    created_at timestamptz NOT NULL,
+    updated_at timestamptz NOT NULL
+);
+
+-- a step is identified by the number of days after the enrollment it is sent
+CREATE TABLE sequence_steps (
+    sequence TEXT NOT NULL
+        REFERENCES sequences (name) ON DELETE CASCADE,
+    day INT NOT NULL CHECK (day >= 0),
+    subject TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    updated_at timestamptz NOT NULL,
+
+    PRIMARY KEY (sequence, day)
+);
+
+CREATE TABLE sequence_enrollments (
+    subscriber_id uuid NOT NULL
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    sequence TEXT NOT NULL
+        REFERENCES sequences (name) ON DELETE CASCADE,
+    enrolled_at timestamptz NOT NULL,
+    -- the day of the last step sent; NULL before the first one
+    sent_through_day INT,
+    last_sent_at timestamptz,
+    stopped_at timestamptz,
+
+    PRIMARY KEY (subscriber_id, sequence)
+);
@@ -0,0 +1,11 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues ADD COLUMN slug TEXT;
+ALTER TABLE newsletter_issues ADD COLUMN public BOOLEAN NOT NULL DEFAULT true;
+
+-- issues published before the archive existed get a slug from their title
+-- and id, which is unique without having to look at the other slugs
+UPDATE newsletter_issues
+SET slug = trim(BOTH '-' FROM regexp_replace(lower(title), '[^a-z0-9]+', '-', 'g')) || '-' || left(id::text, 8);
+
+ALTER TABLE newsletter_issues ALTER COLUMN slug SET NOT NULL;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_slug_key UNIQUE (slug);
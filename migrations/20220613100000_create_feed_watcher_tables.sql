@@ -0,0 +1,20 @@
+-- Add migration script here
+CREATE TABLE issue_drafts(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    -- where the draft came from, e.g. the link of a feed entry
+    source TEXT NOT NULL,
+    created_at timestamptz NOT NULL
+);
+
+CREATE TABLE feed_entries(
+    feed TEXT NOT NULL,
+    guid TEXT NOT NULL,
+    PRIMARY KEY (feed, guid),
+    seen_at timestamptz NOT NULL,
+    draft_id uuid NULL REFERENCES issue_drafts (id) ON DELETE SET NULL,
+    newsletter_issue_id uuid NULL REFERENCES newsletter_issues (id) ON DELETE SET NULL
+);
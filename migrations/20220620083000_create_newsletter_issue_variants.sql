@@ -0,0 +1,12 @@
+-- Add migration script here
+CREATE TABLE newsletter_issue_variants(
+    newsletter_issue_id uuid NOT NULL REFERENCES newsletter_issues (id) ON DELETE CASCADE,
+    locale TEXT NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, locale),
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL
+);
+
+-- the variant a recipient got; NULL for the default content
+ALTER TABLE issue_deliveries ADD COLUMN locale TEXT NULL;
@@ -0,0 +1,12 @@
+-- Add migration script here
+CREATE TABLE email_outbox(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    html_body TEXT NOT NULL,
+    text_body TEXT NOT NULL,
+    queued_at timestamptz NOT NULL,
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT NULL
+);
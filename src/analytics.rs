@@ -0,0 +1,227 @@
+use chrono::{Duration, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::convert::TryFrom;
+
+/// The width of the buckets of a time series.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interval {
+    Day,
+    Week,
+    Month,
+}
+
+impl Interval {
+    /// The postgres `date_trunc` field.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Interval::Day => "day",
+            Interval::Week => "week",
+            Interval::Month => "month",
+        }
+    }
+}
+
+impl TryFrom<&str> for Interval {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "day" | "daily" => Ok(Self::Day),
+            "week" | "weekly" => Ok(Self::Week),
+            "month" | "monthly" => Ok(Self::Month),
+            other => Err(format!("{} is not a supported interval. use 'day', 'week' or 'month'", other)),
+        }
+    }
+}
+
+/// An inclusive range of days, in UTC.
+#[derive(Debug)]
+pub struct DateRange {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+}
+
+impl DateRange {
+    /// Defaults to the last 30 days.
+    pub fn new(from: Option<NaiveDate>, to: Option<NaiveDate>) -> Result<Self, String> {
+        let to = to.unwrap_or_else(|| Utc::today().naive_utc());
+        let from = from.unwrap_or(to - Duration::days(30));
+
+        if from > to {
+            return Err(format!("the range starts ({}) after it ends ({})", from, to));
+        }
+
+        Ok(Self { from, to })
+    }
+}
+
+#[derive(Serialize)]
+pub struct GrowthReport {
+    pub interval: &'static str,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub series: Vec<GrowthPoint>,
+    pub totals: GrowthFigures,
+}
+
+#[derive(Serialize)]
+pub struct GrowthPoint {
+    pub period_start: NaiveDate,
+    #[serde(flatten)]
+    pub figures: GrowthFigures,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct GrowthFigures {
+    pub signups: i64,
+    /// The signups of the period that have confirmed since.
+    #[serde(skip)]
+    pub confirmed_signups: i64,
+    pub confirmations: i64,
+    pub unsubscribes: i64,
+    /// `confirmed_signups / signups`, `None` when there were no signups.
+    pub confirmation_rate: Option<f64>,
+    /// Confirmed subscribers gained minus subscribers lost.
+    pub net_growth: i64,
+}
+
+impl GrowthFigures {
+    fn new(signups: i64, confirmed_signups: i64, confirmations: i64, unsubscribes: i64) -> Self {
+        let confirmation_rate = if signups > 0 {
+            Some(confirmed_signups as f64 / signups as f64)
+        } else {
+            None
+        };
+
+        Self {
+            signups,
+            confirmed_signups,
+            confirmations,
+            unsubscribes,
+            confirmation_rate,
+            net_growth: confirmations - unsubscribes,
+        }
+    }
+}
+
+#[tracing::instrument(name = "compute subscriber growth", skip(pool))]
+pub async fn subscriber_growth(pool: &PgPool, interval: Interval, range: &DateRange) -> Result<GrowthReport, sqlx::Error> {
+    // every bucket of the range is listed, so that quiet periods show up as zeros
+    let rows = sqlx::query!(
+            r#"
+                WITH buckets AS (
+                    SELECT generate_series(
+                        date_trunc($1, $2::date::timestamp),
+                        date_trunc($1, $3::date::timestamp),
+                        ('1 ' || $1)::interval
+                    ) AS bucket
+                ),
+                events AS (
+                    SELECT
+                        (subscribed_at AT TIME ZONE 'UTC') AS subscribed_at,
+                        (confirmed_at AT TIME ZONE 'UTC') AS confirmed_at,
+                        (unsubscribed_at AT TIME ZONE 'UTC') AS unsubscribed_at
+                    FROM subscriptions
+                )
+                SELECT
+                    b.bucket::date AS "period_start!",
+                    count(*) FILTER (WHERE date_trunc($1, e.subscribed_at) = b.bucket AND e.subscribed_at::date BETWEEN $2 AND $3) AS "signups!",
+                    count(*) FILTER (
+                        WHERE date_trunc($1, e.subscribed_at) = b.bucket AND e.subscribed_at::date BETWEEN $2 AND $3
+                        AND e.confirmed_at IS NOT NULL
+                    ) AS "confirmed_signups!",
+                    count(*) FILTER (WHERE date_trunc($1, e.confirmed_at) = b.bucket AND e.confirmed_at::date BETWEEN $2 AND $3) AS "confirmations!",
+                    count(*) FILTER (WHERE date_trunc($1, e.unsubscribed_at) = b.bucket AND e.unsubscribed_at::date BETWEEN $2 AND $3) AS "unsubscribes!"
+                FROM buckets b
+                LEFT JOIN events e ON date_trunc($1, e.subscribed_at) = b.bucket
+                    OR date_trunc($1, e.confirmed_at) = b.bucket
+                    OR date_trunc($1, e.unsubscribed_at) = b.bucket
+                GROUP BY b.bucket
+                ORDER BY b.bucket
+            "#,
+            interval.as_str(), range.from, range.to
+        )
+        .fetch_all(pool)
+        .await?;
+
+    let series: Vec<GrowthPoint> = rows
+        .into_iter()
+        .map(|r| GrowthPoint {
+            period_start: r.period_start,
+            figures: GrowthFigures::new(r.signups, r.confirmed_signups, r.confirmations, r.unsubscribes),
+        })
+        .collect();
+
+    let totals = totals(&series);
+
+    Ok(GrowthReport { interval: interval.as_str(), from: range.from, to: range.to, series, totals })
+}
+
+fn totals(series: &[GrowthPoint]) -> GrowthFigures {
+    let sum = |figure: fn(&GrowthFigures) -> i64| series.iter().map(|p| figure(&p.figures)).sum::<i64>();
+
+    GrowthFigures::new(
+        sum(|f| f.signups),
+        sum(|f| f.confirmed_signups),
+        sum(|f| f.confirmations),
+        sum(|f| f.unsubscribes)
+    )
+}
+
+/// One row per period.
+pub fn write_csv<W: std::io::Write>(writer: W, report: &GrowthReport) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    writer.write_record(["period_start", "signups", "confirmations", "unsubscribes", "confirmation_rate", "net_growth"])?;
+
+    for point in &report.series {
+        let figures = &point.figures;
+
+        writer.write_record([
+            point.period_start.to_string(),
+            figures.signups.to_string(),
+            figures.confirmations.to_string(),
+            figures.unsubscribes.to_string(),
+            figures.confirmation_rate.map(|rate| format!("{:.4}", rate)).unwrap_or_default(),
+            figures.net_growth.to_string(),
+        ])?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use claim::{assert_err, assert_none};
+    use std::convert::TryFrom;
+    use crate::analytics::{DateRange, GrowthFigures, Interval};
+
+    #[test]
+    fn intervals_accept_both_spellings() {
+        assert_eq!(Interval::try_from("weekly"), Ok(Interval::Week));
+        assert_eq!(Interval::try_from("month"), Ok(Interval::Month));
+        assert_err!(Interval::try_from("hourly"));
+    }
+
+    #[test]
+    fn ranges_must_not_end_before_they_start() {
+        assert_err!(DateRange::new(Some(NaiveDate::from_ymd(2022, 5, 2)), Some(NaiveDate::from_ymd(2022, 5, 1))));
+    }
+
+    #[test]
+    fn net_growth_is_confirmations_minus_unsubscribes() {
+        let figures = GrowthFigures::new(4, 3, 5, 2);
+
+        assert_eq!(figures.net_growth, 3);
+        assert_eq!(figures.confirmation_rate, Some(0.75));
+    }
+
+    #[test]
+    fn there_is_no_confirmation_rate_without_signups() {
+        assert_none!(GrowthFigures::new(0, 0, 1, 0).confirmation_rate);
+    }
+}
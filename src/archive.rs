@@ -0,0 +1,329 @@
+//! The public web archive of sent issues. The archive shows the issue as it
+//! was written, not a recipient's copy: merge fields fall back to their
+//! defaults, and links that only make sense for one recipient (tracking,
+//! unsubscribe, preferences) are taken out.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Map;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::merge::{render_html, render_text, MergeFields};
+
+/// Longest slug made from a title, before a suffix that makes it unique.
+const MAX_SLUG_LENGTH: usize = 80;
+
+/// How much of the text body previews of a shared link show.
+const DESCRIPTION_LENGTH: usize = 200;
+
+/// Paths on our own domain that belong to a single recipient.
+const PRIVATE_PATHS: &[&str] = &["/o/", "/r/", "/unsubscribe/", "/preferences/", "/subscriptions/"];
+
+#[derive(Serialize)]
+pub struct ArchivedIssue {
+    pub slug: String,
+    pub title: String,
+    pub published_at: DateTime<Utc>,
+}
+
+pub struct PublicIssue {
+    pub id: Uuid,
+    pub slug: String,
+    pub title: String,
+    pub text: String,
+    pub html: String,
+    pub published_at: DateTime<Utc>,
+}
+
+/// Lowercase ascii letters and digits, with every other run of characters
+/// turned into a single `-`.
+pub fn slugify(title: &str) -> String {
+    let mut slug = String::with_capacity(title.len());
+
+    for c in title.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+
+        if slug.len() >= MAX_SLUG_LENGTH {
+            break;
+        }
+    }
+
+    let slug = slug.trim_end_matches('-');
+
+    if slug.is_empty() {
+        "issue".to_string()
+    } else {
+        slug.to_string()
+    }
+}
+
+/// The slug of the title, or, when another issue already has it, the slug
+/// followed by the start of the issue id.
+#[tracing::instrument(name = "choose an issue slug", skip(pool))]
+pub async fn unique_slug(pool: &PgPool, title: &str, issue_id: Uuid) -> Result<String, sqlx::Error> {
+    let slug = slugify(title);
+
+    let taken = sqlx::query!(
+            r#"SELECT EXISTS (SELECT 1 FROM newsletter_issues WHERE slug = $1) AS "taken!""#,
+            slug
+        )
+        .fetch_one(pool)
+        .await?
+        .taken;
+
+    if taken {
+        Ok(format!("{}-{}", slug, &issue_id.to_string()[..8]))
+    } else {
+        Ok(slug)
+    }
+}
+
+#[tracing::instrument(name = "list archived issues", skip(pool))]
+pub async fn list_public_issues(pool: &PgPool) -> Result<Vec<ArchivedIssue>, sqlx::Error> {
+    let issues = sqlx::query!(
+            r#"
+                SELECT slug, title, published_at FROM newsletter_issues
+                WHERE public
+                ORDER BY published_at DESC
+            "#
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|r| ArchivedIssue { slug: r.slug, title: public_text(&r.title), published_at: r.published_at })
+        .collect();
+
+    Ok(issues)
+}
+
+#[tracing::instrument(name = "fetch an archived issue", skip(pool))]
+pub async fn fetch_public_issue(pool: &PgPool, slug: &str) -> Result<Option<PublicIssue>, sqlx::Error> {
+    let issue = sqlx::query!(
+            r#"
+                SELECT id, slug, title, text_content, html_content, published_at FROM newsletter_issues
+                WHERE slug = $1 AND public
+            "#,
+            slug
+        )
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(issue.map(|r| PublicIssue {
+        id: r.id,
+        slug: r.slug,
+        title: public_text(&r.title),
+        text: public_text(&r.text_content),
+        html: r.html_content,
+        published_at: r.published_at,
+    }))
+}
+
+/// The latest public issues, newest first, with their content.
+#[tracing::instrument(name = "fetch the latest archived issues", skip(pool))]
+pub async fn recent_public_issues(pool: &PgPool, limit: i64) -> Result<Vec<PublicIssue>, sqlx::Error> {
+    let issues = sqlx::query!(
+            r#"
+                SELECT id, slug, title, text_content, html_content, published_at FROM newsletter_issues
+                WHERE public
+                ORDER BY published_at DESC
+                LIMIT $1
+            "#,
+            limit
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|r| PublicIssue {
+            id: r.id,
+            slug: r.slug,
+            title: public_text(&r.title),
+            text: public_text(&r.text_content),
+            html: r.html_content,
+            published_at: r.published_at,
+        })
+        .collect();
+
+    Ok(issues)
+}
+
+/// Returns false when there is no such issue.
+#[tracing::instrument(name = "change the visibility of an issue", skip(pool))]
+pub async fn set_public(pool: &PgPool, issue_id: Uuid, public: bool) -> Result<bool, sqlx::Error> {
+    let updated = sqlx::query!(
+            r#"UPDATE newsletter_issues SET public = $2 WHERE id = $1"#,
+            issue_id, public
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(updated > 0)
+}
+
+/// Merge fields without a recipient: every field renders its fallback.
+fn public_text(template: &str) -> String {
+    let attributes = Map::new();
+    render_text(template, &MergeFields { name: "", email: "", attributes: &attributes })
+}
+
+/// The html body of an issue as the archive shows it.
+pub fn public_html(html: &str, base_url: &str) -> String {
+    let attributes = Map::new();
+    let html = render_html(body_of(html), &MergeFields { name: "", email: "", attributes: &attributes });
+
+    strip_tracking(&html, base_url)
+}
+
+/// The text of a link preview: the start of the text body, on one line.
+pub fn description(text: &str) -> String {
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    match text.char_indices().nth(DESCRIPTION_LENGTH) {
+        Some((end, _)) => format!("{}…", text[..end].trim_end()),
+        None => text,
+    }
+}
+
+/// What is between the body tags of a full html document, or all of it.
+fn body_of(html: &str) -> &str {
+    let lowercase = html.to_ascii_lowercase();
+
+    let start = match lowercase.find("<body") {
+        Some(tag) => match lowercase[tag..].find('>') {
+            Some(end) => tag + end + 1,
+            None => return html,
+        },
+        None => return html,
+    };
+    let end = lowercase.rfind("</body>").filter(|&end| end >= start).unwrap_or(html.len());
+
+    &html[start..end]
+}
+
+fn is_private(url: &str, base_url: &str) -> bool {
+    if url.to_ascii_lowercase().contains("unsubscribe") {
+        return true;
+    }
+
+    match url.strip_prefix(base_url) {
+        Some(path) => PRIVATE_PATHS.iter().any(|private| path.starts_with(private)),
+        None => false,
+    }
+}
+
+/// The value of an attribute of an html tag.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let lowercase = tag.to_ascii_lowercase();
+    let pattern = format!("{}=", name);
+
+    let start = lowercase
+        .match_indices(&pattern)
+        .find(|(i, _)| lowercase[..*i].ends_with(|c: char| c.is_ascii_whitespace()))?
+        .0 + pattern.len();
+
+    let value = &tag[start..];
+    match value.chars().next()? {
+        quote @ ('"' | '\'') => value[1..].split(quote).next(),
+        _ => value.split(|c: char| c.is_ascii_whitespace() || c == '>').next(),
+    }
+}
+
+/// Removes images and unwraps links that point at per-recipient urls; the
+/// text of an unwrapped link stays.
+pub fn strip_tracking(html: &str, base_url: &str) -> String {
+    let lowercase = html.to_ascii_lowercase();
+    let mut stripped = String::with_capacity(html.len());
+    let mut position = 0;
+    // whether each open link was dropped, so that its closing tag is too
+    let mut open_links: Vec<bool> = Vec::new();
+
+    while let Some(offset) = lowercase[position..].find('<') {
+        let start = position + offset;
+        let end = match lowercase[start..].find('>') {
+            Some(length) => start + length + 1,
+            None => break,
+        };
+        let tag = &html[start..end];
+        let name: String = lowercase[start + 1..end]
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '/')
+            .collect();
+
+        let keep = match name.as_str() {
+            "a" => {
+                let dropped = attribute(tag, "href").is_some_and(|url| is_private(url, base_url));
+                open_links.push(dropped);
+                !dropped
+            },
+            "/a" => !open_links.pop().unwrap_or(false),
+            "img" => !attribute(tag, "src").is_some_and(|url| is_private(url, base_url)),
+            _ => true,
+        };
+
+        stripped.push_str(&html[position..start]);
+        if keep {
+            stripped.push_str(tag);
+        }
+
+        position = end;
+    }
+
+    stripped.push_str(&html[position..]);
+
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::{body_of, description, slugify, strip_tracking};
+
+    const BASE_URL: &str = "https://news.example.com";
+
+    #[test]
+    fn slugs_are_made_of_lowercase_words() {
+        assert_eq!(slugify("Issue #12: What's new in Rust?"), "issue-12-what-s-new-in-rust");
+        assert_eq!(slugify("  ¡Olé!  "), "ol");
+        assert_eq!(slugify("???"), "issue");
+        assert!(slugify(&"long title ".repeat(20)).len() <= 80);
+    }
+
+    #[test]
+    fn per_recipient_links_and_pixels_are_stripped() {
+        let html = format!(
+            r#"<p>Read <a href="https://example.com/post">the post</a>.</p><p><A HREF="{base}/unsubscribe/abc">Unsubscribe</A> or <a href='{base}/preferences/1/sig'>manage</a></p><img src="{base}/o/abc" /><img src="https://example.com/logo.png" />"#,
+            base = BASE_URL
+        );
+
+        assert_eq!(
+            strip_tracking(&html, BASE_URL),
+            r#"<p>Read <a href="https://example.com/post">the post</a>.</p><p>Unsubscribe or manage</p><img src="https://example.com/logo.png" />"#
+        );
+    }
+
+    #[test]
+    fn third_party_unsubscribe_links_are_stripped() {
+        let html = r#"<a href="https://mail.example.com/unsubscribe?u=1">leave</a>"#;
+
+        assert_eq!(strip_tracking(html, BASE_URL), "leave");
+    }
+
+    #[test]
+    fn descriptions_are_one_line_and_short() {
+        assert_eq!(description("Hello\n\n  world"), "Hello world");
+
+        let long = "word ".repeat(100);
+        let short = description(&long);
+        assert!(short.ends_with('…'));
+        assert!(short.chars().count() <= 201);
+    }
+
+    #[test]
+    fn only_the_body_of_a_full_document_is_kept() {
+        assert_eq!(body_of("<html><head><title>x</title></head><body class=\"a\"><p>hi</p></body></html>"), "<p>hi</p>");
+        assert_eq!(body_of("<p>hi</p>"), "<p>hi</p>");
+    }
+}
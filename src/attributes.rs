@@ -0,0 +1,300 @@
+//! Custom subscriber fields. Admins declare each attribute with a type; the
+//! values live in the `subscriptions.attributes` JSONB column and are
+//! validated against the declarations on every write.
+
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+/// Names that are already subscriber fields, or form fields of `/subscriptions`.
+const RESERVED_NAMES: &[&str] = &["name", "email", "form", "form_version", "locale"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttributeKind {
+    String,
+    Number,
+    Bool,
+    /// Stored as a `YYYY-MM-DD` string.
+    Date,
+}
+
+impl AttributeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttributeKind::String => "string",
+            AttributeKind::Number => "number",
+            AttributeKind::Bool => "bool",
+            AttributeKind::Date => "date",
+        }
+    }
+
+    /// Checks a json value, as sent to the admin api.
+    fn validate(&self, value: Value) -> Result<Value, String> {
+        match (self, value) {
+            (_, Value::Null) => Ok(Value::Null),
+            (AttributeKind::String, value @ Value::String(_)) => Ok(value),
+            (AttributeKind::Number, value @ Value::Number(_)) => Ok(value),
+            (AttributeKind::Bool, value @ Value::Bool(_)) => Ok(value),
+            (AttributeKind::Date, Value::String(date)) => self.parse(&date),
+            (kind, value) => Err(format!("{} is not a {}", value, kind.as_str())),
+        }
+    }
+
+    /// Converts a form field, where every value is a string.
+    pub(crate) fn parse(&self, raw: &str) -> Result<Value, String> {
+        let raw = raw.trim();
+
+        match self {
+            AttributeKind::String => Ok(Value::String(raw.to_string())),
+            AttributeKind::Number => {
+                if let Ok(integer) = raw.parse::<i64>() {
+                    return Ok(Value::from(integer));
+                }
+
+                raw.parse::<f64>()
+                    .ok()
+                    .and_then(serde_json::Number::from_f64)
+                    .map(Value::Number)
+                    .ok_or_else(|| format!("{} is not a number", raw))
+            },
+            AttributeKind::Bool => match raw.to_lowercase().as_str() {
+                "true" | "on" | "yes" | "1" => Ok(Value::Bool(true)),
+                "false" | "off" | "no" | "0" => Ok(Value::Bool(false)),
+                _ => Err(format!("{} is not a bool", raw)),
+            },
+            AttributeKind::Date => NaiveDate::parse_from_str(raw, "%Y-%m-%d")
+                .map(|date| Value::String(date.to_string()))
+                .map_err(|_| format!("{} is not a date (YYYY-MM-DD)", raw)),
+        }
+    }
+}
+
+impl TryFrom<&str> for AttributeKind {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "string" => Ok(Self::String),
+            "number" => Ok(Self::Number),
+            "bool" => Ok(Self::Bool),
+            "date" => Ok(Self::Date),
+            other => Err(format!("{} is not an attribute type. use 'string', 'number', 'bool' or 'date'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttributeDefinition {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: AttributeKind,
+}
+
+impl AttributeDefinition {
+    pub fn parse(name: String, kind: AttributeKind) -> Result<Self, String> {
+        let is_identifier = name.chars().next().is_some_and(|c| c.is_ascii_lowercase())
+            && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+
+        if !is_identifier {
+            return Err(format!("{} is not a valid attribute name. use lowercase letters, digits and underscores", name));
+        }
+        if RESERVED_NAMES.contains(&name.as_str()) {
+            return Err(format!("{} is reserved", name));
+        }
+
+        Ok(Self { name, kind })
+    }
+}
+
+pub struct AttributeSchema(Vec<AttributeDefinition>);
+
+impl AttributeSchema {
+    pub fn new(definitions: Vec<AttributeDefinition>) -> Self {
+        Self(definitions)
+    }
+
+    pub fn definitions(&self) -> &[AttributeDefinition] {
+        &self.0
+    }
+
+    pub fn kind_of(&self, name: &str) -> Option<AttributeKind> {
+        self.0.iter().find(|d| d.name == name).map(|d| d.kind)
+    }
+
+    /// Keeps the form fields that are declared attributes and converts them
+    /// to their type. Other fields are ignored, and empty fields are left out.
+    pub fn from_form(&self, fields: &HashMap<String, String>) -> Result<Map<String, Value>, String> {
+        let mut attributes = Map::new();
+
+        for (name, raw) in fields {
+            if let Some(kind) = self.kind_of(name) {
+                if raw.trim().is_empty() {
+                    continue;
+                }
+
+                let value = kind.parse(raw).map_err(|e| format!("{}: {}", name, e))?;
+                attributes.insert(name.clone(), value);
+            }
+        }
+
+        Ok(attributes)
+    }
+
+    /// Checks the attributes sent to the admin api. Unlike form fields,
+    /// undeclared attributes are an error. `null` removes an attribute.
+    pub fn from_json(&self, values: Map<String, Value>) -> Result<Map<String, Value>, String> {
+        values
+            .into_iter()
+            .map(|(name, value)| {
+                let kind = self.kind_of(&name).ok_or_else(|| format!("{} is not a declared attribute", name))?;
+                let value = kind.validate(value).map_err(|e| format!("{}: {}", name, e))?;
+
+                Ok((name, value))
+            })
+            .collect()
+    }
+}
+
+#[tracing::instrument(name = "fetch attribute definitions", skip(pool))]
+pub async fn fetch_schema(pool: &PgPool) -> Result<AttributeSchema, sqlx::Error> {
+    let definitions = sqlx::query!(r#"SELECT name, kind FROM attribute_definitions ORDER BY name"#)
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .filter_map(|r| {
+            let kind = AttributeKind::try_from(r.kind.as_str()).ok()?;
+
+            Some(AttributeDefinition { name: r.name, kind })
+        })
+        .collect();
+
+    Ok(AttributeSchema(definitions))
+}
+
+/// Declares an attribute, or changes its type. Values stored before a type
+/// change are left as they are.
+#[tracing::instrument(name = "define an attribute", skip(pool))]
+pub async fn define_attribute(pool: &PgPool, definition: &AttributeDefinition) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+            r#"
+                INSERT INTO attribute_definitions (name, kind, created_at)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (name) DO UPDATE SET kind = EXCLUDED.kind
+            "#,
+            definition.name, definition.kind.as_str(), Utc::now()
+        )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Removes the declaration and the values stored under it.
+#[tracing::instrument(name = "remove an attribute", skip(pool))]
+pub async fn remove_attribute(pool: &PgPool, name: &str) -> Result<bool, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let removed = sqlx::query!(r#"DELETE FROM attribute_definitions WHERE name = $1"#, name)
+        .execute(&mut transaction)
+        .await?
+        .rows_affected();
+
+    sqlx::query!(r#"UPDATE subscriptions SET attributes = attributes - $1 WHERE attributes ? $1"#, name)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(removed > 0)
+}
+
+/// Merges `attributes` into the stored ones; `null` values remove an attribute.
+#[tracing::instrument(name = "update subscriber attributes", skip(pool, attributes))]
+pub async fn update_attributes(pool: &PgPool, subscriber_id: uuid::Uuid, attributes: Map<String, Value>) -> Result<bool, sqlx::Error> {
+    let (removed, set): (Map<String, Value>, Map<String, Value>) = attributes.into_iter().partition(|(_, v)| v.is_null());
+    let removed: Vec<String> = removed.into_iter().map(|(name, _)| name).collect();
+
+    let updated = sqlx::query!(
+            r#"UPDATE subscriptions SET attributes = (attributes - $3::text[]) || $2 WHERE id = $1"#,
+            subscriber_id, Value::Object(set), &removed
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(updated > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use crate::attributes::{AttributeDefinition, AttributeKind, AttributeSchema};
+
+    fn schema() -> AttributeSchema {
+        AttributeSchema::new(vec![
+            AttributeDefinition { name: "company".into(), kind: AttributeKind::String },
+            AttributeDefinition { name: "seats".into(), kind: AttributeKind::Number },
+            AttributeDefinition { name: "beta".into(), kind: AttributeKind::Bool },
+            AttributeDefinition { name: "renewal".into(), kind: AttributeKind::Date },
+        ])
+    }
+
+    fn form(fields: &[(&str, &str)]) -> HashMap<String, String> {
+        fields.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn form_fields_are_converted_to_their_type() {
+        let attributes = schema()
+            .from_form(&form(&[("company", "Acme"), ("seats", "12"), ("beta", "on"), ("renewal", "2022-12-01")]))
+            .unwrap();
+
+        assert_eq!(
+            serde_json::Value::Object(attributes),
+            json!({ "company": "Acme", "seats": 12, "beta": true, "renewal": "2022-12-01" })
+        );
+    }
+
+    #[test]
+    fn undeclared_and_empty_form_fields_are_ignored() {
+        let attributes = schema().from_form(&form(&[("utm_source", "twitter"), ("company", " ")])).unwrap();
+
+        assert!(attributes.is_empty());
+    }
+
+    #[test]
+    fn form_fields_of_the_wrong_type_are_rejected() {
+        assert_err!(schema().from_form(&form(&[("seats", "a dozen")])));
+        assert_err!(schema().from_form(&form(&[("renewal", "01/12/2022")])));
+    }
+
+    #[test]
+    fn json_values_must_be_declared_and_of_the_right_type() {
+        let values = |v: serde_json::Value| v.as_object().unwrap().clone();
+
+        assert_ok!(schema().from_json(values(json!({ "seats": 3.5, "company": null }))));
+        assert_err!(schema().from_json(values(json!({ "plan": "pro" }))));
+        assert_err!(schema().from_json(values(json!({ "beta": "yes" }))));
+    }
+
+    #[test]
+    fn attribute_names_are_identifiers_and_not_reserved() {
+        assert_ok!(AttributeDefinition::parse("plan_2".into(), AttributeKind::String));
+        assert_err!(AttributeDefinition::parse("Plan".into(), AttributeKind::String));
+        assert_err!(AttributeDefinition::parse("email".into(), AttributeKind::String));
+    }
+
+    #[test]
+    fn locale_is_reserved_for_the_language_of_the_subscription() {
+        assert_eq!(
+            AttributeDefinition::parse("locale".into(), AttributeKind::String).unwrap_err(),
+            "locale is reserved"
+        );
+    }
+}
@@ -0,0 +1,24 @@
+use chrono::Utc;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+use crate::suppression::hash_email;
+
+/// Who made a request: an admin username, or `cli` for commands run on the server.
+pub const CLI_ACTOR: &str = "cli";
+
+/// Records a request about a data subject. The address is stored hashed,
+/// like the suppression list, so the log does not undo an erasure.
+#[tracing::instrument(name = "write an audit log entry", skip(executor, email))]
+pub async fn record<'e>(executor: impl PgExecutor<'e>, action: &str, email: &str, actor: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+            r#"
+                INSERT INTO audit_log (id, action, subject_hash, actor, created_at)
+                VALUES ($1, $2, $3, $4, $5)
+            "#,
+            Uuid::new_v4(), action, hash_email(email), actor, Utc::now()
+        )
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
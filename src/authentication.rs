@@ -0,0 +1,201 @@
+use actix_web::dev::Payload;
+use actix_web::http::header::{HeaderMap, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use anyhow::Context;
+use futures::future::LocalBoxFuture;
+use secrecy::{ExposeSecret, Secret};
+use sqlx::PgPool;
+use std::fmt::Formatter;
+use uuid::Uuid;
+use crate::routes::error_chain_fmt;
+
+pub struct Credentials {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum AuthError {
+    #[error("invalid credentials")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AuthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self,f)
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::InvalidCredentials(_) => StatusCode::UNAUTHORIZED,
+            AuthError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            AuthError::InvalidCredentials(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="admin""#).unwrap();
+
+                response
+                    .headers_mut()
+                    .insert(actix_web::http::header::WWW_AUTHENTICATE, header_value);
+
+                response
+            },
+            AuthError::UnexpectedError(_) => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+}
+
+/// An admin that authenticated with http basic auth.
+/// Taking it as a handler argument is enough to protect a route.
+pub struct AdminUser {
+    pub user_id: Uuid,
+    pub username: String,
+}
+
+impl FromRequest for AdminUser {
+    type Error = AuthError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let credentials = basic_authentication(req.headers());
+        let pool = req.app_data::<web::Data<PgPool>>().cloned();
+
+        Box::pin(async move {
+            let credentials = credentials.map_err(AuthError::InvalidCredentials)?;
+            let pool = pool.context("the connection pool is not registered as app data")?;
+            let username = credentials.username.clone();
+            let user_id = validate_credentials(credentials, &pool).await?;
+
+            Ok(AdminUser { user_id, username })
+        })
+    }
+}
+
+pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("the 'Authorization' header was missing")?
+        .to_str()
+        .context("the 'Authorization' header was not a valid utf8 string")?;
+
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("the authorization scheme was not 'Basic'")?;
+
+    let decoded_bytes = base64::decode_config(base64encoded_segment, base64::STANDARD)
+        .context("failed to base64-decode 'Basic' credentials")?;
+
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("the decoded credential string is not valid utf8")?;
+
+    let mut credentials = decoded_credentials.splitn(2, ':');
+
+    let username = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("a username must be provided in 'Basic' auth"))?
+        .to_string();
+
+    let password = credentials
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("a password must be provided in 'Basic' auth"))?
+        .to_string();
+
+    Ok(Credentials { username, password: Secret::new(password) })
+}
+
+#[tracing::instrument(name = "validate credentials", skip(credentials, pool))]
+pub async fn validate_credentials(credentials: Credentials, pool: &PgPool) -> Result<Uuid, AuthError> {
+    let mut user_id = None;
+
+    // verify against a dummy hash when the user does not exist, so that
+    // response times do not reveal which usernames are valid
+    let mut expected_password_hash = Secret::new(
+        "$argon2id$v=19$m=15000,t=2,p=1$\
+        gZiV/M1gPc22ElAH/Jh1Hw$\
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno"
+            .to_string()
+    );
+
+    if let Some((stored_user_id, stored_password_hash)) = get_stored_credentials(&credentials.username, pool).await? {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    tokio::task::spawn_blocking(move || verify_password_hash(expected_password_hash, credentials.password))
+        .await
+        .context("failed to spawn blocking task")??;
+
+    user_id
+        .ok_or_else(|| anyhow::anyhow!("unknown username"))
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(name = "get stored credentials", skip(username, pool))]
+async fn get_stored_credentials(username: &str, pool: &PgPool) -> Result<Option<(Uuid, Secret<String>)>, anyhow::Error> {
+    let row = sqlx::query!(
+            r#"SELECT user_id, password_hash FROM users WHERE username = $1"#,
+            username
+        )
+        .fetch_optional(pool)
+        .await
+        .context("failed to retrieve stored credentials")?
+        .map(|row| (row.user_id, Secret::new(row.password_hash)));
+
+    Ok(row)
+}
+
+#[tracing::instrument(name = "verify password hash", skip(expected_password_hash, password_candidate))]
+fn verify_password_hash(expected_password_hash: Secret<String>, password_candidate: Secret<String>) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("failed to parse hash in PHC string format")?;
+
+    Argon2::default()
+        .verify_password(password_candidate.expose_secret().as_bytes(), &expected_password_hash)
+        .context("invalid password")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+pub fn compute_password_hash(password: Secret<String>) -> Result<Secret<String>, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+
+    let password_hash = Argon2::new(
+            Algorithm::Argon2id,
+            Version::V0x13,
+            Params::new(15000, 2, 1, None).unwrap(),
+        )
+        .hash_password(password.expose_secret().as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!(e))?
+        .to_string();
+
+    Ok(Secret::new(password_hash))
+}
+
+#[tracing::instrument(name = "create an admin user", skip(pool, password))]
+pub async fn create_admin(pool: &PgPool, username: &str, password: Secret<String>) -> Result<Uuid, anyhow::Error> {
+    let user_id = Uuid::new_v4();
+
+    let password_hash = tokio::task::spawn_blocking(move || compute_password_hash(password))
+        .await
+        .context("failed to spawn blocking task")??;
+
+    sqlx::query!(
+            r#"INSERT INTO users (user_id, username, password_hash) VALUES ($1, $2, $3)"#,
+            user_id, username, password_hash.expose_secret()
+        )
+        .execute(pool)
+        .await
+        .context("failed to store admin user")?;
+
+    Ok(user_id)
+}
@@ -0,0 +1,155 @@
+//! A circuit breaker around the email provider. After `failure_threshold`
+//! failed calls in a row (errors, timeouts or 5xx answers) the circuit opens
+//! and sends fail at once instead of waiting out the timeout. After
+//! `open_for`, one call goes through as a probe: the circuit closes if it
+//! works and opens again if it does not.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_for: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    /// A probe is on its way. Another one may go out at `until`, if the
+    /// first never comes back.
+    HalfOpen { until: Instant },
+}
+
+/// The breaker at one point in time, for readiness and metrics.
+#[derive(serde::Serialize, Debug, PartialEq)]
+#[serde(tag = "circuit", rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed { consecutive_failures: u32 },
+    Open { retry_in_seconds: u64 },
+    HalfOpen,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, open_for: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            open_for,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether a call may go out now.
+    pub fn allow(&self) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { until } | State::HalfOpen { until } if now < until => false,
+            State::Open { .. } | State::HalfOpen { .. } => {
+                tracing::info!("probing the email provider");
+                *state = State::HalfOpen { until: now + self.open_for };
+                true
+            },
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        if !matches!(*state, State::Closed { .. }) {
+            tracing::info!("the email provider is back, closing the circuit");
+        }
+        *state = State::Closed { failures: 0 };
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+
+        let failures = match *state {
+            State::Closed { failures } => failures + 1,
+            // a probe failed, or a call that went out before the circuit opened
+            State::Open { .. } | State::HalfOpen { .. } => self.failure_threshold,
+        };
+
+        if failures < self.failure_threshold {
+            *state = State::Closed { failures };
+            return;
+        }
+
+        if !matches!(*state, State::Open { .. }) {
+            tracing::warn!(
+                failures,
+                open_for = ?self.open_for,
+                "the email provider keeps failing, opening the circuit"
+            );
+        }
+        *state = State::Open { until: Instant::now() + self.open_for };
+    }
+
+    pub fn state(&self) -> CircuitState {
+        let now = Instant::now();
+
+        match *self.state.lock().unwrap() {
+            State::Closed { failures } => CircuitState::Closed { consecutive_failures: failures },
+            State::Open { until } if now < until => CircuitState::Open {
+                retry_in_seconds: until.saturating_duration_since(now).as_secs_f64().ceil() as u64,
+            },
+            State::Open { .. } | State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::circuit_breaker::{CircuitBreaker, CircuitState};
+
+    #[test]
+    fn the_circuit_opens_after_consecutive_failures_only() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(30));
+
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.allow());
+        assert_eq!(breaker.state(), CircuitState::Closed { consecutive_failures: 2 });
+
+        breaker.record_failure();
+        assert!(!breaker.allow());
+        assert_eq!(breaker.state(), CircuitState::Open { retry_in_seconds: 30 });
+    }
+
+    #[test]
+    fn a_single_probe_goes_out_once_the_circuit_has_been_open_long_enough() {
+        let breaker = CircuitBreaker::new(1, Duration::ZERO);
+
+        breaker.record_failure();
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        assert!(breaker.allow());
+        breaker.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed { consecutive_failures: 0 });
+    }
+
+    #[test]
+    fn a_failed_probe_opens_the_circuit_again() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(20));
+
+        breaker.record_failure();
+        breaker.record_failure();
+        std::thread::sleep(Duration::from_millis(30));
+
+        assert!(breaker.allow());
+        // only the probe goes out
+        assert!(!breaker.allow());
+
+        breaker.record_failure();
+        assert!(matches!(breaker.state(), CircuitState::Open { .. }));
+        assert!(!breaker.allow());
+    }
+}
@@ -0,0 +1,69 @@
+use clap::Subcommand;
+use sqlx::{Connection, PgConnection};
+use crate::configuration::Settings;
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Validate the configuration and check that the database is reachable.
+    Check,
+}
+
+pub async fn run(config: &Settings, command: ConfigCommand) -> Result<(), anyhow::Error> {
+    match command {
+        ConfigCommand::Check => check(config).await,
+    }
+}
+
+async fn check(config: &Settings) -> Result<(), anyhow::Error> {
+    let checks = vec![
+        (
+            "application.base_url",
+            reqwest::Url::parse(&config.application.base_url)
+                .map(|_| config.application.base_url.clone())
+                .map_err(|e| e.to_string()),
+        ),
+        (
+            "email_client.base_url",
+            reqwest::Url::parse(&config.email_client.base_url)
+                .map(|_| config.email_client.base_url.clone())
+                .map_err(|e| e.to_string()),
+        ),
+        (
+            "email_client.sender_email",
+            config.email_client.sender().map(|sender| sender.to_string()),
+        ),
+        ("database", check_database(config).await),
+    ];
+
+    let mut failures = 0;
+
+    for (name, outcome) in checks {
+        match outcome {
+            Ok(value) => println!("ok      {} ({})", name, value),
+            Err(err) => {
+                failures += 1;
+                println!("failed  {}: {}", name, err);
+            },
+        }
+    }
+
+    if failures > 0 {
+        anyhow::bail!("{} configuration check(s) failed", failures);
+    }
+
+    Ok(())
+}
+
+async fn check_database(config: &Settings) -> Result<String, String> {
+    let options = config.database.with_db();
+    let connect = PgConnection::connect_with(&options);
+
+    match tokio::time::timeout(std::time::Duration::from_secs(5), connect).await {
+        Ok(Ok(connection)) => {
+            let _ = connection.close().await;
+            Ok(format!("{}:{}/{}", config.database.host, config.database.port, config.database.database_name))
+        },
+        Ok(Err(err)) => Err(err.to_string()),
+        Err(_) => Err("timed out connecting to the database".into()),
+    }
+}
@@ -0,0 +1,52 @@
+use clap::Subcommand;
+use crate::audit::CLI_ACTOR;
+use crate::configuration::Settings;
+use crate::domain::SubscriberEmail;
+use crate::gdpr::{access, erase};
+use crate::startup::get_connection_pool;
+
+#[derive(Subcommand)]
+pub enum GdprCommand {
+    /// Print everything held about an email address as json.
+    Access {
+        email: String,
+    },
+    /// Delete the subscription and tokens of an email address and keep it suppressed.
+    Erase {
+        email: String,
+        /// Erasure cannot be undone, so it has to be confirmed.
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+pub async fn run(config: &Settings, command: GdprCommand) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&config.database);
+
+    match command {
+        GdprCommand::Access { email } => {
+            let email = SubscriberEmail::parse(email).map_err(anyhow::Error::msg)?;
+            let data = access(&pool, email.as_ref(), CLI_ACTOR).await?;
+
+            println!("{}", serde_json::to_string_pretty(&data)?);
+
+            Ok(())
+        },
+        GdprCommand::Erase { email, yes } => {
+            let email = SubscriberEmail::parse(email).map_err(anyhow::Error::msg)?;
+
+            if !yes {
+                anyhow::bail!("erasing {} cannot be undone, pass --yes to confirm", email);
+            }
+
+            let report = erase(&pool, email.as_ref(), CLI_ACTOR).await?;
+
+            println!(
+                "erased {}: deleted {} subscriptions, {} tokens and {} deliveries, address suppressed",
+                email, report.subscriptions, report.tokens, report.deliveries
+            );
+
+            Ok(())
+        },
+    }
+}
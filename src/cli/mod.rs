@@ -0,0 +1,202 @@
+mod config;
+mod gdpr;
+mod subscribers;
+
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use secrecy::Secret;
+use crate::authentication::create_admin;
+use crate::configuration::{get_configuration, Settings};
+use crate::digest::send_digests;
+use crate::domain::SubscriberEmail;
+use crate::feed_watcher::watch_feed;
+use crate::scheduler;
+use crate::startup::{get_connection_pool, Application};
+
+pub use config::ConfigCommand;
+pub use gdpr::GdprCommand;
+pub use subscribers::SubscribersCommand;
+
+#[derive(Parser)]
+#[command(name = "newsletter", about = "Runs the newsletter server and its admin tasks.")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the http server. This is the default when no subcommand is given.
+    Serve,
+    /// Run the database migrations embedded in the binary.
+    Migrate,
+    /// Create an admin user.
+    CreateAdmin {
+        #[arg(long)]
+        username: String,
+        #[arg(long, env = "NEWSLETTER_ADMIN_PASSWORD", hide_env_values = true)]
+        password: String,
+    },
+    /// Send a test email through the configured email provider.
+    SendTestEmail {
+        recipient: String,
+    },
+    /// Manage subscribers.
+    Subscribers {
+        #[command(subcommand)]
+        command: SubscribersCommand,
+    },
+    /// Send the weekly digest to the subscribers who chose it.
+    Digest,
+    /// Turn the new entries of the watched feed into drafts or issues.
+    WatchFeed,
+    /// Handle data subject access and erasure requests.
+    Gdpr {
+        #[command(subcommand)]
+        command: GdprCommand,
+    },
+    /// Inspect the configuration.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+impl Command {
+    /// Every command but `serve` writes its logs to stderr, so that stdout
+    /// can be piped (e.g. `newsletter subscribers export > subscribers.csv`).
+    pub fn logs_to_stdout(&self) -> bool {
+        matches!(self, Command::Serve)
+    }
+}
+
+pub async fn run(command: Command) -> Result<(), anyhow::Error> {
+    let config = get_configuration().context("failed to read configuration")?;
+
+    match command {
+        Command::Serve => serve(&config).await,
+        Command::Migrate => migrate(&config).await,
+        Command::CreateAdmin { username, password } => {
+            let pool = get_connection_pool(&config.database);
+            let user_id = create_admin(&pool, &username, Secret::new(password)).await?;
+
+            println!("created admin user {} ({})", username, user_id);
+
+            Ok(())
+        },
+        Command::SendTestEmail { recipient } => send_test_email(&config, recipient).await,
+        Command::Subscribers { command } => subscribers::run(&config, command).await,
+        Command::Digest => send_digest(&config).await,
+        Command::WatchFeed => watch(&config).await,
+        Command::Gdpr { command } => gdpr::run(&config, command).await,
+        Command::Config { command } => config::run(&config, command).await,
+    }
+}
+
+async fn serve(config: &Settings) -> Result<(), anyhow::Error> {
+    let app = Application::build(config).await?;
+    // one client, so that the throttle and the circuit breaker see every send
+    let email_client = app.email_client().clone();
+
+    // the scheduler runs until the process exits, so this returns when the server stops
+    tokio::select! {
+        result = app.run_until_stopped() => result?,
+        result = scheduler::run_until_stopped(config, email_client) => result?,
+    }
+
+    Ok(())
+}
+
+async fn migrate(config: &Settings) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&config.database);
+
+    sqlx::migrate!("./migrations")
+        .run(&pool)
+        .await
+        .context("failed to run migrations")?;
+
+    println!("migrations applied to {}", config.database.database_name);
+
+    Ok(())
+}
+
+async fn send_digest(config: &Settings) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&config.database);
+    let email_client = config.email_client.client();
+
+    let report = send_digests(&pool, &email_client, &config.application.base_url, &config.application.hmac_secret).await?;
+
+    println!("sent {} digests with {} issues ({} failed)", report.sent, report.issues, report.failed);
+
+    Ok(())
+}
+
+async fn watch(config: &Settings) -> Result<(), anyhow::Error> {
+    if config.feed_watcher.source().is_none() {
+        anyhow::bail!("no feed to watch. set feed_watcher.source");
+    }
+
+    let pool = get_connection_pool(&config.database);
+    let email_client = config.email_client.client();
+
+    let report = watch_feed(
+        &pool,
+        &email_client,
+        &config.application.base_url,
+        &config.application.hmac_secret,
+        &config.feed_watcher,
+    ).await?;
+
+    println!(
+        "{} new entries: {} drafted, {} published ({} failed)",
+        report.new_entries, report.drafted, report.published, report.failed
+    );
+
+    Ok(())
+}
+
+async fn send_test_email(config: &Settings, recipient: String) -> Result<(), anyhow::Error> {
+    let recipient = SubscriberEmail::parse(recipient).map_err(anyhow::Error::msg)?;
+    let email_client = config.email_client.client();
+
+    email_client
+        .send_email(
+            &recipient,
+            "Test email",
+            "This is a test email sent by <code>newsletter send-test-email</code>.",
+            "This is a test email sent by `newsletter send-test-email`."
+        )
+        .await
+        .with_context(|| format!("failed to send test email to {}", recipient))?;
+
+    println!("test email sent to {}", recipient);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use crate::cli::{Cli, Command, SubscribersCommand};
+
+    #[test]
+    fn no_subcommand_defaults_to_none() {
+        let cli = Cli::try_parse_from(["newsletter"]).unwrap();
+        assert!(cli.command.is_none());
+    }
+
+    #[test]
+    fn send_test_email_requires_a_recipient() {
+        assert!(Cli::try_parse_from(["newsletter", "send-test-email"]).is_err());
+    }
+
+    #[test]
+    fn subscribers_export_is_parsed() {
+        let cli = Cli::try_parse_from(["newsletter", "subscribers", "export"]).unwrap();
+
+        assert!(matches!(
+            cli.command,
+            Some(Command::Subscribers { command: SubscribersCommand::Export { output: None, status: None } })
+        ));
+    }
+}
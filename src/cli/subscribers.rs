@@ -0,0 +1,145 @@
+use std::convert::TryInto;
+use std::path::PathBuf;
+use anyhow::Context;
+use clap::Subcommand;
+use crate::configuration::Settings;
+use crate::export::{fetch_subscribers, write_csv, ExportFilter};
+use crate::import::{import_subscribers, read_records, ImportOptions, ImportParameters, ImportReport, MailchimpStatus};
+use crate::locales::DEFAULT_LOCALE;
+use crate::reminders::send_reminders;
+use crate::startup::get_connection_pool;
+use crate::sunset::run_sunset;
+
+#[derive(Subcommand)]
+pub enum SubscribersCommand {
+    /// Export subscribers as csv (email, name, status, subscribed_at).
+    Export {
+        /// Write to this file instead of stdout.
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+        /// Only export subscribers with this status, e.g. `confirmed`.
+        #[arg(long)]
+        status: Option<String>,
+    },
+    /// Import subscribers from a csv file with `email` and `name` columns,
+    /// a Mailchimp audience export or a Substack subscriber export.
+    Import {
+        file: PathBuf,
+        /// `csv`, `mailchimp` or `substack`.
+        #[arg(long, default_value = "csv")]
+        format: String,
+        /// The status of a Mailchimp export file (`subscribed`, `unsubscribed` or `cleaned`).
+        /// Guessed from the file name when omitted.
+        #[arg(long)]
+        status: Option<String>,
+        /// `pending` sends confirmation emails, `confirmed` requires --consent
+        /// for csv files. Defaults to `pending` for csv files and to `confirmed`
+        /// for Mailchimp and Substack exports.
+        #[arg(long)]
+        mode: Option<String>,
+        /// How the imported subscribers gave their consent.
+        #[arg(long)]
+        consent: Option<String>,
+        /// Validate the file and report errors without importing anything.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Send a confirmation reminder to the pending subscribers who are due one.
+    /// `serve` does this on its own every `scheduler.poll_interval_seconds`.
+    Remind,
+    /// Apply the sunset policy: send re-engagement emails and move subscribers
+    /// who ignored them to `inactive`.
+    Sunset {
+        /// Report what would happen without sending or changing anything.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+pub async fn run(config: &Settings, command: SubscribersCommand) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&config.database);
+
+    match command {
+        SubscribersCommand::Export { output, status } => {
+            let subscribers = fetch_subscribers(&pool, &ExportFilter { status })
+                .await
+                .context("failed to fetch subscribers")?;
+
+            match output {
+                Some(path) => {
+                    let file = std::fs::File::create(&path)
+                        .with_context(|| format!("failed to create {}", path.display()))?;
+                    write_csv(file, &subscribers)?;
+                },
+                None => write_csv(std::io::stdout(), &subscribers)?,
+            }
+
+            Ok(())
+        },
+        SubscribersCommand::Import { file, format, status, mode, consent, dry_run } => {
+            let status = status.or_else(|| {
+                let file_name = file.file_name()?.to_str()?;
+
+                MailchimpStatus::from_file_name(file_name).map(|status| status.as_str().to_string())
+            });
+
+            let options: ImportOptions = ImportParameters { format: Some(format), status, mode, consent, dry_run }
+                .try_into()
+                .map_err(anyhow::Error::msg)?;
+
+            let file = std::fs::File::open(&file)
+                .with_context(|| format!("failed to open {}", file.display()))?;
+            let (records, errors) = read_records(&options.format, file).map_err(anyhow::Error::msg)?;
+
+            let email_client = config.email_client.client();
+            let locales = config.locales.catalogs()?;
+            let messages = locales.messages(DEFAULT_LOCALE);
+            let report = import_subscribers(&pool, &email_client, &config.application.base_url, &messages, records, errors, &options).await?;
+
+            print_report(&report);
+
+            Ok(())
+        },
+        SubscribersCommand::Remind => {
+            let email_client = config.email_client.client();
+            let locales = config.locales.catalogs()?;
+            let report = send_reminders(&pool, &email_client, &config.application.base_url, &locales, &config.reminders).await?;
+
+            println!("sent {} confirmation reminders ({} failed)", report.sent, report.failed);
+
+            Ok(())
+        },
+        SubscribersCommand::Sunset { dry_run } => {
+            let email_client = config.email_client.client();
+            let report = run_sunset(
+                &pool,
+                &email_client,
+                &config.application.base_url,
+                &config.application.hmac_secret,
+                &config.sunset,
+                dry_run
+            ).await?;
+
+            let (send, deactivate) = if dry_run { ("would send", "would deactivate") } else { ("sent", "deactivated") };
+            println!(
+                "{} {} re-engagement emails ({} failed), {} {} subscribers",
+                send, report.reengagement_sent, report.reengagement_failed, deactivate, report.deactivated
+            );
+
+            Ok(())
+        },
+    }
+}
+
+fn print_report(report: &ImportReport) {
+    for error in &report.errors {
+        println!("row {}: {} ({})", error.row, error.error, error.email);
+    }
+
+    let verb = if report.dry_run { "would import" } else { "imported" };
+
+    println!(
+        "{} {} of {} rows, suppressed {} (unsubscribing {} existing), skipped {}",
+        verb, report.imported, report.total_rows, report.suppressed, report.unsubscribed, report.errors.len()
+    );
+}
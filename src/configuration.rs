@@ -5,12 +5,98 @@ use sqlx::postgres::{PgConnectOptions, PgSslMode};
 use sqlx::ConnectOptions;
 
 use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::throttle::Throttle;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::locales::Locales;
+use std::path::Path;
 
 #[derive(serde::Deserialize)]
 pub struct Settings {
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
+    pub sunset: SunsetSettings,
+    pub reminders: ReminderSettings,
+    pub scheduler: SchedulerSettings,
+    pub feed_watcher: FeedWatcherSettings,
+    pub locales: LocaleSettings,
+}
+
+/// When to give up on subscribers who stopped reading.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct SunsetSettings {
+    /// Issues without an open or a click before a re-engagement email is sent.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub inactive_after_issues: i64,
+    /// Days to wait for a reaction to the re-engagement email before the
+    /// subscriber is moved to `inactive`.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub grace_period_days: i64,
+}
+
+/// Confirmation reminders for subscribers who never followed the link.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct ReminderSettings {
+    /// Reminders sent at most, on top of the first confirmation email.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_reminders: i32,
+    /// Hours between the signup and the first reminder, and between reminders.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub interval_hours: i64,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct SchedulerSettings {
+    /// How often `serve` looks for scheduled work, in seconds.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub poll_interval_seconds: u64,
+}
+
+impl SchedulerSettings {
+    pub fn poll_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.poll_interval_seconds)
+    }
+}
+
+/// Turns the new entries of a feed into issues.
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct FeedWatcherSettings {
+    /// The url of the feed, or the path of a local file. Without one nothing
+    /// is watched.
+    pub source: Option<String>,
+    pub mode: FeedWatcherMode,
+    /// `{{ entry.title }}`, `{{ entry.summary }}` and `{{ entry.link }}` are
+    /// replaced with the entry; merge fields are left for the send.
+    pub html_template: String,
+    pub text_template: String,
+}
+
+impl FeedWatcherSettings {
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref().map(str::trim).filter(|source| !source.is_empty())
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FeedWatcherMode {
+    /// New entries become drafts an admin publishes.
+    Draft,
+    /// New entries are sent to every confirmed subscriber straight away.
+    Send,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct LocaleSettings {
+    /// Where the message catalogs of extra locales are.
+    pub directory: String,
+}
+
+impl LocaleSettings {
+    pub fn catalogs(&self) -> Result<Locales, anyhow::Error> {
+        Locales::load(Path::new(&self.directory))
+    }
 }
 
 #[derive(serde::Deserialize)]
@@ -18,7 +104,23 @@ pub struct EmailClientSettings {
     pub base_url: String,
     pub sender_email: String,
     pub timeout: u64,
-    pub authorization_token: Secret<String>
+    pub authorization_token: Secret<String>,
+    /// Emails of an issue sent at once.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub concurrency: usize,
+    /// Send issues through the `/email/batch` endpoint of the provider.
+    pub batch: bool,
+    /// The quotas of the provider, in messages; 0 is no limit.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_per_second: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_per_hour: u32,
+    /// Failed calls in a row that open the circuit to the provider, and the
+    /// seconds it stays open before a probe.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub circuit_failure_threshold: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub circuit_open_seconds: u64,
 }
 
 impl EmailClientSettings {
@@ -29,6 +131,24 @@ impl EmailClientSettings {
     pub fn timeout(&self) -> std::time::Duration {
         std::time::Duration::from_millis(self.timeout)
     }
+
+    pub fn client(&self) -> EmailClient {
+        let sender_email = self.sender().expect("invalid sender email address.");
+
+        EmailClient::new(
+            self.base_url.clone(),
+            sender_email,
+            self.authorization_token.clone(),
+            self.timeout()
+        )
+        .with_concurrency(self.concurrency)
+        .with_batch(self.batch)
+        .with_throttle(Throttle::new(self.max_per_second, self.max_per_hour))
+        .with_circuit_breaker(CircuitBreaker::new(
+            self.circuit_failure_threshold,
+            std::time::Duration::from_secs(self.circuit_open_seconds)
+        ))
+    }
 }
 
 #[derive(serde::Deserialize)]
@@ -37,6 +157,12 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    pub hmac_secret: Secret<String>,
+    /// The title of the feeds.
+    pub newsletter_name: String,
+    /// Reverse proxies whose `X-Forwarded-For` is believed.
+    #[serde(default)]
+    pub trusted_proxies: Vec<std::net::IpAddr>,
 }
 
 #[derive(serde::Deserialize)]
@@ -115,7 +241,7 @@ impl DatabaseSettings {
         PgConnectOptions::new()
             .host(&self.host)
             .username(&self.username)
-            .password(&self.password.expose_secret())
+            .password(self.password.expose_secret())
             .port(self.port)
             .ssl_mode(ssl_mode)
     }
@@ -0,0 +1,118 @@
+use actix_web::{web, HttpRequest};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::net::IpAddr;
+use uuid::Uuid;
+use crate::startup::TrustedProxies;
+
+/// What we know about how a subscriber opted in and confirmed.
+#[derive(Serialize)]
+pub struct ConsentRecord {
+    /// `signup`, or the importer the subscriber came from.
+    pub source: String,
+    /// How imported subscribers gave their consent.
+    pub attestation: Option<String>,
+    pub consent_at: Option<DateTime<Utc>>,
+    pub consent_ip: Option<String>,
+    pub consent_user_agent: Option<String>,
+    pub signup_form: Option<String>,
+    pub signup_form_version: Option<String>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub confirmed_ip: Option<String>,
+}
+
+/// The request details recorded when someone fills in a signup form.
+pub struct SignupConsent {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub form: Option<String>,
+    pub form_version: Option<String>,
+}
+
+/// The address of the client. `X-Forwarded-For` is only believed when the
+/// request comes from one of `application.trusted_proxies`, since anyone can
+/// send it.
+pub fn client_ip(request: &HttpRequest) -> Option<String> {
+    let peer = request.peer_addr()?.ip();
+    let trusted = request
+        .app_data::<web::Data<TrustedProxies>>()
+        .map_or(&[][..], |proxies| &proxies.0[..]);
+
+    let forwarded_for = request
+        .headers()
+        .get_all("X-Forwarded-For")
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    Some(forwarded_client(peer, &forwarded_for, trusted).to_string())
+}
+
+/// Each proxy appends the address it got the request from, so the client is
+/// the last address that is not one of our proxies. Whatever comes before it
+/// was sent by the client and cannot be trusted.
+fn forwarded_client(peer: IpAddr, forwarded_for: &str, trusted: &[IpAddr]) -> IpAddr {
+    let mut client = peer;
+
+    for hop in forwarded_for.rsplit(',') {
+        if !trusted.contains(&client) {
+            break;
+        }
+        match hop.trim().parse() {
+            Ok(address) => client = address,
+            Err(_) => break,
+        }
+    }
+
+    client
+}
+
+pub fn user_agent(request: &HttpRequest) -> Option<String> {
+    request
+        .headers()
+        .get("User-Agent")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+#[tracing::instrument(name = "fetch the consent record of a subscriber", skip(pool))]
+pub async fn fetch_consent(pool: &PgPool, subscriber_id: Uuid) -> Result<Option<ConsentRecord>, sqlx::Error> {
+    sqlx::query_as!(
+            ConsentRecord,
+            r#"
+                SELECT source, consent_attestation AS attestation, consent_at, consent_ip, consent_user_agent,
+                    signup_form, signup_form_version, confirmed_at, confirmed_ip
+                FROM subscriptions
+                WHERE id = $1
+            "#,
+            subscriber_id
+        )
+        .fetch_optional(pool)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use crate::consent::forwarded_client;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn forwarded_addresses_are_ignored_unless_the_peer_is_a_trusted_proxy() {
+        assert_eq!(forwarded_client(ip("203.0.113.7"), "198.51.100.1", &[]), ip("203.0.113.7"));
+        assert_eq!(forwarded_client(ip("203.0.113.7"), "198.51.100.1", &[ip("10.0.0.1")]), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn the_client_is_the_last_address_our_proxies_did_not_add() {
+        let proxies = [ip("10.0.0.1"), ip("10.0.0.2")];
+
+        // the client made up the first address
+        assert_eq!(forwarded_client(ip("10.0.0.1"), "1.2.3.4, 198.51.100.1, 10.0.0.2", &proxies), ip("198.51.100.1"));
+        assert_eq!(forwarded_client(ip("10.0.0.1"), "", &proxies), ip("10.0.0.1"));
+    }
+}
@@ -0,0 +1,197 @@
+//! The weekly digest. Issues published to subscribers who chose the weekly
+//! digest are stored as queued deliveries; the digest sends each of them one
+//! email with every issue they have been queued since the last one.
+
+use anyhow::Context;
+use chrono::Utc;
+use secrecy::Secret;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::domain::SubscriberEmail;
+use crate::email_client::{Email, EmailClient};
+use crate::merge::{html_escape, render_text, MergeFields};
+use crate::preferences::preferences_url;
+use crate::tracking::{add_footer, inject_pixel, list_unsubscribe_headers, personalize, pixel_url, unsubscribe_url};
+
+#[derive(Serialize, Debug, Default)]
+pub struct DigestReport {
+    /// Digest emails sent, and the issues they contained.
+    pub sent: usize,
+    pub issues: usize,
+    pub failed: usize,
+}
+
+struct QueuedIssue {
+    delivery_id: Uuid,
+    token: String,
+    title: String,
+    text: String,
+    html: String,
+    track_opens: bool,
+}
+
+struct Digest {
+    subscriber_id: Uuid,
+    email: String,
+    name: String,
+    attributes: Map<String, Value>,
+    issues: Vec<QueuedIssue>,
+}
+
+/// Paused subscribers keep their queue until the pause ends. Each issue is
+/// in the variant chosen when it was published.
+#[tracing::instrument(name = "fetch queued digest deliveries", skip(pool))]
+async fn fetch_digests(pool: &PgPool) -> Result<Vec<Digest>, sqlx::Error> {
+    let rows = sqlx::query!(
+            r#"
+                SELECT d.id, d.token, d.subscriber_id, s.email, s.name, s.attributes,
+                    COALESCE(v.title, i.title) AS "title!",
+                    COALESCE(v.text_content, i.text_content) AS "text_content!",
+                    COALESCE(v.html_content, i.html_content) AS "html_content!",
+                    i.track_opens
+                FROM issue_deliveries d
+                JOIN subscriptions s ON s.id = d.subscriber_id
+                JOIN newsletter_issues i ON i.id = d.newsletter_issue_id
+                LEFT JOIN newsletter_issue_variants v ON v.newsletter_issue_id = i.id AND v.locale = d.locale
+                WHERE d.digest AND d.sent_at IS NULL AND d.failed_at IS NULL
+                    AND s.status IN ('confirmed', 'inactive')
+                    AND (s.paused_until IS NULL OR s.paused_until <= now())
+                ORDER BY d.subscriber_id, i.published_at
+            "#
+        )
+        .fetch_all(pool)
+        .await?;
+
+    let mut digests: Vec<Digest> = Vec::new();
+
+    for row in rows {
+        let issue = QueuedIssue {
+            delivery_id: row.id,
+            token: row.token,
+            title: row.title,
+            text: row.text_content,
+            html: row.html_content,
+            track_opens: row.track_opens,
+        };
+
+        match digests.last_mut() {
+            Some(digest) if digest.subscriber_id == row.subscriber_id => digest.issues.push(issue),
+            _ => digests.push(Digest {
+                subscriber_id: row.subscriber_id,
+                email: row.email,
+                name: row.name,
+                attributes: match row.attributes {
+                    Value::Object(attributes) => attributes,
+                    _ => Map::new(),
+                },
+                issues: vec![issue],
+            }),
+        }
+    }
+
+    Ok(digests)
+}
+
+#[tracing::instrument(name = "send the weekly digest", skip(pool, email_client, base_url, hmac_secret))]
+pub async fn send_digests(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+) -> Result<DigestReport, anyhow::Error> {
+    let digests = fetch_digests(pool).await.context("failed to fetch the queued deliveries")?;
+    let mut report = DigestReport::default();
+
+    for digest in digests {
+        let delivery_ids: Vec<Uuid> = digest.issues.iter().map(|issue| issue.delivery_id).collect();
+
+        match send_digest(email_client, base_url, hmac_secret, &digest).await {
+            Ok(()) => {
+                sqlx::query!(
+                        r#"UPDATE issue_deliveries SET sent_at = $2 WHERE id = ANY($1)"#,
+                        &delivery_ids, Utc::now()
+                    )
+                    .execute(pool)
+                    .await
+                    .context("failed to mark the digest deliveries as sent")?;
+
+                report.sent += 1;
+                report.issues += delivery_ids.len();
+            },
+            Err(e) => {
+                tracing::warn!(error.cause_chain = ?e, "failed to send a digest");
+
+                sqlx::query!(
+                        r#"UPDATE issue_deliveries SET failed_at = $2 WHERE id = ANY($1)"#,
+                        &delivery_ids, Utc::now()
+                    )
+                    .execute(pool)
+                    .await
+                    .context("failed to mark the digest deliveries as failed")?;
+
+                report.failed += 1;
+            },
+        }
+    }
+
+    Ok(report)
+}
+
+/// Every issue keeps its own delivery token, so opens and clicks are
+/// attributed to the issue they belong to.
+async fn send_digest(
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    digest: &Digest,
+) -> Result<(), anyhow::Error> {
+    let recipient = SubscriberEmail::parse(digest.email.clone()).map_err(anyhow::Error::msg)?;
+    let fields = MergeFields { name: &digest.name, email: &digest.email, attributes: &digest.attributes };
+
+    let mut html_sections = Vec::with_capacity(digest.issues.len());
+    let mut text_sections = Vec::with_capacity(digest.issues.len());
+
+    for issue in &digest.issues {
+        let title = render_text(&issue.title, &fields);
+        let (mut html, text) = personalize(&issue.html, &issue.text, &fields, base_url, hmac_secret, &issue.token);
+
+        if issue.track_opens {
+            html = inject_pixel(&html, &pixel_url(base_url, &issue.token));
+        }
+
+        html_sections.push(format!("<h2>{}</h2>{}", html_escape(&title), html));
+        text_sections.push(format!("{}\n\n{}", title, text));
+    }
+
+    let subject = match digest.issues.as_slice() {
+        [issue] => render_text(&issue.title, &fields),
+        issues => format!("Your weekly digest: {} issues", issues.len()),
+    };
+
+    // any of the tokens attributes the unsubscribe to an issue of the digest
+    let last_token = &digest.issues[digest.issues.len() - 1].token;
+    let unsubscribe_url = unsubscribe_url(base_url, last_token);
+    let (html, text) = add_footer(
+        &html_sections.join("<hr />"),
+        &text_sections.join("\n\n----\n\n"),
+        &unsubscribe_url,
+        &preferences_url(base_url, hmac_secret, digest.subscriber_id),
+    );
+
+    let email = Email {
+        recipient: &recipient,
+        subject: &subject,
+        html_body: &html,
+        text_body: &text,
+        headers: &list_unsubscribe_headers(&unsubscribe_url),
+    };
+
+    email_client
+        .send(&email)
+        .await
+        .with_context(|| format!("failed to send the digest to {}", recipient))?;
+
+    Ok(())
+}
@@ -0,0 +1,102 @@
+//! Issues that are written but not sent yet. Publishing a draft sends it to
+//! every confirmed subscriber, like `POST /newsletters` with the defaults.
+
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::email_client::EmailClient;
+use crate::routes::{publish_issue_in, BodyData, PublishError, PublishReport};
+
+#[derive(Serialize)]
+pub struct Draft {
+    pub id: Uuid,
+    pub title: String,
+    pub text: String,
+    pub html: String,
+    pub source: String,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct DraftContent<'a> {
+    pub title: &'a str,
+    pub text: &'a str,
+    pub html: &'a str,
+    pub source: &'a str,
+}
+
+#[tracing::instrument(name = "list drafts", skip(pool))]
+pub async fn list_drafts(pool: &PgPool) -> Result<Vec<Draft>, sqlx::Error> {
+    sqlx::query_as!(
+            Draft,
+            r#"
+                SELECT id, title, text_content AS text, html_content AS html, source, created_at
+                FROM issue_drafts
+                ORDER BY created_at
+            "#
+        )
+        .fetch_all(pool)
+        .await
+}
+
+#[tracing::instrument(name = "store a draft", skip(pool, content))]
+pub async fn insert_draft(pool: &PgPool, content: &DraftContent<'_>) -> Result<Uuid, sqlx::Error> {
+    let draft_id = Uuid::new_v4();
+
+    sqlx::query!(
+            r#"
+                INSERT INTO issue_drafts (id, title, text_content, html_content, source, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            draft_id, content.title, content.text, content.html, content.source, Utc::now()
+        )
+        .execute(pool)
+        .await?;
+
+    Ok(draft_id)
+}
+
+#[tracing::instrument(name = "delete a draft", skip(pool))]
+pub async fn delete_draft(pool: &PgPool, draft_id: Uuid) -> Result<bool, sqlx::Error> {
+    let deleted = sqlx::query!(r#"DELETE FROM issue_drafts WHERE id = $1"#, draft_id)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(deleted > 0)
+}
+
+/// Returns what happened to the issue, or `None` when there is no such
+/// draft. The draft is taken out in the transaction that stores the issue, so
+/// that publishing it twice at once does not send it twice, and a draft the
+/// issue could not be made from is kept.
+#[tracing::instrument(name = "publish a draft", skip(pool, email_client, base_url, hmac_secret))]
+pub async fn publish_draft(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    draft_id: Uuid,
+) -> Result<Option<PublishReport>, PublishError> {
+    let mut transaction = pool.begin()
+        .await
+        .map_err(|e| PublishError::ServerSideError(anyhow::Error::new(e).context("failed to start a transaction")))?;
+
+    let draft = sqlx::query!(
+            r#"DELETE FROM issue_drafts WHERE id = $1 RETURNING title, text_content, html_content"#,
+            draft_id
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .map_err(|e| PublishError::ServerSideError(anyhow::Error::new(e).context("failed to take out the draft")))?;
+
+    let draft = match draft {
+        Some(draft) => draft,
+        None => return Ok(None),
+    };
+
+    let issue = BodyData::new(draft.title, draft.text_content, draft.html_content);
+
+    publish_issue_in(transaction, pool, email_client, base_url, hmac_secret, &issue).await.map(Some)
+}
@@ -0,0 +1,145 @@
+//! Moving a subscription to a new address. The change is requested from the
+//! preference page and only applied once the new address confirms it.
+
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::outbox::send_or_enqueue;
+use crate::routes::generate_subscription_token;
+
+/// How long the confirmation link sent to the new address works for.
+pub const EMAIL_CHANGE_VALID_HOURS: i64 = 48;
+
+#[derive(thiserror::Error, Debug)]
+pub enum EmailChangeError {
+    #[error("{0} is already subscribed to this newsletter")]
+    Taken(String),
+    #[error(transparent)]
+    Unexpected(#[from] sqlx::Error),
+}
+
+/// Stores a pending change and returns the token that confirms it. Only the
+/// latest request of a subscriber can be confirmed.
+#[tracing::instrument(name = "request an email address change", skip(pool, new_email))]
+pub async fn request_email_change(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    new_email: &SubscriberEmail,
+) -> Result<String, EmailChangeError> {
+    let taken = sqlx::query!(
+            r#"SELECT id FROM subscriptions WHERE lower(email) = lower($1)"#,
+            new_email.as_ref()
+        )
+        .fetch_optional(pool)
+        .await?;
+
+    if taken.is_some() {
+        return Err(EmailChangeError::Taken(new_email.to_string()));
+    }
+
+    let token = generate_subscription_token();
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query!(r#"DELETE FROM email_changes WHERE subscriber_id = $1"#, subscriber_id)
+        .execute(&mut transaction)
+        .await?;
+
+    sqlx::query!(
+            r#"
+                INSERT INTO email_changes (token, subscriber_id, new_email, requested_at)
+                VALUES ($1, $2, $3, $4)
+            "#,
+            token, subscriber_id, new_email.as_ref(), Utc::now()
+        )
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(token)
+}
+
+/// While the email provider is down or rate limiting, the email waits in the outbox.
+#[tracing::instrument(name = "send an email change confirmation", skip(pool, email_client, new_email, base_url, token))]
+pub async fn send_email_change_confirmation(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    new_email: &SubscriberEmail,
+    base_url: &str,
+    token: &str,
+) -> Result<(), anyhow::Error> {
+    let confirmation_link = format!("{}/subscriptions/email/confirm?token={}", base_url, token);
+
+    let html_body = format!(
+        "Someone asked to move their newsletter subscription to this address. <br />\
+        Click <a href=\"{}\">here</a> to confirm. If it was not you, ignore this email.",
+        confirmation_link
+    );
+
+    let text_body = format!(
+        "Someone asked to move their newsletter subscription to this address.\n\
+        Visit {} to confirm. If it was not you, ignore this email.",
+        confirmation_link
+    );
+
+    send_or_enqueue(pool, email_client, new_email, "Confirm your new address", &html_body, &text_body).await
+}
+
+/// Swaps the address of the subscription the token was issued for, and
+/// returns the new address. `None` means the token is unknown or expired.
+#[tracing::instrument(name = "confirm an email address change", skip(pool, token))]
+pub async fn confirm_email_change(pool: &PgPool, token: &str) -> Result<Option<String>, EmailChangeError> {
+    let mut transaction = pool.begin().await?;
+
+    let change = sqlx::query!(
+            r#"
+                SELECT subscriber_id, new_email FROM email_changes
+                WHERE token = $1 AND requested_at > $2
+                FOR UPDATE
+            "#,
+            token, Utc::now() - Duration::hours(EMAIL_CHANGE_VALID_HOURS)
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    let change = match change {
+        Some(change) => change,
+        None => return Ok(None),
+    };
+
+    // the address may have subscribed since the change was requested, maybe
+    // spelled with other capitals
+    let taken = sqlx::query!(
+            r#"SELECT id FROM subscriptions WHERE lower(email) = lower($1) AND id <> $2"#,
+            change.new_email, change.subscriber_id
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    if taken.is_some() {
+        return Err(EmailChangeError::Taken(change.new_email));
+    }
+
+    sqlx::query!(
+            r#"UPDATE subscriptions SET email = $2 WHERE id = $1"#,
+            change.subscriber_id, change.new_email
+        )
+        .execute(&mut transaction)
+        .await
+        .map_err(|e| match &e {
+            sqlx::Error::Database(db) if db.code().as_deref() == Some("23505") => {
+                EmailChangeError::Taken(change.new_email.clone())
+            },
+            _ => EmailChangeError::Unexpected(e),
+        })?;
+
+    sqlx::query!(r#"DELETE FROM email_changes WHERE subscriber_id = $1"#, change.subscriber_id)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(Some(change.new_email))
+}
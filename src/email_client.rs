@@ -1,6 +1,41 @@
-use reqwest::Client;
+use std::sync::Arc;
+use std::time::Duration;
+use reqwest::{Client, Response, StatusCode};
 use secrecy::{Secret, ExposeSecret};
 use crate::domain::SubscriberEmail;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::throttle::Throttle;
+
+/// How many emails are sent at once, unless configured otherwise.
+const DEFAULT_CONCURRENCY: usize = 10;
+
+/// The most messages Postmark takes in one call to `/email/batch`.
+pub const MAX_BATCH_SIZE: usize = 500;
+
+/// Times a call the provider rate limited is made again, after the pause it
+/// asked for.
+const MAX_RATE_LIMITED_RETRIES: usize = 3;
+
+/// The pause after a `429` without a usable `Retry-After`.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+/// Failed calls in a row that open the circuit, and how long it stays open,
+/// unless configured otherwise.
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_OPEN_FOR: Duration = Duration::from_secs(30);
+
+#[derive(thiserror::Error, Debug)]
+pub enum SendEmailError {
+    /// The provider failed too often lately, so the email was not sent.
+    #[error("the circuit to the email provider is open")]
+    CircuitOpen,
+    /// The quotas of the provider, or its rate limiting, would hold the email
+    /// back longer than an interactive client waits, so it was not sent.
+    #[error("the email provider is rate limiting, so the email was not sent")]
+    Throttled,
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+}
 
 #[derive(Clone)]
 pub struct EmailClient {
@@ -8,6 +43,13 @@ pub struct EmailClient {
     http_client: Client,
     base_url: String,   
     authorization_token : Secret<String>,
+    concurrency: usize,
+    batch: bool,
+    throttle: Arc<Throttle>,
+    breaker: Arc<CircuitBreaker>,
+    timeout: Duration,
+    /// How long a send waits for the throttle at most; no limit if `None`.
+    max_throttle_wait: Option<Duration>,
 }
 
 #[derive(serde::Serialize)]
@@ -17,6 +59,65 @@ struct SendEmailRequest<'a> {
     subject: &'a str,
     html_body: &'a str,
     text_body: &'a str,
+    #[serde(skip_serializing_if = "<[Header]>::is_empty")]
+    headers: &'a [Header],
+}
+
+/// An extra header of an email, such as `List-Unsubscribe`.
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct Header {
+    pub name: &'static str,
+    pub value: String,
+}
+
+/// An email with extra headers, or one message of a batch.
+pub struct Email<'a> {
+    pub recipient: &'a SubscriberEmail,
+    pub subject: &'a str,
+    pub html_body: &'a str,
+    pub text_body: &'a str,
+    pub headers: &'a [Header],
+}
+
+/// The outcome of one message of a batch, in the order they were sent.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BatchResult {
+    error_code: i64,
+    message: String,
+}
+
+#[derive(thiserror::Error, Debug, Clone)]
+pub enum BatchError {
+    #[error("the provider refused the message: {message} (error code {code})")]
+    Refused { code: i64, message: String },
+    /// The call for the chunk the message was in failed as a whole.
+    #[error("failed to send the batch: {reason}")]
+    Request { reason: String, transient: bool },
+}
+
+impl BatchError {
+    /// Whether the message may go through if it is sent again: the provider
+    /// rate limited it (error code or status `429`), answered `5xx`, or the
+    /// connection failed before the batch went out. A timeout is not
+    /// transient, as the provider may have taken the batch.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, BatchError::Refused { code: 429, .. } | BatchError::Request { transient: true, .. })
+    }
+}
+
+impl From<SendEmailError> for BatchError {
+    fn from(e: SendEmailError) -> Self {
+        let transient = match &e {
+            SendEmailError::CircuitOpen | SendEmailError::Throttled => false,
+            SendEmailError::Request(e) => match e.status() {
+                Some(status) => status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+                None => e.is_connect(),
+            },
+        };
+
+        BatchError::Request { reason: format!("{:#}", anyhow::Error::from(e)), transient }
+    }
 }
 
 impl EmailClient {
@@ -30,32 +131,199 @@ impl EmailClient {
             http_client,
             base_url,
             sender,
-            authorization_token
+            authorization_token,
+            concurrency: DEFAULT_CONCURRENCY,
+            batch: false,
+            throttle: Arc::new(Throttle::unlimited()),
+            breaker: Arc::new(CircuitBreaker::new(DEFAULT_FAILURE_THRESHOLD, DEFAULT_OPEN_FOR)),
+            timeout,
+            max_throttle_wait: None,
         }
     }
 
-    pub async fn send_email(&self, recipient: &SubscriberEmail, subject: &str, html_body: &str, text_body: &str) -> Result<(), reqwest::Error> {
+    /// Bulk sends keep at most `concurrency` requests to the provider in
+    /// flight, over the connections of the one http client.
+    pub fn with_concurrency(self, concurrency: usize) -> Self {
+        Self { concurrency: concurrency.max(1), ..self }
+    }
+
+    pub fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    /// Whether the provider has a batch endpoint, so bulk sends can use
+    /// `send_batch`.
+    pub fn with_batch(self, batch: bool) -> Self {
+        Self { batch, ..self }
+    }
+
+    pub fn supports_batch(&self) -> bool {
+        self.batch
+    }
+
+    /// Every send, from this client or its clones, waits for `throttle`.
+    pub fn with_throttle(self, throttle: Throttle) -> Self {
+        Self { throttle: Arc::new(throttle), ..self }
+    }
+
+    pub fn throttle(&self) -> &Throttle {
+        &self.throttle
+    }
+
+    /// Every send, from this client or its clones, goes through `breaker`.
+    pub fn with_circuit_breaker(self, breaker: CircuitBreaker) -> Self {
+        Self { breaker: Arc::new(breaker), ..self }
+    }
+
+    pub fn circuit_breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    /// A client for requests someone is waiting on: a send that the throttle
+    /// would hold back longer than the request timeout fails at once with
+    /// `SendEmailError::Throttled`. It shares the throttle and the circuit
+    /// breaker of this client.
+    pub fn interactive(&self) -> Self {
+        Self { max_throttle_wait: Some(self.timeout), ..self.clone() }
+    }
+
+    pub async fn send_email(&self, recipient: &SubscriberEmail, subject: &str, html_body: &str, text_body: &str) -> Result<(), SendEmailError> {
+        self.send(&Email { recipient, subject, html_body, text_body, headers: &[] }).await
+    }
+
+    pub async fn send(&self, email: &Email<'_>) -> Result<(), SendEmailError> {
         let url = format!("{}/email",self.base_url);
 
-        let request_body = SendEmailRequest {
+        self.post(&url, &self.request(email), 1).await?;
+
+        Ok(())
+    }
+
+    fn request<'a>(&'a self, email: &Email<'a>) -> SendEmailRequest<'a> {
+        SendEmailRequest {
             from: self.sender.as_ref(),
-            to: recipient.as_ref(),
-            subject,
-            html_body,
-            text_body,
-        };
+            to: email.recipient.as_ref(),
+            subject: email.subject,
+            html_body: email.html_body,
+            text_body: email.text_body,
+            headers: email.headers,
+        }
+    }
 
-        self
-            .http_client
-            .post(&url)
-            .header("X-POSTMARK-SERVER-TOKEN", self.authorization_token.expose_secret())
-            .json(&request_body)
-            .send()
+    /// Sends `emails` to `/email/batch`, `MAX_BATCH_SIZE` per call, and returns
+    /// the outcome of every message in the same order. A call that fails as a
+    /// whole fails all the messages of its chunk.
+    pub async fn send_batch(&self, emails: &[Email<'_>]) -> Vec<Result<(), BatchError>> {
+        let mut outcomes = Vec::with_capacity(emails.len());
+
+        for chunk in emails.chunks(MAX_BATCH_SIZE) {
+            match self.send_chunk(chunk).await {
+                Ok(results) => outcomes.extend(results.into_iter().map(|result| match result.error_code {
+                    0 => Ok(()),
+                    code => Err(BatchError::Refused { code, message: result.message }),
+                })),
+                Err(e) => outcomes.extend(chunk.iter().map(|_| Err(e.clone()))),
+            }
+        }
+
+        outcomes
+    }
+
+    async fn send_chunk(&self, chunk: &[Email<'_>]) -> Result<Vec<BatchResult>, BatchError> {
+        let url = format!("{}/email/batch",self.base_url);
+
+        let request_body: Vec<SendEmailRequest> = chunk
+            .iter()
+            .map(|email| self.request(email))
+            .collect();
+
+        // the provider took the batch once it answers, so a bad answer is
+        // not worth sending the batch again
+        let not_transient = |reason: String| BatchError::Request { reason, transient: false };
+
+        let results: Vec<BatchResult> = self
+            .post(&url, &request_body, chunk.len())
             .await?
-            .error_for_status()?;
-        
-        Ok(())
+            .json()
+            .await
+            .map_err(|e| not_transient(format!("failed to read the answer of the provider: {}", e)))?;
+
+        if results.len() != chunk.len() {
+            return Err(not_transient(format!("the provider answered {} results for {} messages", results.len(), chunk.len())));
+        }
+
+        Ok(results)
+    }
+
+    /// Posts `body`, which holds `messages` emails, once the circuit and the
+    /// throttle let it through. A `429` pauses the throttle and the call is
+    /// made again.
+    async fn post(&self, url: &str, body: &impl serde::Serialize, messages: usize) -> Result<Response, SendEmailError> {
+        let mut retries = 0;
+
+        loop {
+            if !self.breaker.allow() {
+                return Err(SendEmailError::CircuitOpen);
+            }
+
+            match self.max_throttle_wait {
+                Some(max_wait) => {
+                    if !self.throttle.acquire_within(messages, max_wait).await {
+                        return Err(SendEmailError::Throttled);
+                    }
+                },
+                None => self.throttle.acquire(messages).await,
+            }
+
+            let response = self
+                .http_client
+                .post(url)
+                .header("X-POSTMARK-SERVER-TOKEN", self.authorization_token.expose_secret())
+                .json(body)
+                .send()
+                .await;
+
+            // errors, timeouts included, and 5xx count against the provider;
+            // a 4xx is about the request, which the provider did answer
+            let response = match response {
+                Ok(response) if response.status().is_server_error() => {
+                    self.breaker.record_failure();
+                    response
+                },
+                Ok(response) => {
+                    self.breaker.record_success();
+                    response
+                },
+                Err(e) => {
+                    self.breaker.record_failure();
+                    return Err(e.into());
+                },
+            };
+
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                self.throttle.rate_limited(retry_after(&response).unwrap_or(DEFAULT_RETRY_AFTER));
+
+                if retries < MAX_RATE_LIMITED_RETRIES {
+                    retries += 1;
+                    continue;
+                }
+            }
+
+            return Ok(response.error_for_status()?);
+        }
+    }
+}
+
+/// The `Retry-After` of a response, in seconds or as an http date.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
     }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&chrono::Utc) - chrono::Utc::now()).to_std().unwrap_or_default())
 }
 
 #[cfg(test)]
@@ -63,7 +331,7 @@ mod tests {
     use secrecy::Secret;
     use claim::{assert_ok,assert_err};
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{BatchError, Email, EmailClient, MAX_BATCH_SIZE};
     use fake::faker::internet::en::SafeEmail;
     use fake::faker::lorem::en::{Paragraph,Sentence};
     use fake::{Fake,Faker};
@@ -170,4 +438,114 @@ mod tests {
 
         assert_err!(outcome);
     }
-}
\ No newline at end of file
+
+    fn batch_results(error_codes: &[i64]) -> serde_json::Value {
+        error_codes
+            .iter()
+            .map(|&code| serde_json::json!({
+                "ErrorCode": code,
+                "Message": if code == 0 { "OK" } else { "You tried to send to a recipient that has been marked as inactive." },
+            }))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn send_batch_returns_the_outcome_of_every_message() {
+        let mock_server  = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients: Vec<SubscriberEmail> = (0..3).map(|_| email()).collect();
+        let (subject, content) = (subject(), content());
+
+        Mock::given(header_exists("X-POSTMARK-SERVER-TOKEN"))
+            .and(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(batch_results(&[0, 406, 0])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let emails: Vec<Email> = recipients
+            .iter()
+            .map(|recipient| Email { recipient, subject: &subject, html_body: &content, text_body: &content, headers: &[] })
+            .collect();
+        let outcomes = email_client.send_batch(&emails).await;
+
+        assert_eq!(outcomes.len(), 3);
+        assert_ok!(&outcomes[0]);
+        assert!(matches!(outcomes[1], Err(BatchError::Refused { code: 406, .. })));
+        assert_ok!(&outcomes[2]);
+
+        let request = &mock_server.received_requests().await.unwrap()[0];
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body.len(), 3);
+        assert_eq!(body[1]["to"], recipients[1].as_ref());
+    }
+
+    #[tokio::test]
+    async fn send_batch_splits_large_batches() {
+        let mock_server  = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipient = email();
+
+        Mock::given(path("/email/batch"))
+            .respond_with(|request: &Request| {
+                let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+                ResponseTemplate::new(200).set_body_json(batch_results(&vec![0; messages.len()]))
+            })
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let emails: Vec<Email> = (0..MAX_BATCH_SIZE + 1)
+            .map(|_| Email { recipient: &recipient, subject: "subject", html_body: "html", text_body: "text", headers: &[] })
+            .collect();
+        let outcomes = email_client.send_batch(&emails).await;
+
+        assert_eq!(outcomes.len(), MAX_BATCH_SIZE + 1);
+        assert!(outcomes.iter().all(Result::is_ok));
+    }
+
+    #[tokio::test]
+    async fn send_batch_fails_every_message_if_the_server_returns_500() {
+        let mock_server  = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipients: Vec<SubscriberEmail> = (0..2).map(|_| email()).collect();
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let emails: Vec<Email> = recipients
+            .iter()
+            .map(|recipient| Email { recipient, subject: "subject", html_body: "html", text_body: "text", headers: &[] })
+            .collect();
+        let outcomes = email_client.send_batch(&emails).await;
+
+        assert_eq!(outcomes.len(), 2);
+        assert!(outcomes.iter().all(|outcome| outcome.as_ref().unwrap_err().is_transient()));
+    }
+
+    #[tokio::test]
+    async fn send_batch_failures_the_provider_may_have_taken_are_not_transient() {
+        let mock_server  = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipient = email();
+        let emails = [Email { recipient: &recipient, subject: "subject", html_body: "html", text_body: "text", headers: &[] }];
+
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_delay(std::time::Duration::from_secs(180)))
+            .up_to_n_times(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&mock_server)
+            .await;
+
+        for _ in 0..2 {
+            let outcome = email_client.send_batch(&emails).await.pop().unwrap();
+            assert!(matches!(outcome, Err(BatchError::Request { transient: false, .. })));
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+
+/// How many of the latest deliveries the score looks at.
+const SCORED_DELIVERIES: i64 = 10;
+
+#[derive(Serialize)]
+pub struct Engagement {
+    pub subscriber_id: Uuid,
+    /// 0 to 100, `None` until the subscriber has received an issue.
+    pub score: Option<u8>,
+    /// The deliveries the score is computed from.
+    pub deliveries: i64,
+    pub opened: i64,
+    pub clicked: i64,
+    /// Issues sent since the subscriber last opened or clicked anything.
+    pub issues_since_engagement: i64,
+    pub last_engaged_at: Option<DateTime<Utc>>,
+}
+
+/// Every delivery is worth a point when it was opened or clicked, and a
+/// second one when it was clicked: a click says more than an open, which
+/// image blocking and mail privacy proxies make unreliable anyway.
+pub fn score(deliveries: i64, engaged: i64, clicked: i64) -> Option<u8> {
+    if deliveries == 0 {
+        return None;
+    }
+
+    Some(((engaged + clicked) * 100 / (2 * deliveries)) as u8)
+}
+
+/// Records that the subscriber showed signs of life. It ends a pending
+/// re-engagement and brings inactive subscribers back.
+#[tracing::instrument(name = "mark a subscriber as engaged", skip(executor))]
+pub async fn mark_engaged<'e>(executor: impl PgExecutor<'e>, subscriber_id: Uuid, at: DateTime<Utc>) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+            r#"
+                UPDATE subscriptions
+                SET last_engaged_at = $2,
+                    reengagement_sent_at = NULL,
+                    status = CASE WHEN status = 'inactive' THEN 'confirmed' ELSE status END
+                WHERE id = $1
+            "#,
+            subscriber_id, at
+        )
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "compute the engagement of a subscriber", skip(pool))]
+pub async fn fetch_engagement(pool: &PgPool, subscriber_id: Uuid) -> Result<Option<Engagement>, sqlx::Error> {
+    let subscriber = sqlx::query!(
+            r#"
+                SELECT
+                    s.last_engaged_at,
+                    (
+                        SELECT count(*) FROM issue_deliveries d
+                        WHERE d.subscriber_id = s.id AND d.sent_at > COALESCE(s.last_engaged_at, '-infinity')
+                    ) AS "issues_since_engagement!"
+                FROM subscriptions s
+                WHERE s.id = $1
+            "#,
+            subscriber_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+    let subscriber = match subscriber {
+        Some(subscriber) => subscriber,
+        None => return Ok(None),
+    };
+
+    let recent = sqlx::query!(
+            r#"
+                WITH recent AS (
+                    SELECT
+                        d.first_opened_at IS NOT NULL AS opened,
+                        EXISTS (SELECT 1 FROM link_clicks c WHERE c.delivery_id = d.id) AS clicked
+                    FROM issue_deliveries d
+                    WHERE d.subscriber_id = $1 AND d.sent_at IS NOT NULL
+                    ORDER BY d.sent_at DESC
+                    LIMIT $2
+                )
+                SELECT
+                    count(*) AS "deliveries!",
+                    count(*) FILTER (WHERE opened) AS "opened!",
+                    count(*) FILTER (WHERE clicked) AS "clicked!",
+                    count(*) FILTER (WHERE opened OR clicked) AS "engaged!"
+                FROM recent
+            "#,
+            subscriber_id, SCORED_DELIVERIES
+        )
+        .fetch_one(pool)
+        .await?;
+
+    Ok(Some(Engagement {
+        subscriber_id,
+        score: score(recent.deliveries, recent.engaged, recent.clicked),
+        deliveries: recent.deliveries,
+        opened: recent.opened,
+        clicked: recent.clicked,
+        issues_since_engagement: subscriber.issues_since_engagement,
+        last_engaged_at: subscriber.last_engaged_at,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_none;
+    use crate::engagement::score;
+
+    #[test]
+    fn there_is_no_score_without_deliveries() {
+        assert_none!(score(0, 0, 0));
+    }
+
+    #[test]
+    fn clicks_weigh_more_than_opens() {
+        assert_eq!(score(4, 2, 0), Some(25));
+        assert_eq!(score(4, 2, 2), Some(50));
+        assert_eq!(score(4, 4, 4), Some(100));
+    }
+}
@@ -0,0 +1,92 @@
+use actix_web::web::Bytes;
+use chrono::{DateTime, Utc};
+use futures::{Stream, TryStreamExt};
+use sqlx::PgPool;
+
+const CSV_HEADERS: [&str; 4] = ["email", "name", "status", "subscribed_at"];
+
+#[derive(serde::Serialize)]
+pub struct ExportedSubscriber {
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct ExportFilter {
+    /// Only export subscribers with this status, e.g. `confirmed`.
+    pub status: Option<String>,
+}
+
+#[tracing::instrument(name = "fetch subscribers for export", skip(pool))]
+pub async fn fetch_subscribers(pool: &PgPool, filter: &ExportFilter) -> Result<Vec<ExportedSubscriber>, sqlx::Error> {
+    sqlx::query_as!(
+            ExportedSubscriber,
+            r#"
+                SELECT email, name, status, subscribed_at FROM subscriptions
+                WHERE $1::TEXT IS NULL OR status = $1
+                ORDER BY subscribed_at
+            "#,
+            filter.status
+        )
+        .fetch_all(pool)
+        .await
+}
+
+/// Streams the export as csv chunks, one row at a time, so that large lists
+/// are never held in memory.
+pub fn stream_csv(pool: PgPool, filter: ExportFilter) -> impl Stream<Item = Result<Bytes, anyhow::Error>> {
+    async_stream::try_stream! {
+        yield csv_chunk(|writer| writer.write_record(CSV_HEADERS))?;
+
+        let mut rows = sqlx::query_as!(
+                ExportedSubscriber,
+                r#"
+                    SELECT email, name, status, subscribed_at FROM subscriptions
+                    WHERE $1::TEXT IS NULL OR status = $1
+                    ORDER BY subscribed_at
+                "#,
+                filter.status
+            )
+            .fetch(&pool);
+
+        while let Some(subscriber) = rows.try_next().await? {
+            yield csv_chunk(|writer| writer.serialize(&subscriber))?;
+        }
+    }
+}
+
+pub fn write_csv<W: std::io::Write>(writer: W, subscribers: &[ExportedSubscriber]) -> Result<(), csv::Error> {
+    // headers are written by hand so an empty export is still a valid csv file
+    let mut writer = csv_writer(writer);
+
+    writer.write_record(CSV_HEADERS)?;
+
+    for subscriber in subscribers {
+        writer.serialize(subscriber)?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+fn csv_writer<W: std::io::Write>(writer: W) -> csv::Writer<W> {
+    csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(writer)
+}
+
+fn csv_chunk<F>(write: F) -> Result<Bytes, anyhow::Error>
+    where
+    F: FnOnce(&mut csv::Writer<Vec<u8>>) -> Result<(), csv::Error>
+{
+    let mut writer = csv_writer(Vec::new());
+
+    write(&mut writer)?;
+
+    let chunk = writer.into_inner().map_err(|e| anyhow::anyhow!(e.to_string()))?;
+
+    Ok(Bytes::from(chunk))
+}
@@ -0,0 +1,370 @@
+//! RSS-to-email: every new entry of a watched RSS or Atom feed becomes an
+//! issue, written from the `feed_watcher` templates. Entries are remembered
+//! by their guid, so each one is turned into an issue once. The first time a
+//! feed is read its entries are only remembered, so that subscribers are not
+//! sent the whole back catalogue.
+
+use std::time::Duration;
+use anyhow::Context;
+use chrono::Utc;
+use secrecy::Secret;
+use serde::Serialize;
+use sqlx::PgPool;
+use crate::archive::description;
+use crate::configuration::{FeedWatcherMode, FeedWatcherSettings};
+use crate::drafts::{insert_draft, DraftContent};
+use crate::email_client::EmailClient;
+use crate::merge::html_escape;
+use crate::routes::{publish_issue, BodyData};
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+const BLOCK_ELEMENTS: &[&str] = &["p", "br", "div", "li", "ul", "ol", "h1", "h2", "h3", "h4", "h5", "h6", "blockquote", "pre"];
+
+#[derive(Debug, PartialEq)]
+pub struct FeedEntry {
+    pub guid: String,
+    pub title: String,
+    /// Plain text, one line.
+    pub summary: String,
+    pub link: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct FeedReport {
+    pub new_entries: usize,
+    pub drafted: usize,
+    pub published: usize,
+    pub failed: usize,
+}
+
+/// Reads the feed and turns its new entries into drafts or issues.
+#[tracing::instrument(name = "watch the feed", skip(pool, email_client, base_url, hmac_secret, settings))]
+pub async fn watch_feed(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    settings: &FeedWatcherSettings,
+) -> Result<FeedReport, anyhow::Error> {
+    let mut report = FeedReport::default();
+
+    let source = match settings.source() {
+        Some(source) => source,
+        None => return Ok(report),
+    };
+
+    let xml = read_feed(source).await?;
+    let entries = parse_feed(&xml).with_context(|| format!("failed to parse the feed at {}", source))?;
+
+    let known = sqlx::query!(
+            r#"SELECT EXISTS (SELECT 1 FROM feed_entries WHERE feed = $1) AS "known!""#,
+            source
+        )
+        .fetch_one(pool)
+        .await
+        .context("failed to look up the feed")?
+        .known;
+
+    // feeds list the newest entry first; issues go out in publication order
+    for entry in entries.iter().rev() {
+        let seen = sqlx::query!(
+                r#"
+                    INSERT INTO feed_entries (feed, guid, seen_at) VALUES ($1, $2, $3)
+                    ON CONFLICT DO NOTHING
+                "#,
+                source, entry.guid, Utc::now()
+            )
+            .execute(pool)
+            .await
+            .context("failed to remember a feed entry")?
+            .rows_affected() == 0;
+
+        if seen || !known {
+            continue;
+        }
+
+        report.new_entries += 1;
+
+        // the entry is remembered before anything is sent, so that a failure
+        // halfway through a send never sends it twice
+        match entry_to_issue(pool, email_client, base_url, hmac_secret, settings, source, entry).await {
+            Ok(FeedWatcherMode::Draft) => report.drafted += 1,
+            Ok(FeedWatcherMode::Send) => report.published += 1,
+            Err(e) => {
+                tracing::warn!(error.cause_chain = ?e, guid = %entry.guid, "failed to turn a feed entry into an issue");
+                report.failed += 1;
+            },
+        }
+    }
+
+    Ok(report)
+}
+
+async fn entry_to_issue(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    settings: &FeedWatcherSettings,
+    source: &str,
+    entry: &FeedEntry,
+) -> Result<FeedWatcherMode, anyhow::Error> {
+    let html = render_entry(&settings.html_template, entry, html_escape);
+    let text = render_entry(&settings.text_template, entry, str::to_string);
+
+    match settings.mode {
+        FeedWatcherMode::Draft => {
+            let content = DraftContent { title: &entry.title, text: &text, html: &html, source: &entry.link };
+            let draft_id = insert_draft(pool, &content).await.context("failed to store the draft")?;
+
+            sqlx::query!(
+                    r#"UPDATE feed_entries SET draft_id = $3 WHERE feed = $1 AND guid = $2"#,
+                    source, entry.guid, draft_id
+                )
+                .execute(pool)
+                .await
+                .context("failed to record the draft of a feed entry")?;
+        },
+        FeedWatcherMode::Send => {
+            let issue = BodyData::new(entry.title.clone(), text, html);
+            let report = publish_issue(pool, email_client, base_url, hmac_secret, &issue)
+                .await
+                .context("failed to publish the issue")?;
+
+            sqlx::query!(
+                    r#"UPDATE feed_entries SET newsletter_issue_id = $3 WHERE feed = $1 AND guid = $2"#,
+                    source, entry.guid, report.issue_id
+                )
+                .execute(pool)
+                .await
+                .context("failed to record the issue of a feed entry")?;
+        },
+    }
+
+    Ok(settings.mode)
+}
+
+/// `source` is a http(s) url or the path of a local file.
+async fn read_feed(source: &str) -> Result<String, anyhow::Error> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let client = reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .build()
+            .context("failed to build the http client")?;
+
+        client.get(source)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("failed to fetch the feed at {}", source))?
+            .text()
+            .await
+            .with_context(|| format!("failed to read the feed at {}", source))
+    } else {
+        std::fs::read_to_string(source).with_context(|| format!("failed to read the feed file {}", source))
+    }
+}
+
+/// The entries of an Atom feed, or the items of an RSS one, in the order of
+/// the document. Entries without anything to identify them are skipped.
+pub fn parse_feed(xml: &str) -> Result<Vec<FeedEntry>, anyhow::Error> {
+    let document = roxmltree::Document::parse(xml)?;
+    let root = document.root_element();
+
+    let entries = if root.has_tag_name("feed") {
+        root.children()
+            .filter(|node| node.has_tag_name("entry"))
+            .filter_map(atom_entry)
+            .collect()
+    } else if root.has_tag_name("rss") || root.has_tag_name("RDF") {
+        root.descendants()
+            .filter(|node| node.has_tag_name("item"))
+            .filter_map(rss_item)
+            .collect()
+    } else {
+        anyhow::bail!("<{}> is neither an Atom nor an RSS feed", root.tag_name().name());
+    };
+
+    Ok(entries)
+}
+
+fn child_text(node: roxmltree::Node, name: &str) -> Option<String> {
+    node.children()
+        .find(|child| child.has_tag_name(name))
+        .map(|child| child.text().unwrap_or_default().trim().to_string())
+        .filter(|text| !text.is_empty())
+}
+
+fn atom_entry(entry: roxmltree::Node) -> Option<FeedEntry> {
+    let link = entry.children()
+        .filter(|child| child.has_tag_name("link"))
+        .find(|link| link.attribute("rel").is_none_or(|rel| rel == "alternate"))
+        .and_then(|link| link.attribute("href"))
+        .unwrap_or_default()
+        .to_string();
+    let summary = child_text(entry, "summary").or_else(|| child_text(entry, "content")).unwrap_or_default();
+
+    Some(FeedEntry {
+        guid: child_text(entry, "id").or_else(|| Some(link.clone()).filter(|link| !link.is_empty()))?,
+        title: child_text(entry, "title").unwrap_or_default(),
+        summary: plain_text(&summary),
+        link,
+    })
+}
+
+fn rss_item(item: roxmltree::Node) -> Option<FeedEntry> {
+    let link = child_text(item, "link").unwrap_or_default();
+    let title = child_text(item, "title").unwrap_or_default();
+
+    Some(FeedEntry {
+        guid: child_text(item, "guid")
+            .or_else(|| Some(link.clone()).filter(|link| !link.is_empty()))
+            .or_else(|| Some(title.clone()).filter(|title| !title.is_empty()))?,
+        summary: plain_text(&child_text(item, "description").unwrap_or_default()),
+        title,
+        link,
+    })
+}
+
+/// Summaries are often html; the templates get them as short plain text.
+fn plain_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(start) = rest.find('<') {
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let name: String = rest[start + 1..end]
+            .trim_start_matches('/')
+            .chars()
+            .take_while(char::is_ascii_alphanumeric)
+            .collect::<String>()
+            .to_ascii_lowercase();
+
+        text.push_str(&rest[..start]);
+        // block elements separate words, inline ones do not
+        if BLOCK_ELEMENTS.contains(&name.as_str()) {
+            text.push(' ');
+        }
+
+        rest = &rest[end + 1..];
+    }
+
+    text.push_str(rest);
+
+    description(&text)
+}
+
+/// Replaces the `{{ entry.* }}` tags of a template. Every other tag is left
+/// as it is, for the merge fields of each recipient.
+pub fn render_entry(template: &str, entry: &FeedEntry, escape: impl Fn(&str) -> String) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        let value = match rest[start + 2..end].trim() {
+            "entry.title" => Some(&entry.title),
+            "entry.summary" => Some(&entry.summary),
+            "entry.link" => Some(&entry.link),
+            _ => None,
+        };
+
+        rendered.push_str(&rest[..start]);
+        match value {
+            Some(value) => rendered.push_str(&escape(value)),
+            None => rendered.push_str(&rest[start..end + 2]),
+        }
+
+        rest = &rest[end + 2..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::feed_watcher::{parse_feed, render_entry, FeedEntry};
+    use crate::merge::html_escape;
+
+    #[test]
+    fn atom_entries_are_parsed() {
+        let xml = r#"<?xml version="1.0" encoding="utf-8"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+                <title>Engineering</title>
+                <entry>
+                    <id>tag:blog.example.com,2022:2</id>
+                    <title>Faster builds</title>
+                    <link rel="replies" href="https://blog.example.com/2/comments" />
+                    <link href="https://blog.example.com/2" />
+                    <summary type="html">&lt;p&gt;How we made   builds &lt;b&gt;faster&lt;/b&gt;.&lt;/p&gt;</summary>
+                </entry>
+                <entry>
+                    <title>No id</title>
+                    <link rel="alternate" href="https://blog.example.com/1" />
+                    <content>Hello</content>
+                </entry>
+            </feed>"#;
+
+        assert_eq!(parse_feed(xml).unwrap(), vec![
+            FeedEntry {
+                guid: "tag:blog.example.com,2022:2".into(),
+                title: "Faster builds".into(),
+                summary: "How we made builds faster.".into(),
+                link: "https://blog.example.com/2".into(),
+            },
+            FeedEntry {
+                guid: "https://blog.example.com/1".into(),
+                title: "No id".into(),
+                summary: "Hello".into(),
+                link: "https://blog.example.com/1".into(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn rss_items_are_parsed() {
+        let xml = r#"<rss version="2.0"><channel><title>Engineering</title>
+            <item><title>First</title><link>https://blog.example.com/1</link><guid>1</guid><description>&lt;p&gt;Hi&lt;/p&gt;&lt;p&gt;there&lt;/p&gt;</description></item>
+            <item><title>No guid</title><link>https://blog.example.com/2</link></item>
+            <item><description>nothing to identify it by</description></item>
+            </channel></rss>"#;
+
+        let entries = parse_feed(xml).unwrap();
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].guid, "1");
+        assert_eq!(entries[0].summary, "Hi there");
+        assert_eq!(entries[1].guid, "https://blog.example.com/2");
+    }
+
+    #[test]
+    fn other_documents_are_rejected() {
+        assert!(parse_feed("<html><body /></html>").is_err());
+        assert!(parse_feed("not xml").is_err());
+    }
+
+    #[test]
+    fn templates_get_the_entry_and_keep_merge_fields() {
+        let entry = FeedEntry {
+            guid: "1".into(),
+            title: "Tips & tricks".into(),
+            summary: "Some tips".into(),
+            link: "https://blog.example.com/1?a=1&b=2".into(),
+        };
+
+        assert_eq!(
+            render_entry(r#"<p>Hi {{ name }}, <a href="{{entry.link}}">{{ entry.title }}</a>: {{ entry.summary }}</p>"#, &entry, html_escape),
+            r#"<p>Hi {{ name }}, <a href="https://blog.example.com/1?a=1&amp;b=2">Tips &amp; tricks</a>: Some tips</p>"#
+        );
+        assert_eq!(render_entry("{{ entry.title }} {{ entry.other }}", &entry, str::to_string), "Tips & tricks {{ entry.other }}");
+    }
+}
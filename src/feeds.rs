@@ -0,0 +1,158 @@
+//! RSS and Atom feeds of the public archive, with the full content of every
+//! issue. Entries are identified by the issue id, so that renaming an issue
+//! does not make feed readers show it twice.
+
+use chrono::{DateTime, TimeZone, Utc};
+use sha2::{Digest, Sha256};
+use crate::archive::{description, public_html, PublicIssue};
+
+/// How many of the latest issues the feeds carry.
+pub const FEED_LENGTH: i64 = 20;
+
+pub struct Feed<'a> {
+    pub title: &'a str,
+    pub base_url: &'a str,
+    /// Newest first.
+    pub issues: &'a [PublicIssue],
+}
+
+impl Feed<'_> {
+    /// When the newest issue was sent.
+    pub fn last_modified(&self) -> Option<DateTime<Utc>> {
+        self.issues.iter().map(|issue| issue.published_at).max()
+    }
+
+    fn link(&self, issue: &PublicIssue) -> String {
+        format!("{}/archive/{}", self.base_url, issue.slug)
+    }
+}
+
+fn guid(issue: &PublicIssue) -> String {
+    format!("urn:uuid:{}", issue.id)
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+pub fn rss(feed: &Feed) -> String {
+    let items: String = feed.issues
+        .iter()
+        .map(|issue| format!(
+            "<item><title>{}</title><link>{}</link><guid isPermaLink=\"false\">{}</guid>\
+            <pubDate>{}</pubDate><description>{}</description></item>",
+            xml_escape(&issue.title),
+            xml_escape(&feed.link(issue)),
+            guid(issue),
+            issue.published_at.to_rfc2822(),
+            xml_escape(&public_html(&issue.html, feed.base_url)),
+        ))
+        .collect();
+
+    let last_build_date = feed.last_modified()
+        .map(|date| format!("<lastBuildDate>{}</lastBuildDate>", date.to_rfc2822()))
+        .unwrap_or_default();
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\"><channel>\
+        <title>{title}</title><link>{base_url}/archive</link><description>{title}</description>\
+        <atom:link href=\"{base_url}/feed.rss\" rel=\"self\" type=\"application/rss+xml\" />\
+        {last_build_date}{items}</channel></rss>",
+        title = xml_escape(feed.title),
+        base_url = xml_escape(feed.base_url),
+        last_build_date = last_build_date,
+        items = items,
+    )
+}
+
+pub fn atom(feed: &Feed) -> String {
+    let entries: String = feed.issues
+        .iter()
+        .map(|issue| format!(
+            "<entry><title>{}</title><link href=\"{}\" /><id>{}</id>\
+            <published>{published}</published><updated>{published}</updated>\
+            <summary>{}</summary><content type=\"html\">{}</content></entry>",
+            xml_escape(&issue.title),
+            xml_escape(&feed.link(issue)),
+            guid(issue),
+            xml_escape(&description(&issue.text)),
+            xml_escape(&public_html(&issue.html, feed.base_url)),
+            published = issue.published_at.to_rfc3339(),
+        ))
+        .collect();
+
+    // a feed without entries still needs an update time; the epoch is as
+    // stable as it gets
+    let updated = feed.last_modified().unwrap_or_else(|| Utc.timestamp(0, 0));
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <feed xmlns=\"http://www.w3.org/2005/Atom\">\
+        <title>{title}</title><id>{base_url}/feed.atom</id>\
+        <link href=\"{base_url}/archive\" /><link href=\"{base_url}/feed.atom\" rel=\"self\" />\
+        <author><name>{title}</name></author><updated>{updated}</updated>{entries}</feed>",
+        title = xml_escape(feed.title),
+        base_url = xml_escape(feed.base_url),
+        updated = updated.to_rfc3339(),
+        entries = entries,
+    )
+}
+
+/// A strong validator of a feed body: it changes whenever the body does,
+/// including when an issue is made private.
+pub fn etag(body: &str) -> String {
+    format!("\"{}\"", &hex::encode(Sha256::digest(body.as_bytes()))[..32])
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use uuid::Uuid;
+    use crate::archive::PublicIssue;
+    use crate::feeds::{atom, etag, rss, Feed};
+
+    fn issue() -> PublicIssue {
+        PublicIssue {
+            id: Uuid::parse_str("6f1e2ee4-3b59-4bd6-a4d7-4a3c1e3f4c10").unwrap(),
+            slug: "tips-tricks".into(),
+            title: "Tips & tricks".into(),
+            text: "Some tips.".into(),
+            html: "<p>Some <b>tips</b>.</p>".into(),
+            published_at: Utc.ymd(2022, 6, 1).and_hms(9, 30, 0),
+        }
+    }
+
+    #[test]
+    fn rss_items_have_a_stable_guid_and_the_send_date() {
+        let issues = [issue()];
+        let feed = rss(&Feed { title: "News", base_url: "https://news.example.com", issues: &issues });
+
+        assert!(feed.contains("<title>Tips &amp; tricks</title>"));
+        assert!(feed.contains(r#"<guid isPermaLink="false">urn:uuid:6f1e2ee4-3b59-4bd6-a4d7-4a3c1e3f4c10</guid>"#));
+        assert!(feed.contains("<pubDate>Wed, 01 Jun 2022 09:30:00 +0000</pubDate>"));
+        assert!(feed.contains("<link>https://news.example.com/archive/tips-tricks</link>"));
+        assert!(feed.contains("&lt;p&gt;Some &lt;b&gt;tips&lt;/b&gt;.&lt;/p&gt;"));
+    }
+
+    #[test]
+    fn atom_entries_carry_the_full_content() {
+        let issues = [issue()];
+        let feed = atom(&Feed { title: "News", base_url: "https://news.example.com", issues: &issues });
+
+        assert!(feed.contains("<id>urn:uuid:6f1e2ee4-3b59-4bd6-a4d7-4a3c1e3f4c10</id>"));
+        assert!(feed.contains("<updated>2022-06-01T09:30:00+00:00</updated>"));
+        assert!(feed.contains(r#"<content type="html">&lt;p&gt;Some &lt;b&gt;tips&lt;/b&gt;.&lt;/p&gt;</content>"#));
+    }
+
+    #[test]
+    fn etags_follow_the_body() {
+        assert_eq!(etag("a"), etag("a"));
+        assert_ne!(etag("a"), etag("b"));
+        assert!(etag("a").starts_with('"') && etag("a").ends_with('"'));
+    }
+}
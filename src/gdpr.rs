@@ -0,0 +1,255 @@
+//! Data subject requests: access (everything we hold about an address) and
+//! erasure. Both are written to the audit log.
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::audit;
+use crate::consent::ConsentRecord;
+use crate::suppression::{hash_email, suppress};
+use crate::tags::fetch_tags;
+
+#[derive(Serialize)]
+pub struct SubjectData {
+    pub email: String,
+    pub subscriptions: Vec<SubscriptionRecord>,
+    pub suppression: Option<SuppressionRecord>,
+}
+
+#[derive(Serialize)]
+pub struct SubscriptionRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTime<Utc>,
+    pub attributes: serde_json::Value,
+    pub tags: Vec<String>,
+    pub frequency: String,
+    pub locale: String,
+    pub paused_until: Option<DateTime<Utc>>,
+    /// An address change that has been requested but not confirmed yet.
+    pub pending_email: Option<String>,
+    pub consent: ConsentRecord,
+    pub tokens: Vec<String>,
+    pub deliveries: Vec<DeliveryRecord>,
+}
+
+#[derive(Serialize)]
+pub struct DeliveryRecord {
+    pub issue_title: String,
+    pub sent_at: Option<DateTime<Utc>>,
+    pub first_opened_at: Option<DateTime<Utc>>,
+    pub open_count: i32,
+    pub click_count: i64,
+}
+
+#[derive(Serialize)]
+pub struct SuppressionRecord {
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ErasureReport {
+    pub subscriptions: u64,
+    pub tokens: u64,
+    pub deliveries: u64,
+}
+
+#[tracing::instrument(name = "export the data held about a subject", skip(pool, email))]
+pub async fn access(pool: &PgPool, email: &str, actor: &str) -> Result<SubjectData, anyhow::Error> {
+    let rows = sqlx::query!(
+            r#"
+                SELECT id, email, name, status, subscribed_at, source, consent_attestation, consent_at,
+                    consent_ip, consent_user_agent, signup_form, signup_form_version, confirmed_at, confirmed_ip,
+                    attributes, frequency, locale, paused_until
+                FROM subscriptions
+                WHERE lower(email) = lower($1)
+            "#,
+            email
+        )
+        .fetch_all(pool)
+        .await
+        .context("failed to fetch subscriptions")?;
+
+    let mut subscriptions = Vec::with_capacity(rows.len());
+
+    for row in rows {
+        let tokens = sqlx::query!(
+                r#"SELECT subscription_token FROM subscription_tokens WHERE subscriber_id = $1"#,
+                row.id
+            )
+            .fetch_all(pool)
+            .await
+            .context("failed to fetch subscription tokens")?
+            .into_iter()
+            .map(|t| t.subscription_token)
+            .collect();
+
+        let deliveries = sqlx::query_as!(
+                DeliveryRecord,
+                r#"
+                    SELECT i.title AS issue_title, d.sent_at, d.first_opened_at, d.open_count,
+                        (SELECT count(*) FROM link_clicks c WHERE c.delivery_id = d.id) AS "click_count!"
+                    FROM issue_deliveries d
+                    JOIN newsletter_issues i ON i.id = d.newsletter_issue_id
+                    WHERE d.subscriber_id = $1
+                    ORDER BY i.published_at
+                "#,
+                row.id
+            )
+            .fetch_all(pool)
+            .await
+            .context("failed to fetch deliveries")?;
+
+        let pending_email = sqlx::query!(
+                r#"SELECT new_email FROM email_changes WHERE subscriber_id = $1"#,
+                row.id
+            )
+            .fetch_optional(pool)
+            .await
+            .context("failed to fetch pending email changes")?
+            .map(|c| c.new_email);
+
+        subscriptions.push(SubscriptionRecord {
+            id: row.id,
+            email: row.email,
+            name: row.name,
+            status: row.status,
+            subscribed_at: row.subscribed_at,
+            attributes: row.attributes,
+            tags: fetch_tags(pool, row.id).await.context("failed to fetch tags")?,
+            frequency: row.frequency,
+            locale: row.locale,
+            paused_until: row.paused_until,
+            pending_email,
+            consent: ConsentRecord {
+                source: row.source,
+                attestation: row.consent_attestation,
+                consent_at: row.consent_at,
+                consent_ip: row.consent_ip,
+                consent_user_agent: row.consent_user_agent,
+                signup_form: row.signup_form,
+                signup_form_version: row.signup_form_version,
+                confirmed_at: row.confirmed_at,
+                confirmed_ip: row.confirmed_ip,
+            },
+            tokens,
+            deliveries,
+        });
+    }
+
+    let suppression = sqlx::query_as!(
+            SuppressionRecord,
+            r#"SELECT reason, created_at FROM suppressions WHERE email_hash = $1"#,
+            hash_email(email)
+        )
+        .fetch_optional(pool)
+        .await
+        .context("failed to fetch suppression")?;
+
+    audit::record(pool, "access", email, actor)
+        .await
+        .context("failed to write the audit log")?;
+
+    Ok(SubjectData { email: email.to_string(), subscriptions, suppression })
+}
+
+/// Deletes the subscription, its tokens and its delivery history. The hashed address stays on the
+/// suppression list so that it is not imported again.
+#[tracing::instrument(name = "erase the data held about a subject", skip(pool, email))]
+pub async fn erase(pool: &PgPool, email: &str, actor: &str) -> Result<ErasureReport, anyhow::Error> {
+    let mut transaction = pool.begin().await.context("failed to start a transaction")?;
+
+    sqlx::query!(
+            r#"
+                DELETE FROM delivery_opens
+                WHERE delivery_id IN (
+                    SELECT d.id FROM issue_deliveries d
+                    JOIN subscriptions s ON s.id = d.subscriber_id
+                    WHERE lower(s.email) = lower($1)
+                )
+            "#,
+            email
+        )
+        .execute(&mut transaction)
+        .await
+        .context("failed to delete opens")?;
+
+    sqlx::query!(
+            r#"
+                DELETE FROM link_clicks
+                WHERE delivery_id IN (
+                    SELECT d.id FROM issue_deliveries d
+                    JOIN subscriptions s ON s.id = d.subscriber_id
+                    WHERE lower(s.email) = lower($1)
+                )
+            "#,
+            email
+        )
+        .execute(&mut transaction)
+        .await
+        .context("failed to delete clicks")?;
+
+    let deliveries = sqlx::query!(
+            r#"
+                DELETE FROM issue_deliveries
+                WHERE subscriber_id IN (SELECT id FROM subscriptions WHERE lower(email) = lower($1))
+            "#,
+            email
+        )
+        .execute(&mut transaction)
+        .await
+        .context("failed to delete deliveries")?
+        .rows_affected();
+
+    let tokens = sqlx::query!(
+            r#"
+                DELETE FROM subscription_tokens
+                WHERE subscriber_id IN (SELECT id FROM subscriptions WHERE lower(email) = lower($1))
+            "#,
+            email
+        )
+        .execute(&mut transaction)
+        .await
+        .context("failed to delete subscription tokens")?
+        .rows_affected();
+
+    // changes of other subscriptions to this address; the subscription's own go with it
+    sqlx::query!(
+            r#"DELETE FROM email_changes WHERE lower(new_email) = lower($1)"#,
+            email
+        )
+        .execute(&mut transaction)
+        .await
+        .context("failed to delete email changes")?;
+
+    sqlx::query!(r#"DELETE FROM email_outbox WHERE lower(recipient) = lower($1)"#, email)
+        .execute(&mut transaction)
+        .await
+        .context("failed to delete queued emails")?;
+
+    let subscriptions = sqlx::query!(
+            r#"DELETE FROM subscriptions WHERE lower(email) = lower($1)"#,
+            email
+        )
+        .execute(&mut transaction)
+        .await
+        .context("failed to delete subscriptions")?
+        .rows_affected();
+
+    suppress(&mut transaction, email, "erased")
+        .await
+        .context("failed to suppress the address")?;
+
+    audit::record(&mut transaction, "erasure", email, actor)
+        .await
+        .context("failed to write the audit log")?;
+
+    transaction.commit().await.context("failed to commit the erasure")?;
+
+    Ok(ErasureReport { subscriptions, tokens, deliveries })
+}
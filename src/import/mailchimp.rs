@@ -0,0 +1,179 @@
+//! Mailchimp audience exports come as one csv file per member status
+//! (`subscribed_members_export_*.csv`, `unsubscribed_…`, `cleaned_…`), all
+//! sharing the same columns.
+
+use std::convert::TryFrom;
+use crate::import::{column, csv_reader, headers, name_or_local_part, row_error, row_number, ImportRecord, RecordAction, RowError};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MailchimpStatus {
+    Subscribed,
+    Unsubscribed,
+    /// Addresses Mailchimp removed after hard bounces.
+    Cleaned,
+}
+
+impl MailchimpStatus {
+    /// Guesses the status from the name Mailchimp gives each export file.
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        let file_name = file_name.to_lowercase();
+
+        // "unsubscribed" contains "subscribed", so it has to be checked first
+        if file_name.contains("unsubscribed") {
+            Some(Self::Unsubscribed)
+        } else if file_name.contains("cleaned") {
+            Some(Self::Cleaned)
+        } else if file_name.contains("subscribed") {
+            Some(Self::Subscribed)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MailchimpStatus::Subscribed => "subscribed",
+            MailchimpStatus::Unsubscribed => "unsubscribed",
+            MailchimpStatus::Cleaned => "cleaned",
+        }
+    }
+
+    fn action(&self) -> RecordAction {
+        match self {
+            MailchimpStatus::Subscribed => RecordAction::Subscribe,
+            MailchimpStatus::Unsubscribed => RecordAction::Suppress { reason: "unsubscribed" },
+            MailchimpStatus::Cleaned => RecordAction::Suppress { reason: "cleaned" },
+        }
+    }
+}
+
+impl TryFrom<&str> for MailchimpStatus {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "subscribed" => Ok(Self::Subscribed),
+            "unsubscribed" => Ok(Self::Unsubscribed),
+            "cleaned" => Ok(Self::Cleaned),
+            other => Err(format!(
+                "{} is not a mailchimp status. use 'subscribed', 'unsubscribed' or 'cleaned'", other
+            )),
+        }
+    }
+}
+
+pub fn read_csv<R: std::io::Read>(reader: R, status: MailchimpStatus) -> Result<(Vec<ImportRecord>, Vec<RowError>), String> {
+    let mut reader = csv_reader(reader);
+    let headers = headers(&mut reader)?;
+
+    let email_column = column(&headers, &["Email Address"])
+        .ok_or("the file has no 'Email Address' column. is it a mailchimp audience export?")?;
+    let first_name_column = column(&headers, &["First Name", "FNAME"]);
+    let last_name_column = column(&headers, &["Last Name", "LNAME"]);
+    let optin_time_column = column(&headers, &["OPTIN_TIME"]);
+    let optin_ip_column = column(&headers, &["OPTIN_IP"]);
+    let confirm_time_column = column(&headers, &["CONFIRM_TIME"]);
+
+    let mut records = vec![];
+    let mut errors = vec![];
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                errors.push(row_error(err));
+                continue;
+            },
+        };
+
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|c| record.get(c))
+                .filter(|value| !value.is_empty())
+        };
+
+        let email = field(Some(email_column)).unwrap_or_default().to_string();
+
+        let name = [field(first_name_column), field(last_name_column)]
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mut consent_attestation = "imported from mailchimp".to_string();
+
+        if let Some(optin_time) = field(optin_time_column) {
+            consent_attestation.push_str(&format!(", opted in at {}", optin_time));
+        }
+        if let Some(optin_ip) = field(optin_ip_column) {
+            consent_attestation.push_str(&format!(" from {}", optin_ip));
+        }
+        if let Some(confirm_time) = field(confirm_time_column) {
+            consent_attestation.push_str(&format!(", confirmed at {}", confirm_time));
+        }
+
+        records.push(ImportRecord {
+            row: row_number(&record),
+            name: name_or_local_part(&name, &email),
+            email,
+            action: status.action(),
+            consent_attestation: Some(consent_attestation),
+        });
+    }
+
+    Ok((records, errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_err;
+    use crate::import::mailchimp::{read_csv, MailchimpStatus};
+    use crate::import::RecordAction;
+
+    const EXPORT: &str = "\
+Email Address,First Name,Last Name,MEMBER_RATING,OPTIN_TIME,OPTIN_IP,CONFIRM_TIME,CONFIRM_IP
+ursula@example.com,Ursula,Le Guin,2,2021-03-01 10:00:00,203.0.113.7,2021-03-01 10:05:00,203.0.113.7
+ged@example.com,,,1,,,,
+";
+
+    #[test]
+    fn the_status_is_guessed_from_the_export_file_name() {
+        assert_eq!(MailchimpStatus::from_file_name("subscribed_members_export_3f1c.csv"), Some(MailchimpStatus::Subscribed));
+        assert_eq!(MailchimpStatus::from_file_name("unsubscribed_members_export_3f1c.csv"), Some(MailchimpStatus::Unsubscribed));
+        assert_eq!(MailchimpStatus::from_file_name("cleaned_members_export_3f1c.csv"), Some(MailchimpStatus::Cleaned));
+        assert_eq!(MailchimpStatus::from_file_name("audience.csv"), None);
+    }
+
+    #[test]
+    fn subscribed_members_keep_their_opt_in_details() {
+        let (records, errors) = read_csv(EXPORT.as_bytes(), MailchimpStatus::Subscribed).unwrap();
+
+        assert!(errors.is_empty());
+        assert_eq!(records[0].name, "Ursula Le Guin");
+        assert_eq!(records[0].action, RecordAction::Subscribe);
+        assert_eq!(
+            records[0].consent_attestation.as_deref(),
+            Some("imported from mailchimp, opted in at 2021-03-01 10:00:00 from 203.0.113.7, confirmed at 2021-03-01 10:05:00")
+        );
+    }
+
+    #[test]
+    fn members_without_a_name_fall_back_to_the_local_part() {
+        let (records, _) = read_csv(EXPORT.as_bytes(), MailchimpStatus::Subscribed).unwrap();
+
+        assert_eq!(records[1].name, "ged");
+    }
+
+    #[test]
+    fn cleaned_members_are_suppressed() {
+        let (records, _) = read_csv(EXPORT.as_bytes(), MailchimpStatus::Cleaned).unwrap();
+
+        assert!(records.iter().all(|r| r.action == RecordAction::Suppress { reason: "cleaned" }));
+    }
+
+    #[test]
+    fn files_that_are_not_mailchimp_exports_are_rejected() {
+        assert_err!(read_csv("email,name\n".as_bytes(), MailchimpStatus::Subscribed));
+    }
+}
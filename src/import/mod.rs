@@ -0,0 +1,525 @@
+mod mailchimp;
+mod substack;
+
+use std::collections::HashSet;
+use std::convert::{TryFrom, TryInto};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::email_client::EmailClient;
+use crate::locales::Messages;
+use crate::routes::{generate_subscription_token, send_confirmation_email, store_token, ConfirmationEmail};
+use crate::sequences::stop_sequences;
+use crate::suppression::{hash_email, suppress, suppressed};
+
+pub use mailchimp::MailchimpStatus;
+
+/// The layout of the imported file.
+#[derive(Debug)]
+pub enum ImportFormat {
+    /// Our own format: a csv file with (at least) an `email` and a `name` column.
+    Csv,
+    /// One of the per-status files of a Mailchimp audience export.
+    Mailchimp(MailchimpStatus),
+    /// The subscriber csv of a Substack export.
+    Substack,
+}
+
+impl ImportFormat {
+    /// Stored in `subscriptions.source`.
+    pub fn source(&self) -> &'static str {
+        match self {
+            ImportFormat::Csv => "csv_import",
+            ImportFormat::Mailchimp(_) => "mailchimp",
+            ImportFormat::Substack => "substack",
+        }
+    }
+}
+
+/// What happens to the subscribed rows.
+#[derive(Debug)]
+pub enum ImportMode {
+    /// Rows are stored as `pending_confirmation` and receive a confirmation email.
+    Pending,
+    /// Rows are stored as `confirmed` straight away. The attestation records how
+    /// consent was collected, e.g. "opted in at the 2022 conference booth".
+    Confirmed { consent_attestation: String },
+}
+
+impl TryFrom<(&str, Option<String>)> for ImportMode {
+    type Error = String;
+
+    fn try_from((mode, consent_attestation): (&str, Option<String>)) -> Result<Self, Self::Error> {
+        match mode {
+            "pending" => Ok(Self::Pending),
+            "confirmed" => match consent_attestation {
+                Some(consent_attestation) if !consent_attestation.trim().is_empty() => {
+                    Ok(Self::Confirmed { consent_attestation })
+                },
+                _ => Err("importing confirmed subscribers requires a consent attestation".into()),
+            },
+            other => Err(format!("{} is not a supported import mode. use either 'pending' or 'confirmed'", other)),
+        }
+    }
+}
+
+/// Import settings as they come in from the admin api or the cli.
+#[derive(Debug, Default, serde::Deserialize)]
+pub struct ImportParameters {
+    /// `csv` (default), `mailchimp` or `substack`.
+    pub format: Option<String>,
+    /// The status of a Mailchimp export file: `subscribed`, `unsubscribed` or `cleaned`.
+    pub status: Option<String>,
+    /// `pending` or `confirmed`. Defaults to `pending` for our own csv format and to
+    /// `confirmed` for Mailchimp and Substack exports, whose subscribers already opted in.
+    pub mode: Option<String>,
+    pub consent: Option<String>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug)]
+pub struct ImportOptions {
+    pub format: ImportFormat,
+    pub mode: ImportMode,
+    pub dry_run: bool,
+}
+
+impl TryFrom<ImportParameters> for ImportOptions {
+    type Error = String;
+
+    fn try_from(value: ImportParameters) -> Result<Self, Self::Error> {
+        let format = match value.format.as_deref().unwrap_or("csv") {
+            "csv" => ImportFormat::Csv,
+            "mailchimp" => {
+                let status = value.status
+                    .as_deref()
+                    .ok_or("mailchimp imports require the status of the exported file")?;
+
+                ImportFormat::Mailchimp(status.try_into()?)
+            },
+            "substack" => ImportFormat::Substack,
+            other => return Err(format!("{} is not a supported import format. use 'csv', 'mailchimp' or 'substack'", other)),
+        };
+
+        let mode = match (&format, value.mode.as_deref()) {
+            (ImportFormat::Csv, mode) => (mode.unwrap_or("pending"), value.consent).try_into()?,
+            (_, mode) => {
+                let consent = value.consent.or_else(|| Some(format!("imported from {}", format.source())));
+
+                (mode.unwrap_or("confirmed"), consent).try_into()?
+            },
+        };
+
+        Ok(Self { format, mode, dry_run: value.dry_run })
+    }
+}
+
+/// What to do with a row.
+#[derive(Debug, PartialEq)]
+pub enum RecordAction {
+    Subscribe,
+    /// Do not create a subscription, and keep the address from being imported
+    /// again. A subscription the address already has is unsubscribed, or
+    /// marked as bounced if the address was cleaned.
+    Suppress { reason: &'static str },
+}
+
+/// A raw row, before validation.
+#[derive(Debug)]
+pub struct ImportRecord {
+    /// Line number in the source file, header included.
+    pub row: usize,
+    pub email: String,
+    pub name: String,
+    pub action: RecordAction,
+    /// Overrides the attestation of [`ImportMode::Confirmed`] for this row.
+    pub consent_attestation: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct RowError {
+    pub row: usize,
+    pub email: String,
+    pub error: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub total_rows: usize,
+    pub imported: usize,
+    pub suppressed: usize,
+    /// Existing subscriptions that suppressed rows unsubscribed or marked as bounced.
+    pub unsubscribed: usize,
+    /// Every row that was skipped, and why.
+    pub errors: Vec<RowError>,
+}
+
+pub fn read_records<R: std::io::Read>(format: &ImportFormat, reader: R) -> Result<(Vec<ImportRecord>, Vec<RowError>), String> {
+    match format {
+        ImportFormat::Csv => read_csv(reader),
+        ImportFormat::Mailchimp(status) => mailchimp::read_csv(reader, *status),
+        ImportFormat::Substack => substack::read_csv(reader),
+    }
+}
+
+/// Reads a csv file with (at least) an `email` and a `name` column. Header
+/// names are matched case-insensitively and other columns are ignored.
+pub fn read_csv<R: std::io::Read>(reader: R) -> Result<(Vec<ImportRecord>, Vec<RowError>), String> {
+    let mut reader = csv_reader(reader);
+    let headers = headers(&mut reader)?;
+
+    let email_column = column(&headers, &["email"]).ok_or("the csv file has no 'email' column")?;
+    let name_column = column(&headers, &["name"]).ok_or("the csv file has no 'name' column")?;
+
+    let mut records = vec![];
+    let mut errors = vec![];
+
+    for record in reader.records() {
+        match record {
+            Ok(record) => records.push(ImportRecord {
+                row: row_number(&record),
+                email: record.get(email_column).unwrap_or_default().to_string(),
+                name: record.get(name_column).unwrap_or_default().to_string(),
+                action: RecordAction::Subscribe,
+                consent_attestation: None,
+            }),
+            Err(err) => errors.push(row_error(err)),
+        }
+    }
+
+    Ok((records, errors))
+}
+
+fn csv_reader<R: std::io::Read>(reader: R) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+}
+
+fn headers<R: std::io::Read>(reader: &mut csv::Reader<R>) -> Result<csv::StringRecord, String> {
+    reader
+        .headers()
+        .cloned()
+        .map_err(|e| format!("failed to read csv headers: {}", e))
+}
+
+/// Position of the first header matching one of `names`, case-insensitively.
+fn column(headers: &csv::StringRecord, names: &[&str]) -> Option<usize> {
+    headers.iter().position(|h| names.iter().any(|name| h.eq_ignore_ascii_case(name)))
+}
+
+fn row_number(record: &csv::StringRecord) -> usize {
+    record.position().map(|p| p.line() as usize).unwrap_or_default()
+}
+
+fn row_error(err: csv::Error) -> RowError {
+    RowError {
+        row: err.position().map(|p| p.line() as usize).unwrap_or_default(),
+        email: String::new(),
+        error: err.to_string(),
+    }
+}
+
+/// Exports from other platforms do not always carry a name, which we require.
+/// Fall back to the local part of the address.
+fn name_or_local_part(name: &str, email: &str) -> String {
+    if name.trim().is_empty() {
+        email.split('@').next().unwrap_or_default().to_string()
+    } else {
+        name.trim().to_string()
+    }
+}
+
+#[tracing::instrument(
+    name = "import subscribers",
+    skip(pool, email_client, base_url, messages, records, errors),
+    fields(rows = records.len())
+)]
+pub async fn import_subscribers(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    messages: &Messages<'_>,
+    records: Vec<ImportRecord>,
+    errors: Vec<RowError>,
+    options: &ImportOptions,
+) -> Result<ImportReport, anyhow::Error> {
+    let mut report = ImportReport {
+        dry_run: options.dry_run,
+        total_rows: records.len() + errors.len(),
+        imported: 0,
+        suppressed: 0,
+        unsubscribed: 0,
+        errors,
+    };
+
+    let emails: Vec<String> = records.iter().map(|r| r.email.clone()).collect();
+    let mut seen = existing_emails(pool, &emails).await?;
+    let suppressed_emails = suppressed(pool, &emails)
+        .await
+        .context("failed to look up suppressed addresses")?;
+
+    for record in records {
+        if let RecordAction::Suppress { reason } = record.action {
+            let outcome = match SubscriberEmail::parse(record.email.clone()) {
+                Ok(email) => suppress_record(pool, &email, reason, options.dry_run)
+                    .await
+                    .map_err(|e| format!("{:#}", e)),
+                Err(error) => Err(error),
+            };
+
+            match outcome {
+                Ok(unsubscribed) => {
+                    report.suppressed += 1;
+                    report.unsubscribed += usize::from(unsubscribed);
+                },
+                Err(error) => report.errors.push(RowError { row: record.row, email: record.email, error }),
+            }
+
+            continue;
+        }
+
+        let new_subscriber = match parse_record(&record) {
+            Ok(new_subscriber) => new_subscriber,
+            Err(error) => {
+                report.errors.push(RowError { row: record.row, email: record.email, error });
+                continue;
+            },
+        };
+
+        if let Some(reason) = suppressed_emails.get(&hash_email(new_subscriber.email.as_ref())) {
+            report.errors.push(RowError {
+                row: record.row,
+                email: record.email,
+                error: format!("suppressed ({})", reason),
+            });
+            continue;
+        }
+
+        if !seen.insert(new_subscriber.email.as_ref().to_lowercase()) {
+            report.errors.push(RowError {
+                row: record.row,
+                email: record.email,
+                error: "already subscribed".into(),
+            });
+            continue;
+        }
+
+        if options.dry_run {
+            report.imported += 1;
+            continue;
+        }
+
+        let consent_attestation = record.consent_attestation.as_deref();
+
+        match import_subscriber(pool, email_client, base_url, messages, &new_subscriber, consent_attestation, options).await {
+            Ok(()) => report.imported += 1,
+            Err(err) => {
+                tracing::warn!(err.cause_chain = ?err, row = record.row, "failed to import a subscriber");
+                report.errors.push(RowError { row: record.row, email: record.email, error: format!("{:#}", err) });
+            },
+        }
+    }
+
+    Ok(report)
+}
+
+/// Suppresses the address and ends the subscription it may already have.
+/// Returns whether there was one to end.
+async fn suppress_record(pool: &PgPool, email: &SubscriberEmail, reason: &str, dry_run: bool) -> Result<bool, anyhow::Error> {
+    // cleaned addresses bounced; anything else unsubscribed
+    let status = if reason == "cleaned" { "bounced" } else { "unsubscribed" };
+
+    let mut transaction = pool.begin().await.context("failed to start a transaction")?;
+
+    suppress(&mut transaction, email.as_ref(), reason)
+        .await
+        .context("failed to store suppression")?;
+
+    let ended = sqlx::query!(
+            r#"
+                UPDATE subscriptions SET
+                    status = $2,
+                    unsubscribed_at = CASE WHEN $2 = 'unsubscribed' THEN COALESCE(unsubscribed_at, $3) ELSE unsubscribed_at END
+                WHERE lower(email) = lower($1) AND status NOT IN ('unsubscribed', 'bounced')
+                RETURNING id
+            "#,
+            email.as_ref(), status, Utc::now()
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .context("failed to end the existing subscription")?;
+
+    if let Some(subscriber) = &ended {
+        stop_sequences(&mut transaction, subscriber.id)
+            .await
+            .context("failed to stop the sequences")?;
+    }
+
+    if dry_run {
+        transaction.rollback().await.context("failed to roll back the dry run")?;
+    } else {
+        transaction.commit().await.context("failed to commit the suppression")?;
+    }
+
+    Ok(ended.is_some())
+}
+
+fn parse_record(record: &ImportRecord) -> Result<NewSubscriber, String> {
+    let name = SubscriberName::parse(record.name.clone())?;
+    let email = SubscriberEmail::parse(record.email.clone())?;
+
+    Ok(NewSubscriber { email, name })
+}
+
+/// The lowercased addresses among `emails` that already have a subscription,
+/// whatever their case.
+async fn existing_emails(pool: &PgPool, emails: &[String]) -> Result<HashSet<String>, anyhow::Error> {
+    let emails: Vec<String> = emails.iter().map(|email| email.to_lowercase()).collect();
+
+    let rows = sqlx::query!(
+            r#"SELECT lower(email) AS "email!" FROM subscriptions WHERE lower(email) = ANY($1)"#,
+            &emails
+        )
+        .fetch_all(pool)
+        .await
+        .context("failed to look up existing subscribers")?;
+
+    Ok(rows.into_iter().map(|r| r.email).collect())
+}
+
+async fn import_subscriber(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    messages: &Messages<'_>,
+    new_subscriber: &NewSubscriber,
+    consent_attestation: Option<&str>,
+    options: &ImportOptions,
+) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin()
+        .await
+        .context("failed to acquire a postgres connection from the pool")?;
+
+    let subscriber_id = insert_imported_subscriber(&mut transaction, new_subscriber, consent_attestation, options)
+        .await
+        .context("failed to insert subscriber")?;
+
+    match &options.mode {
+        ImportMode::Confirmed { .. } => {
+            transaction.commit().await.context("failed to commit")?;
+        },
+        ImportMode::Pending => {
+            let subscription_token = generate_subscription_token();
+
+            store_token(&mut transaction, subscriber_id, &subscription_token)
+                .await
+                .context("failed to store token")?;
+
+            transaction.commit().await.context("failed to commit")?;
+
+            send_confirmation_email(pool, email_client, messages, ConfirmationEmail::Welcome, new_subscriber, base_url, &subscription_token)
+                .await
+                .context("imported, but failed to send the confirmation email")?;
+        },
+    }
+
+    Ok(())
+}
+
+async fn insert_imported_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+    consent_attestation: Option<&str>,
+    options: &ImportOptions,
+) -> Result<Uuid, sqlx::Error> {
+    let uuid = Uuid::new_v4();
+
+    let (status, consent_attestation) = match &options.mode {
+        ImportMode::Pending => ("pending_confirmation", None),
+        ImportMode::Confirmed { consent_attestation: attestation } => {
+            ("confirmed", Some(consent_attestation.unwrap_or(attestation)))
+        },
+    };
+
+    let now = Utc::now();
+    let confirmed_at = (status == "confirmed").then_some(now);
+
+    sqlx::query!(
+            r#"
+                INSERT INTO subscriptions (id, email, name, subscribed_at, status, source, consent_attestation, confirmed_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+            uuid,
+            new_subscriber.email.as_ref(),
+            new_subscriber.name.as_ref(),
+            now,
+            status,
+            options.format.source(),
+            consent_attestation,
+            confirmed_at
+        )
+        .execute(transaction)
+        .await?;
+
+    Ok(uuid)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::TryFrom;
+    use claim::{assert_err, assert_ok};
+    use crate::import::{read_csv, ImportFormat, ImportMode, ImportOptions, ImportParameters};
+
+    #[test]
+    fn headers_are_matched_case_insensitively_and_extra_columns_are_ignored() {
+        let csv = "Name,Company,EMAIL\nUrsula,Earthsea Inc,ursula@example.com\n";
+
+        let (records, errors) = read_csv(csv.as_bytes()).unwrap();
+
+        assert!(errors.is_empty());
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].row, 2);
+        assert_eq!(records[0].email, "ursula@example.com");
+        assert_eq!(records[0].name, "Ursula");
+    }
+
+    #[test]
+    fn a_file_without_an_email_column_is_rejected() {
+        assert_err!(read_csv("name\nUrsula\n".as_bytes()));
+    }
+
+    #[test]
+    fn confirmed_mode_requires_a_consent_attestation() {
+        assert_err!(ImportMode::try_from(("confirmed", None)));
+        assert_err!(ImportMode::try_from(("confirmed", Some("  ".to_string()))));
+        assert_ok!(ImportMode::try_from(("confirmed", Some("double opt-in on the old site".to_string()))));
+    }
+
+    #[test]
+    fn unknown_modes_are_rejected() {
+        assert_err!(ImportMode::try_from(("subscribed", None)));
+    }
+
+    #[test]
+    fn platform_exports_are_imported_as_confirmed_by_default() {
+        let parameters = ImportParameters { format: Some("substack".into()), ..Default::default() };
+
+        let options = ImportOptions::try_from(parameters).unwrap();
+
+        assert!(matches!(options.format, ImportFormat::Substack));
+        assert!(matches!(options.mode, ImportMode::Confirmed { .. }));
+    }
+
+    #[test]
+    fn mailchimp_imports_require_a_status() {
+        let parameters = ImportParameters { format: Some("mailchimp".into()), ..Default::default() };
+
+        assert_err!(ImportOptions::try_from(parameters));
+    }
+}
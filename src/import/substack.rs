@@ -0,0 +1,93 @@
+//! The subscriber list of a Substack export (`email_list.*.csv`). It has no
+//! status column; readers who turned emails off are flagged with `email_disabled`.
+
+use crate::import::{column, csv_reader, headers, name_or_local_part, row_error, row_number, ImportRecord, RecordAction, RowError};
+
+pub fn read_csv<R: std::io::Read>(reader: R) -> Result<(Vec<ImportRecord>, Vec<RowError>), String> {
+    let mut reader = csv_reader(reader);
+    let headers = headers(&mut reader)?;
+
+    let email_column = column(&headers, &["email"]).ok_or("the csv file has no 'email' column")?;
+    let name_column = column(&headers, &["name"]);
+    let email_disabled_column = column(&headers, &["email_disabled"]);
+    let created_at_column = column(&headers, &["created_at"]);
+
+    let mut records = vec![];
+    let mut errors = vec![];
+
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                errors.push(row_error(err));
+                continue;
+            },
+        };
+
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|c| record.get(c))
+                .filter(|value| !value.is_empty())
+        };
+
+        let email = field(Some(email_column)).unwrap_or_default().to_string();
+        let name = field(name_column).unwrap_or_default();
+
+        let email_disabled = field(email_disabled_column)
+            .map(|value| value.eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+
+        let action = if email_disabled {
+            RecordAction::Suppress { reason: "unsubscribed" }
+        } else {
+            RecordAction::Subscribe
+        };
+
+        let consent_attestation = match field(created_at_column) {
+            Some(created_at) => format!("imported from substack, subscribed at {}", created_at),
+            None => "imported from substack".to_string(),
+        };
+
+        records.push(ImportRecord {
+            row: row_number(&record),
+            name: name_or_local_part(name, &email),
+            email,
+            action,
+            consent_attestation: Some(consent_attestation),
+        });
+    }
+
+    Ok((records, errors))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::import::substack::read_csv;
+    use crate::import::RecordAction;
+
+    const EXPORT: &str = "\
+email,active_subscription,expiry,plan,email_disabled,title,created_at
+ursula@example.com,false,,free,false,,2021-11-02T09:12:44.000Z
+ged@example.com,true,2023-01-01,paid,true,,2021-12-24T18:00:00.000Z
+";
+
+    #[test]
+    fn readers_with_emails_enabled_are_subscribed() {
+        let (records, errors) = read_csv(EXPORT.as_bytes()).unwrap();
+
+        assert!(errors.is_empty());
+        assert_eq!(records[0].action, RecordAction::Subscribe);
+        assert_eq!(records[0].name, "ursula");
+        assert_eq!(
+            records[0].consent_attestation.as_deref(),
+            Some("imported from substack, subscribed at 2021-11-02T09:12:44.000Z")
+        );
+    }
+
+    #[test]
+    fn readers_with_emails_disabled_are_suppressed() {
+        let (records, _) = read_csv(EXPORT.as_bytes()).unwrap();
+
+        assert_eq!(records[1].action, RecordAction::Suppress { reason: "unsubscribed" });
+    }
+}
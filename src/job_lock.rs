@@ -0,0 +1,36 @@
+//! Locks that keep a background job from running twice at once, whether on
+//! several instances or in a scheduler tick and an admin request at the same
+//! time. They are postgres advisory locks held by a transaction, so they are
+//! released when the job is done, or when its connection is lost.
+
+use sqlx::{PgPool, Postgres, Transaction};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Job {
+    Outbox = 1,
+    Reminders = 2,
+    Sequences = 3,
+}
+
+/// Holds the lock of a job until it is dropped.
+pub struct JobLock {
+    _transaction: Transaction<'static, Postgres>,
+}
+
+/// Takes the lock of `job`, or returns `None` if the job is running elsewhere.
+#[tracing::instrument(name = "lock a job", skip(pool))]
+pub async fn try_lock(pool: &PgPool, job: Job) -> Result<Option<JobLock>, sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    let locked = sqlx::query!(r#"SELECT pg_try_advisory_xact_lock($1) AS "locked!""#, job as i64)
+        .fetch_one(&mut transaction)
+        .await?
+        .locked;
+
+    if !locked {
+        tracing::info!("the job is already running elsewhere, skipping it");
+        return Ok(None);
+    }
+
+    Ok(Some(JobLock { _transaction: transaction }))
+}
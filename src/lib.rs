@@ -3,4 +3,37 @@ pub mod routes;
 pub mod startup;
 pub mod telemetry;
 pub mod domain;
-pub mod email_client;
\ No newline at end of file
+pub mod email_client;
+pub mod authentication;
+pub mod export;
+pub mod import;
+pub mod suppression;
+pub mod audit;
+pub mod gdpr;
+pub mod consent;
+pub mod tracking;
+pub mod stats;
+pub mod analytics;
+pub mod signing;
+pub mod engagement;
+pub mod sunset;
+pub mod attributes;
+pub mod merge;
+pub mod tags;
+pub mod segments;
+pub mod preferences;
+pub mod digest;
+pub mod email_change;
+pub mod reminders;
+pub mod scheduler;
+pub mod sequences;
+pub mod archive;
+pub mod feeds;
+pub mod drafts;
+pub mod feed_watcher;
+pub mod locales;
+pub mod throttle;
+pub mod circuit_breaker;
+pub mod outbox;
+pub mod job_lock;
+pub mod cli;
\ No newline at end of file
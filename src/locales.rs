@@ -0,0 +1,234 @@
+//! Message catalogs for the emails and pages the application writes itself.
+//! A catalog is a json object of message keys and templates, with `{name}`
+//! placeholders. English is built in and fills in for any locale or message
+//! that is missing; every `<locale>.json` in `locales.directory` adds (or
+//! overrides) a locale when the application starts.
+
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::merge::html_escape;
+
+pub const DEFAULT_LOCALE: &str = "en";
+
+const BUILT_IN: &str = include_str!("../locales/en.json");
+
+type Catalog = HashMap<String, String>;
+
+pub struct Locales {
+    catalogs: HashMap<String, Catalog>,
+}
+
+impl Locales {
+    /// English only.
+    pub fn built_in() -> Self {
+        let english = serde_json::from_str(BUILT_IN).expect("the built in catalog is not valid json");
+
+        Self { catalogs: HashMap::from([(DEFAULT_LOCALE.to_string(), english)]) }
+    }
+
+    /// The built in catalog and the catalogs in `directory`, which does not
+    /// have to exist.
+    pub fn load(directory: &Path) -> Result<Self, anyhow::Error> {
+        let mut locales = Self::built_in();
+
+        let entries = match std::fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(locales),
+            Err(e) => return Err(anyhow::Error::new(e).context(format!("failed to read {}", directory.display()))),
+        };
+
+        for entry in entries {
+            let path = entry.context("failed to read the locales directory")?.path();
+
+            let locale = match (path.file_stem().and_then(|stem| stem.to_str()), path.extension()) {
+                (Some(stem), Some(extension)) if extension == "json" => parse_locale(stem)
+                    .map_err(anyhow::Error::msg)
+                    .with_context(|| format!("{} is not named after a locale", path.display()))?,
+                _ => continue,
+            };
+
+            let catalog: Catalog = std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::new)
+                .and_then(|json| Ok(serde_json::from_str(&json)?))
+                .with_context(|| format!("failed to load the catalog {}", path.display()))?;
+
+            locales.catalogs.entry(locale).or_default().extend(catalog);
+        }
+
+        Ok(locales)
+    }
+
+    pub fn is_available(&self, locale: &str) -> bool {
+        self.catalogs.contains_key(locale)
+    }
+
+    /// The locale to talk to a new subscriber in: the one they asked for,
+    /// else the best one their browser accepts, else English. A regional
+    /// locale (`fr-ca`) falls back to its language (`fr`).
+    pub fn negotiate(&self, requested: Option<&str>, accept_language: Option<&str>) -> String {
+        let accepted = accept_language.map(parse_accept_language).unwrap_or_default();
+
+        requested
+            .into_iter()
+            .chain(accepted.iter().map(String::as_str))
+            .filter_map(|tag| self.available(tag))
+            .next()
+            .unwrap_or_else(|| DEFAULT_LOCALE.to_string())
+    }
+
+    fn available(&self, tag: &str) -> Option<String> {
+        let locale = parse_locale(tag).ok()?;
+
+        if self.is_available(&locale) {
+            return Some(locale);
+        }
+
+        let language = locale.split('-').next()?;
+        self.is_available(language).then(|| language.to_string())
+    }
+
+    pub fn messages(&self, locale: &str) -> Messages<'_> {
+        Messages {
+            catalog: self.catalogs.get(locale),
+            fallback: &self.catalogs[DEFAULT_LOCALE],
+        }
+    }
+}
+
+/// The messages of one locale.
+pub struct Messages<'a> {
+    catalog: Option<&'a Catalog>,
+    fallback: &'a Catalog,
+}
+
+impl Messages<'_> {
+    fn template<'k>(&'k self, key: &'k str) -> &'k str {
+        match self.catalog.and_then(|catalog| catalog.get(key)).or_else(|| self.fallback.get(key)) {
+            Some(template) => template,
+            None => {
+                tracing::warn!(key, "no message in the catalogs");
+                key
+            },
+        }
+    }
+
+    pub fn text(&self, key: &str, args: &[(&str, &str)]) -> String {
+        fill(self.template(key), args, str::to_string)
+    }
+
+    /// Arguments are html-escaped.
+    pub fn html(&self, key: &str, args: &[(&str, &str)]) -> String {
+        fill(self.template(key), args, html_escape)
+    }
+}
+
+fn fill(template: &str, args: &[(&str, &str)], escape: impl Fn(&str) -> String) -> String {
+    args.iter().fold(template.to_string(), |message, (name, value)| {
+        message.replace(&format!("{{{}}}", name), &escape(value))
+    })
+}
+
+/// Lowercase language tags, with `-` between subtags: `pt_BR` is `pt-br`.
+pub fn parse_locale(tag: &str) -> Result<String, String> {
+    let locale = tag.trim().to_ascii_lowercase().replace('_', "-");
+
+    let valid = !locale.is_empty()
+        && locale.len() <= 35
+        && locale.split('-').all(|subtag| !subtag.is_empty() && subtag.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    if valid {
+        Ok(locale)
+    } else {
+        Err(format!("{} is not a valid locale", tag))
+    }
+}
+
+/// The tags of an `Accept-Language` header, most preferred first.
+fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut tags: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .find_map(|param| param.trim().strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then(|| (tag.to_string(), quality))
+        })
+        .collect();
+
+    // stable, so tags of the same quality keep their order
+    tags.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    tags.into_iter().map(|(tag, _)| tag).collect()
+}
+
+#[tracing::instrument(name = "get the locale of a subscriber", skip(pool))]
+pub async fn subscriber_locale(pool: &PgPool, subscriber_id: Uuid) -> Result<String, sqlx::Error> {
+    let locale = sqlx::query!(r#"SELECT locale FROM subscriptions WHERE id = $1"#, subscriber_id)
+        .fetch_optional(pool)
+        .await?
+        .map(|r| r.locale);
+
+    Ok(locale.unwrap_or_else(|| DEFAULT_LOCALE.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use crate::locales::{parse_accept_language, parse_locale, Locales, DEFAULT_LOCALE};
+
+    fn english_and_french() -> Locales {
+        let mut locales = Locales::built_in();
+        locales.catalogs.insert("fr".into(), HashMap::from([("confirmation.subject".into(), "Bienvenue !".into())]));
+        locales
+    }
+
+    #[test]
+    fn the_requested_locale_wins_over_the_browser() {
+        let locales = english_and_french();
+
+        assert_eq!(locales.negotiate(Some("fr"), Some("en-US")), "fr");
+        assert_eq!(locales.negotiate(Some("de"), Some("de-DE, fr;q=0.8, en;q=0.5")), "fr");
+        assert_eq!(locales.negotiate(None, Some("fr-CA")), "fr");
+        assert_eq!(locales.negotiate(None, Some("de, *;q=0.5")), DEFAULT_LOCALE);
+        assert_eq!(locales.negotiate(None, None), DEFAULT_LOCALE);
+    }
+
+    #[test]
+    fn accept_language_is_sorted_by_quality() {
+        assert_eq!(parse_accept_language("en;q=0.5, fr-CH, fr;q=0.9, de;q=0"), vec!["fr-CH", "fr", "en"]);
+        assert!(parse_accept_language("").is_empty());
+    }
+
+    #[test]
+    fn missing_messages_fall_back_to_english() {
+        let locales = english_and_french();
+        let french = locales.messages("fr");
+
+        assert_eq!(french.text("confirmation.subject", &[]), "Bienvenue !");
+        assert_eq!(french.text("reminder.subject", &[]), "Please confirm your subscription");
+        assert_eq!(locales.messages("xx").text("confirmation.subject", &[]), "Welcome!");
+    }
+
+    #[test]
+    fn html_arguments_are_escaped() {
+        let locales = Locales::built_in();
+
+        assert_eq!(
+            locales.messages("en").html("confirmation.html", &[("link", "https://example.com/?a=1&b=2")]),
+            "Welcome to our newsletter! <br />Click <a href=\"https://example.com/?a=1&amp;b=2\">here</a> to confirm your subscription."
+        );
+    }
+
+    #[test]
+    fn locales_are_normalised() {
+        assert_eq!(parse_locale("pt_BR"), Ok("pt-br".into()));
+        assert!(parse_locale("../etc").is_err());
+        assert!(parse_locale("").is_err());
+    }
+}
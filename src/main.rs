@@ -1,18 +1,18 @@
-use newsletter::startup::{Application};
-use newsletter::configuration::get_configuration;
+use clap::Parser;
+use newsletter::cli::{self, Cli, Command};
 use newsletter::telemetry;
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
-    let subscriber = telemetry::get_subscriber("newsletter_dev".into(),"info".into(),std::io::stdout);
+async fn main() -> Result<(), anyhow::Error> {
+    let command = Cli::parse().command.unwrap_or(Command::Serve);
 
-    telemetry::init_subscriber(subscriber);
+    if command.logs_to_stdout() {
+        let subscriber = telemetry::get_subscriber("newsletter_dev".into(),"info".into(),std::io::stdout);
+        telemetry::init_subscriber(subscriber);
+    } else {
+        let subscriber = telemetry::get_subscriber("newsletter_dev".into(),"info".into(),std::io::stderr);
+        telemetry::init_subscriber(subscriber);
+    }
 
-    let config = get_configuration().expect("failed to read configuration.");
-
-    let app = Application::build(&config).await?;
-
-    app.run_until_stopped().await?;
-
-    Ok(())
+    cli::run(command).await
 }
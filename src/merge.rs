@@ -0,0 +1,134 @@
+//! Merge variables: `{{ company }}` in an issue is replaced with the value of
+//! the recipient's `company` attribute, and `{{ company | your team }}` falls
+//! back to "your team" when they have none. `name` and `email` are always
+//! available.
+
+use serde_json::{Map, Value};
+
+pub struct MergeFields<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    pub attributes: &'a Map<String, Value>,
+}
+
+impl MergeFields<'_> {
+    fn get(&self, field: &str) -> Option<String> {
+        match field {
+            "name" => Some(self.name.to_string()),
+            "email" => Some(self.email.to_string()),
+            _ => match self.attributes.get(field)? {
+                Value::Null => None,
+                Value::String(s) => Some(s.clone()),
+                other => Some(other.to_string()),
+            },
+        }
+    }
+}
+
+pub fn render_html(template: &str, fields: &MergeFields) -> String {
+    render(template, fields, html_escape)
+}
+
+pub fn render_text(template: &str, fields: &MergeFields) -> String {
+    render(template, fields, str::to_string)
+}
+
+/// Unknown fields without a fallback render as an empty string, and anything
+/// that is not a well formed tag is left as it is.
+fn render(template: &str, fields: &MergeFields, escape: impl Fn(&str) -> String) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        let (field, fallback) = match rest[start + 2..end].split_once('|') {
+            Some((field, fallback)) => (field.trim(), Some(fallback.trim())),
+            None => (rest[start + 2..end].trim(), None),
+        };
+
+        rendered.push_str(&rest[..start]);
+
+        if is_field_name(field) {
+            let value = fields.get(field)
+                .filter(|value| !value.is_empty())
+                .or_else(|| fallback.map(str::to_string))
+                .unwrap_or_default();
+
+            rendered.push_str(&escape(&value));
+        } else {
+            rendered.push_str(&rest[start..end + 2]);
+        }
+
+        rest = &rest[end + 2..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+fn is_field_name(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+pub fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Map, Value};
+    use crate::merge::{render_html, render_text, MergeFields};
+
+    fn attributes() -> Map<String, Value> {
+        json!({ "company": "Smith & Sons", "seats": 12, "beta": true })
+            .as_object()
+            .unwrap()
+            .clone()
+    }
+
+    #[test]
+    fn fields_and_attributes_are_merged() {
+        let attributes = attributes();
+        let fields = MergeFields { name: "Ursula", email: "ursula@example.com", attributes: &attributes };
+
+        assert_eq!(
+            render_text("Hi {{name}}, {{ company }} has {{ seats }} seats ({{beta}})", &fields),
+            "Hi Ursula, Smith & Sons has 12 seats (true)"
+        );
+    }
+
+    #[test]
+    fn missing_fields_use_the_fallback() {
+        let attributes = Map::new();
+        let fields = MergeFields { name: "Ursula", email: "ursula@example.com", attributes: &attributes };
+
+        assert_eq!(render_text("Hello {{ company | friend }}{{ plan }}!", &fields), "Hello friend!");
+    }
+
+    #[test]
+    fn values_are_escaped_in_html() {
+        let attributes = attributes();
+        let fields = MergeFields { name: "<b>Ursula</b>", email: "ursula@example.com", attributes: &attributes };
+
+        assert_eq!(
+            render_html("<p>{{ name }} of {{ company }}</p>", &fields),
+            "<p>&lt;b&gt;Ursula&lt;/b&gt; of Smith &amp; Sons</p>"
+        );
+    }
+
+    #[test]
+    fn text_that_is_not_a_tag_is_left_alone() {
+        let attributes = Map::new();
+        let fields = MergeFields { name: "Ursula", email: "ursula@example.com", attributes: &attributes };
+
+        assert_eq!(render_text("{{ not a field }} and {{ unclosed", &fields), "{{ not a field }} and {{ unclosed");
+    }
+}
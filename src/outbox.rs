@@ -0,0 +1,144 @@
+//! Emails that could not go out because the circuit to the email provider was
+//! open, or because an interactive send would have waited too long for the
+//! throttle. They wait in `email_outbox` until `flush_outbox` sends them, which
+//! `serve` does on every scheduler tick.
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, SendEmailError};
+use crate::job_lock::{try_lock, Job};
+
+/// Tries per email before it is given up on.
+const MAX_ATTEMPTS: i32 = 5;
+
+/// Emails sent per flush, oldest first.
+const FLUSH_LIMIT: i64 = 100;
+
+#[derive(serde::Serialize, Default, Debug)]
+pub struct OutboxReport {
+    pub sent: usize,
+    /// Emails given up on after `MAX_ATTEMPTS` tries.
+    pub failed: usize,
+}
+
+/// Sends the email, or queues it if the circuit is open or the throttle held
+/// it back. Any other failure is returned, as the provider did get to see the
+/// email.
+#[tracing::instrument(name = "send or enqueue an email", skip(pool, email_client, subject, html_body, text_body))]
+pub async fn send_or_enqueue(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    subject: &str,
+    html_body: &str,
+    text_body: &str,
+) -> Result<(), anyhow::Error> {
+    match email_client.send_email(recipient, subject, html_body, text_body).await {
+        Err(e @ (SendEmailError::CircuitOpen | SendEmailError::Throttled)) => {
+            tracing::info!(reason = %e, "queueing the email");
+
+            sqlx::query!(
+                    r#"
+                        INSERT INTO email_outbox (id, recipient, subject, html_body, text_body, queued_at)
+                        VALUES ($1, $2, $3, $4, $5, $6)
+                    "#,
+                    Uuid::new_v4(), recipient.as_ref(), subject, html_body, text_body, Utc::now()
+                )
+                .execute(pool)
+                .await
+                .context("failed to queue the email")?;
+
+            Ok(())
+        },
+        result => Ok(result?),
+    }
+}
+
+#[tracing::instrument(name = "count queued emails", skip(pool))]
+pub async fn queued_emails(pool: &PgPool) -> Result<i64, sqlx::Error> {
+    let count = sqlx::query!(r#"SELECT count(*) AS "count!" FROM email_outbox"#)
+        .fetch_one(pool)
+        .await?
+        .count;
+
+    Ok(count)
+}
+
+/// Sends the queued emails, and stops at the first one the open circuit
+/// holds back. Does nothing while another flush is under way.
+#[tracing::instrument(name = "flush the outbox", skip(pool, email_client))]
+pub async fn flush_outbox(pool: &PgPool, email_client: &EmailClient) -> Result<OutboxReport, anyhow::Error> {
+    let _lock = match try_lock(pool, Job::Outbox).await.context("failed to lock the job")? {
+        Some(lock) => lock,
+        None => return Ok(OutboxReport::default()),
+    };
+
+    let queued = sqlx::query!(
+            r#"
+                SELECT id, recipient, subject, html_body, text_body, attempts
+                FROM email_outbox
+                ORDER BY queued_at
+                LIMIT $1
+            "#,
+            FLUSH_LIMIT
+        )
+        .fetch_all(pool)
+        .await
+        .context("failed to fetch the queued emails")?;
+
+    let mut report = OutboxReport::default();
+
+    for email in queued {
+        let recipient = match SubscriberEmail::parse(email.recipient) {
+            Ok(recipient) => recipient,
+            Err(error) => {
+                tracing::error!(%error, "dropping a queued email to an invalid address");
+                delete(pool, email.id).await?;
+                report.failed += 1;
+                continue;
+            },
+        };
+
+        let sent = email_client
+            .send_email(&recipient, &email.subject, &email.html_body, &email.text_body)
+            .await;
+
+        let error = match sent {
+            Ok(()) => {
+                delete(pool, email.id).await?;
+                report.sent += 1;
+                continue;
+            },
+            Err(SendEmailError::CircuitOpen) => break,
+            Err(e) => e.to_string(),
+        };
+
+        if email.attempts + 1 >= MAX_ATTEMPTS {
+            tracing::error!(%error, "giving up on a queued email to {}", recipient);
+            delete(pool, email.id).await?;
+            report.failed += 1;
+        } else {
+            sqlx::query!(
+                    r#"UPDATE email_outbox SET attempts = attempts + 1, last_error = $2 WHERE id = $1"#,
+                    email.id, error
+                )
+                .execute(pool)
+                .await
+                .context("failed to record the failed attempt")?;
+        }
+    }
+
+    Ok(report)
+}
+
+async fn delete(pool: &PgPool, id: Uuid) -> Result<(), anyhow::Error> {
+    sqlx::query!(r#"DELETE FROM email_outbox WHERE id = $1"#, id)
+        .execute(pool)
+        .await
+        .context("failed to delete a queued email")?;
+
+    Ok(())
+}
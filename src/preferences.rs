@@ -0,0 +1,219 @@
+//! The preference center. Every email links to a page, signed for its
+//! subscriber, where they can change their name, topics and frequency, pause
+//! the newsletter or unsubscribe.
+
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+use serde::Serialize;
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use uuid::Uuid;
+use crate::domain::SubscriberName;
+use crate::signing;
+
+/// How long "pause" pauses the newsletter for.
+pub const PAUSE_DAYS: i64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Frequency {
+    EveryIssue,
+    /// Issues are queued and sent together by the weekly digest.
+    WeeklyDigest,
+}
+
+impl Frequency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Frequency::EveryIssue => "every_issue",
+            Frequency::WeeklyDigest => "weekly_digest",
+        }
+    }
+}
+
+impl TryFrom<&str> for Frequency {
+    type Error = String;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "every_issue" => Ok(Self::EveryIssue),
+            "weekly_digest" => Ok(Self::WeeklyDigest),
+            other => Err(format!("{} is not a frequency. use 'every_issue' or 'weekly_digest'", other)),
+        }
+    }
+}
+
+pub struct Preferences {
+    pub name: String,
+    pub email: String,
+    pub status: String,
+    pub frequency: Frequency,
+    pub paused_until: Option<DateTime<Utc>>,
+    pub topics: Vec<TopicChoice>,
+}
+
+impl Preferences {
+    pub fn is_paused(&self) -> bool {
+        self.paused_until.is_some_and(|until| until > Utc::now())
+    }
+}
+
+pub struct TopicChoice {
+    pub tag: String,
+    pub label: String,
+    pub selected: bool,
+}
+
+pub struct PreferenceUpdate {
+    pub name: SubscriberName,
+    pub frequency: Frequency,
+    /// The tags of the chosen topics.
+    pub topics: Vec<String>,
+}
+
+/// Topic checkboxes are named `topic_<tag>`, as html forms send repeated
+/// fields that our form parser cannot collect.
+pub fn chosen_topics(fields: &HashMap<String, String>) -> Vec<String> {
+    let mut topics: Vec<String> = fields
+        .keys()
+        .filter_map(|field| field.strip_prefix("topic_"))
+        .map(str::to_string)
+        .collect();
+
+    topics.sort();
+    topics
+}
+
+pub fn preferences_url(base_url: &str, secret: &Secret<String>, subscriber_id: Uuid) -> String {
+    let subscriber_id = subscriber_id.to_string();
+    let signature = signing::sign(secret, &["preferences", &subscriber_id]);
+
+    format!("{}/preferences/{}/{}", base_url, subscriber_id, signature)
+}
+
+pub fn verify_preferences(secret: &Secret<String>, subscriber_id: Uuid, signature: &str) -> bool {
+    signing::verify(secret, &["preferences", &subscriber_id.to_string()], signature)
+}
+
+#[tracing::instrument(name = "fetch the preferences of a subscriber", skip(pool))]
+pub async fn fetch_preferences(pool: &PgPool, subscriber_id: Uuid) -> Result<Option<Preferences>, sqlx::Error> {
+    let subscriber = sqlx::query!(
+            r#"SELECT name, email, status, frequency, paused_until FROM subscriptions WHERE id = $1"#,
+            subscriber_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+    let subscriber = match subscriber {
+        Some(subscriber) => subscriber,
+        None => return Ok(None),
+    };
+
+    let topics = sqlx::query!(
+            r#"
+                SELECT t.tag, t.label, EXISTS (
+                    SELECT 1 FROM subscriber_tags st WHERE st.subscriber_id = $1 AND st.tag = t.tag
+                ) AS "selected!"
+                FROM topics t
+                ORDER BY t.label
+            "#,
+            subscriber_id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|r| TopicChoice { tag: r.tag, label: r.label, selected: r.selected })
+        .collect();
+
+    Ok(Some(Preferences {
+        name: subscriber.name,
+        email: subscriber.email,
+        status: subscriber.status,
+        frequency: Frequency::try_from(subscriber.frequency.as_str()).unwrap_or(Frequency::EveryIssue),
+        paused_until: subscriber.paused_until,
+        topics,
+    }))
+}
+
+/// Topics that are not in `update.topics` are removed; tags that are not
+/// topics are left alone.
+#[tracing::instrument(name = "update the preferences of a subscriber", skip(pool, update))]
+pub async fn update_preferences(pool: &PgPool, subscriber_id: Uuid, update: &PreferenceUpdate) -> Result<(), sqlx::Error> {
+    let mut transaction = pool.begin().await?;
+
+    sqlx::query!(
+            r#"UPDATE subscriptions SET name = $2, frequency = $3 WHERE id = $1"#,
+            subscriber_id, update.name.as_ref(), update.frequency.as_str()
+        )
+        .execute(&mut transaction)
+        .await?;
+
+    sqlx::query!(
+            r#"
+                DELETE FROM subscriber_tags
+                WHERE subscriber_id = $1 AND tag IN (SELECT tag FROM topics) AND NOT (tag = ANY($2))
+            "#,
+            subscriber_id, &update.topics
+        )
+        .execute(&mut transaction)
+        .await?;
+
+    sqlx::query!(
+            r#"
+                INSERT INTO subscriber_tags (subscriber_id, tag, created_at)
+                SELECT $1, tag, $3 FROM topics WHERE tag = ANY($2)
+                ON CONFLICT DO NOTHING
+            "#,
+            subscriber_id, &update.topics, Utc::now()
+        )
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Pauses the newsletter until `until`, or resumes it with `None`.
+#[tracing::instrument(name = "pause the newsletter for a subscriber", skip(pool))]
+pub async fn pause(pool: &PgPool, subscriber_id: Uuid, until: Option<DateTime<Utc>>) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+            r#"UPDATE subscriptions SET paused_until = $2 WHERE id = $1"#,
+            subscriber_id, until
+        )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+    use crate::preferences::{chosen_topics, preferences_url, verify_preferences};
+
+    #[test]
+    fn preference_links_only_work_for_their_subscriber() {
+        let secret = Secret::new("secret".to_string());
+        let subscriber_id = Uuid::new_v4();
+
+        let url = preferences_url("https://news.example.com", &secret, subscriber_id);
+        let signature = url.rsplit('/').next().unwrap();
+
+        assert!(verify_preferences(&secret, subscriber_id, signature));
+        assert!(!verify_preferences(&secret, Uuid::new_v4(), signature));
+    }
+
+    #[test]
+    fn topics_are_read_from_checkbox_names() {
+        let fields: HashMap<String, String> = [("topic_rust", "on"), ("topic_beta", "on"), ("name", "Ursula")]
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        assert_eq!(chosen_topics(&fields), vec!["beta", "rust"]);
+    }
+}
@@ -0,0 +1,112 @@
+//! Confirmation reminders. Subscribers still `pending_confirmation`
+//! `interval_hours` after they signed up (or after their last reminder) get
+//! the confirmation email again, with a fresh token, up to `max_reminders`
+//! times, in the locale they signed up in.
+
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::configuration::ReminderSettings;
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::email_client::EmailClient;
+use crate::job_lock::{try_lock, Job};
+use crate::locales::{Locales, Messages};
+use crate::routes::{generate_subscription_token, send_confirmation_email, store_token, ConfirmationEmail};
+
+#[derive(Serialize, Debug, Default)]
+pub struct ReminderReport {
+    pub sent: usize,
+    pub failed: usize,
+}
+
+/// Does nothing while another run is under way.
+#[tracing::instrument(name = "send confirmation reminders", skip(pool, email_client, base_url, locales))]
+pub async fn send_reminders(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    locales: &Locales,
+    policy: &ReminderSettings,
+) -> Result<ReminderReport, anyhow::Error> {
+    let _lock = match try_lock(pool, Job::Reminders).await.context("failed to lock the job")? {
+        Some(lock) => lock,
+        None => return Ok(ReminderReport::default()),
+    };
+
+    let due_before = Utc::now() - Duration::hours(policy.interval_hours);
+
+    let candidates = sqlx::query!(
+            r#"
+                SELECT id, email, name, locale FROM subscriptions
+                WHERE status = 'pending_confirmation'
+                    AND reminder_count < $1
+                    AND COALESCE(last_reminded_at, subscribed_at) <= $2
+            "#,
+            policy.max_reminders, due_before
+        )
+        .fetch_all(pool)
+        .await
+        .context("failed to find subscribers to remind")?;
+
+    let mut report = ReminderReport::default();
+
+    for candidate in candidates {
+        let messages = locales.messages(&candidate.locale);
+
+        match send_reminder(pool, email_client, base_url, &messages, candidate.id, candidate.email, candidate.name).await {
+            Ok(()) => report.sent += 1,
+            Err(e) => {
+                tracing::warn!(error.cause_chain = ?e, "failed to send a confirmation reminder");
+                report.failed += 1;
+            },
+        }
+    }
+
+    Ok(report)
+}
+
+/// Earlier tokens keep working, so an old email can still confirm.
+async fn send_reminder(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    messages: &Messages<'_>,
+    subscriber_id: Uuid,
+    email: String,
+    name: String,
+) -> Result<(), anyhow::Error> {
+    let subscriber = NewSubscriber {
+        email: SubscriberEmail::parse(email).map_err(anyhow::Error::msg)?,
+        name: SubscriberName::parse(name).map_err(anyhow::Error::msg)?,
+    };
+    let subscription_token = generate_subscription_token();
+
+    let mut transaction = pool.begin().await.context("failed to start a transaction")?;
+
+    store_token(&mut transaction, subscriber_id, &subscription_token)
+        .await
+        .context("failed to store token")?;
+
+    // counted whether or not the provider takes the email, so that an address
+    // it rejects is not retried forever. while it is down the email is queued
+    sqlx::query!(
+            r#"
+                UPDATE subscriptions SET reminder_count = reminder_count + 1, last_reminded_at = $2
+                WHERE id = $1
+            "#,
+            subscriber_id, Utc::now()
+        )
+        .execute(&mut transaction)
+        .await
+        .context("failed to record the reminder")?;
+
+    transaction.commit().await.context("failed to commit the reminder")?;
+
+    send_confirmation_email(pool, email_client, messages, ConfirmationEmail::Reminder, &subscriber, base_url, &subscription_token)
+        .await
+        .with_context(|| format!("failed to send a confirmation reminder to {}", subscriber.email))?;
+
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use chrono::NaiveDate;
+use sqlx::PgPool;
+use std::convert::TryFrom;
+use std::fmt::Formatter;
+use crate::analytics::{subscriber_growth, write_csv, DateRange, Interval};
+use crate::authentication::AdminUser;
+use crate::routes::error_chain_fmt;
+
+#[derive(serde::Deserialize)]
+pub struct GrowthParameters {
+    /// `day` (default), `week` or `month`.
+    interval: Option<String>,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    /// `json` (default) or `csv`.
+    format: Option<String>,
+}
+
+#[derive(thiserror::Error)]
+pub enum AnalyticsError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AnalyticsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self,f)
+    }
+}
+
+impl ResponseError for AnalyticsError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AnalyticsError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            AnalyticsError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[tracing::instrument(name = "get subscriber growth", skip(parameters, pool, admin), fields(admin = %admin.username))]
+pub async fn subscriber_growth_report(
+    parameters: web::Query<GrowthParameters>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> Result<HttpResponse, AnalyticsError> {
+    let parameters = parameters.into_inner();
+
+    let interval = Interval::try_from(parameters.interval.as_deref().unwrap_or("day"))
+        .map_err(AnalyticsError::ValidationError)?;
+    let range = DateRange::new(parameters.from, parameters.to).map_err(AnalyticsError::ValidationError)?;
+
+    let report = subscriber_growth(&pool, interval, &range)
+        .await
+        .context("failed to compute subscriber growth")?;
+
+    match parameters.format.as_deref().unwrap_or("json") {
+        "json" => Ok(HttpResponse::Ok().json(report)),
+        "csv" => {
+            let mut csv = vec![];
+            write_csv(&mut csv, &report).context("failed to write subscriber growth")?;
+
+            Ok(HttpResponse::Ok().content_type("text/csv; charset=utf-8").body(csv))
+        },
+        other => Err(AnalyticsError::ValidationError(format!("{} is not a supported format. use 'json' or 'csv'", other))),
+    }
+}
@@ -0,0 +1,106 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use serde_json::{Map, Value};
+use sqlx::PgPool;
+use std::fmt::Formatter;
+use uuid::Uuid;
+use crate::attributes::{define_attribute, fetch_schema, remove_attribute, update_attributes, AttributeDefinition};
+use crate::authentication::AdminUser;
+use crate::routes::error_chain_fmt;
+
+#[derive(thiserror::Error)]
+pub enum AttributeError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("not found")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AttributeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self,f)
+    }
+}
+
+impl ResponseError for AttributeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AttributeError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            AttributeError::NotFound => StatusCode::NOT_FOUND,
+            AttributeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[tracing::instrument(name = "list attribute definitions", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn attribute_definitions(
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> Result<HttpResponse, AttributeError> {
+    let schema = fetch_schema(&pool)
+        .await
+        .context("failed to fetch the attribute definitions")?;
+
+    Ok(HttpResponse::Ok().json(schema.definitions()))
+}
+
+#[tracing::instrument(name = "declare an attribute", skip(definition, pool, admin), fields(admin = %admin.username))]
+pub async fn declare_attribute(
+    definition: web::Json<AttributeDefinition>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> Result<HttpResponse, AttributeError> {
+    let definition = definition.into_inner();
+    let definition = AttributeDefinition::parse(definition.name, definition.kind)
+        .map_err(AttributeError::ValidationError)?;
+
+    define_attribute(&pool, &definition)
+        .await
+        .context("failed to store the attribute definition")?;
+
+    Ok(HttpResponse::Ok().json(definition))
+}
+
+#[tracing::instrument(name = "delete an attribute", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn delete_attribute(
+    name: web::Path<String>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> Result<HttpResponse, AttributeError> {
+    let removed = remove_attribute(&pool, &name)
+        .await
+        .context("failed to remove the attribute")?;
+
+    if !removed {
+        return Err(AttributeError::NotFound);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[tracing::instrument(name = "update the attributes of a subscriber", skip(attributes, pool, admin), fields(admin = %admin.username))]
+pub async fn update_subscriber_attributes(
+    subscriber_id: web::Path<Uuid>,
+    attributes: web::Json<Map<String, Value>>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> Result<HttpResponse, AttributeError> {
+    let attributes = fetch_schema(&pool)
+        .await
+        .context("failed to fetch the attribute definitions")?
+        .from_json(attributes.into_inner())
+        .map_err(AttributeError::ValidationError)?;
+
+    let updated = update_attributes(&pool, subscriber_id.into_inner(), attributes)
+        .await
+        .context("failed to update the subscriber attributes")?;
+
+    if !updated {
+        return Err(AttributeError::NotFound);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,62 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use sqlx::PgPool;
+use std::fmt::Formatter;
+use crate::authentication::AdminUser;
+use crate::domain::SubscriberEmail;
+use crate::gdpr;
+use crate::routes::error_chain_fmt;
+
+#[derive(serde::Deserialize)]
+pub struct DataSubject {
+    email: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum DataSubjectError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for DataSubjectError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self,f)
+    }
+}
+
+impl ResponseError for DataSubjectError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            DataSubjectError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            DataSubjectError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[tracing::instrument(name = "data subject access request", skip(subject, pool, admin), fields(admin = %admin.username))]
+pub async fn data_subject_access(
+    subject: web::Query<DataSubject>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> Result<HttpResponse, DataSubjectError> {
+    let email = SubscriberEmail::parse(subject.0.email).map_err(DataSubjectError::ValidationError)?;
+
+    let data = gdpr::access(&pool, email.as_ref(), &admin.username).await?;
+
+    Ok(HttpResponse::Ok().json(data))
+}
+
+#[tracing::instrument(name = "data subject erasure request", skip(subject, pool, admin), fields(admin = %admin.username))]
+pub async fn data_subject_erasure(
+    subject: web::Json<DataSubject>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> Result<HttpResponse, DataSubjectError> {
+    let email = SubscriberEmail::parse(subject.0.email).map_err(DataSubjectError::ValidationError)?;
+
+    let report = gdpr::erase(&pool, email.as_ref(), &admin.username).await?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
@@ -0,0 +1,78 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::authentication::AdminUser;
+use crate::configuration::FeedWatcherSettings;
+use crate::drafts::{delete_draft, list_drafts, publish_draft};
+use crate::email_client::EmailClient;
+use crate::feed_watcher::watch_feed;
+use crate::routes::PublishError;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+
+#[tracing::instrument(name = "list drafts", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn draft_list(pool: web::Data<PgPool>, admin: AdminUser) -> HttpResponse {
+    match list_drafts(&pool).await {
+        Ok(drafts) => HttpResponse::Ok().json(drafts),
+        Err(e) => {
+            tracing::error!("failed to list drafts. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(name = "delete a draft", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn remove_draft(draft_id: web::Path<Uuid>, pool: web::Data<PgPool>, admin: AdminUser) -> HttpResponse {
+    match delete_draft(&pool, draft_id.into_inner()).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("failed to delete the draft. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(
+    name = "publish a draft",
+    skip(pool, email_client, base_url, hmac_secret, admin),
+    fields(admin = %admin.username)
+)]
+pub async fn publish_draft_issue(
+    draft_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    admin: AdminUser,
+) -> Result<HttpResponse, PublishError> {
+    match publish_draft(&pool, &email_client, &base_url.0, &hmac_secret.0, draft_id.into_inner()).await? {
+        Some(report) => Ok(HttpResponse::Ok().json(report)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[tracing::instrument(
+    name = "run the feed watcher",
+    skip(pool, email_client, base_url, hmac_secret, settings, admin),
+    fields(admin = %admin.username)
+)]
+pub async fn run_feed_watcher(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    settings: web::Data<FeedWatcherSettings>,
+    admin: AdminUser,
+) -> HttpResponse {
+    if settings.source().is_none() {
+        return HttpResponse::Conflict().body("no feed is watched. set feed_watcher.source");
+    }
+
+    match watch_feed(&pool, &email_client, &base_url.0, &hmac_secret.0, &settings).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "failed to watch the feed");
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
@@ -0,0 +1,66 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::archive::set_public;
+use crate::authentication::AdminUser;
+use crate::stats::{fetch_issue_stats, write_csv, IssueStats};
+
+#[derive(serde::Deserialize)]
+pub struct VisibilityBody {
+    public: bool,
+}
+
+async fn issue_stats_or_response(pool: &PgPool, issue_id: Uuid) -> Result<IssueStats, HttpResponse> {
+    match fetch_issue_stats(pool, issue_id).await {
+        Ok(Some(stats)) => Ok(stats),
+        Ok(None) => Err(HttpResponse::NotFound().finish()),
+        Err(e) => {
+            tracing::error!("failed to compute issue statistics. {:?}", e);
+            Err(HttpResponse::InternalServerError().finish())
+        },
+    }
+}
+
+#[tracing::instrument(name = "get issue statistics", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn issue_stats(issue_id: web::Path<Uuid>, pool: web::Data<PgPool>, admin: AdminUser) -> HttpResponse {
+    match issue_stats_or_response(&pool, issue_id.into_inner()).await {
+        Ok(stats) => HttpResponse::Ok().json(stats),
+        Err(response) => response,
+    }
+}
+
+#[tracing::instrument(name = "download issue statistics", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn issue_stats_csv(issue_id: web::Path<Uuid>, pool: web::Data<PgPool>, admin: AdminUser) -> HttpResponse {
+    let stats = match issue_stats_or_response(&pool, issue_id.into_inner()).await {
+        Ok(stats) => stats,
+        Err(response) => return response,
+    };
+
+    let mut csv = vec![];
+    if let Err(e) = write_csv(&mut csv, &stats) {
+        tracing::error!("failed to write issue statistics. {:?}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(("Content-Disposition", format!(r#"attachment; filename="issue-{}-stats.csv""#, stats.issue_id)))
+        .body(csv)
+}
+
+#[tracing::instrument(name = "change the visibility of an issue", skip(body, pool, admin), fields(admin = %admin.username))]
+pub async fn issue_visibility(
+    issue_id: web::Path<Uuid>,
+    body: web::Json<VisibilityBody>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> HttpResponse {
+    match set_public(&pool, issue_id.into_inner(), body.public).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("failed to change the visibility of the issue. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
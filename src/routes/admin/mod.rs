@@ -0,0 +1,19 @@
+mod analytics;
+mod attributes;
+mod data_subjects;
+mod drafts;
+mod issues;
+mod segments;
+mod sequences;
+mod subscribers;
+mod tags;
+
+pub use analytics::*;
+pub use attributes::*;
+pub use data_subjects::*;
+pub use drafts::*;
+pub use issues::*;
+pub use segments::*;
+pub use sequences::*;
+pub use subscribers::*;
+pub use tags::*;
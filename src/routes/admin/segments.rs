@@ -0,0 +1,132 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Formatter;
+use crate::attributes::fetch_schema;
+use crate::authentication::AdminUser;
+use crate::routes::error_chain_fmt;
+use crate::segments::{
+    audience_filter, count_subscribers, delete_segment, fetch_segment, list_segments, parse_segment_name,
+    save_segment, validate_expression, Audience, Segment, SegmentError,
+};
+
+#[derive(thiserror::Error)]
+pub enum SegmentRouteError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("not found")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for SegmentRouteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self,f)
+    }
+}
+
+impl ResponseError for SegmentRouteError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SegmentRouteError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            SegmentRouteError::NotFound => StatusCode::NOT_FOUND,
+            SegmentRouteError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<SegmentError> for SegmentRouteError {
+    fn from(e: SegmentError) -> Self {
+        match e {
+            SegmentError::Invalid(message) => Self::ValidationError(message),
+            SegmentError::Unexpected(e) => Self::UnexpectedError(e.into()),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct SegmentBody {
+    expression: String,
+}
+
+/// A segment, and how many confirmed subscribers an issue sent to it would reach.
+#[derive(serde::Serialize)]
+struct SegmentSummary {
+    #[serde(flatten)]
+    segment: Segment,
+    recipients: i64,
+}
+
+async fn summarize(pool: &PgPool, segment: Segment) -> Result<SegmentSummary, SegmentRouteError> {
+    let filter = audience_filter(pool, &Audience { segment: Some(&segment.name), ..Audience::default() }).await?;
+
+    let recipients = count_subscribers(pool, &filter)
+        .await
+        .context("failed to count the recipients of the segment")?;
+
+    Ok(SegmentSummary { segment, recipients })
+}
+
+#[tracing::instrument(name = "list segments", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn segment_list(pool: web::Data<PgPool>, admin: AdminUser) -> Result<HttpResponse, SegmentRouteError> {
+    let segments = list_segments(&pool)
+        .await
+        .context("failed to list segments")?;
+
+    Ok(HttpResponse::Ok().json(segments))
+}
+
+#[tracing::instrument(name = "get a segment", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn segment_details(
+    name: web::Path<String>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> Result<HttpResponse, SegmentRouteError> {
+    let segment = fetch_segment(&pool, &name)
+        .await
+        .context("failed to fetch the segment")?
+        .ok_or(SegmentRouteError::NotFound)?;
+
+    Ok(HttpResponse::Ok().json(summarize(&pool, segment).await?))
+}
+
+#[tracing::instrument(name = "save a segment", skip(body, pool, admin), fields(admin = %admin.username))]
+pub async fn put_segment(
+    name: web::Path<String>,
+    body: web::Json<SegmentBody>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> Result<HttpResponse, SegmentRouteError> {
+    let name = parse_segment_name(&name).map_err(SegmentRouteError::ValidationError)?;
+
+    let schema = fetch_schema(&pool)
+        .await
+        .context("failed to fetch the attribute definitions")?;
+
+    validate_expression(&body.expression, &schema).map_err(SegmentRouteError::ValidationError)?;
+
+    let segment = save_segment(&pool, &name, body.expression.trim())
+        .await
+        .context("failed to save the segment")?;
+
+    Ok(HttpResponse::Ok().json(summarize(&pool, segment).await?))
+}
+
+#[tracing::instrument(name = "delete a segment", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn remove_segment(
+    name: web::Path<String>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> Result<HttpResponse, SegmentRouteError> {
+    let deleted = delete_segment(&pool, &name)
+        .await
+        .context("failed to delete the segment")?;
+
+    if !deleted {
+        return Err(SegmentRouteError::NotFound);
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
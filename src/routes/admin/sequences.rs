@@ -0,0 +1,150 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use crate::authentication::AdminUser;
+use crate::email_client::EmailClient;
+use crate::sequences::{
+    delete_sequence, delete_step, fetch_sequence, list_sequences, parse_sequence_name, save_sequence, save_step,
+    send_due_steps, StepContent,
+};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+
+#[derive(serde::Deserialize)]
+pub struct SequenceBody {
+    #[serde(default = "active_by_default")]
+    active: bool,
+}
+
+fn active_by_default() -> bool {
+    true
+}
+
+#[derive(serde::Deserialize)]
+pub struct StepBody {
+    subject: String,
+    text: String,
+    html: String,
+}
+
+#[tracing::instrument(name = "list sequences", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn sequence_list(pool: web::Data<PgPool>, admin: AdminUser) -> HttpResponse {
+    match list_sequences(&pool).await {
+        Ok(sequences) => HttpResponse::Ok().json(sequences),
+        Err(e) => {
+            tracing::error!("failed to list sequences. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(name = "get a sequence", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn sequence_details(name: web::Path<String>, pool: web::Data<PgPool>, admin: AdminUser) -> HttpResponse {
+    match fetch_sequence(&pool, &name).await {
+        Ok(Some(sequence)) => HttpResponse::Ok().json(sequence),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("failed to fetch the sequence. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(name = "save a sequence", skip(body, pool, admin), fields(admin = %admin.username))]
+pub async fn put_sequence(
+    name: web::Path<String>,
+    body: web::Json<SequenceBody>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> HttpResponse {
+    let name = match parse_sequence_name(&name) {
+        Ok(name) => name,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    match save_sequence(&pool, &name, body.active).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            tracing::error!("failed to save the sequence. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(name = "delete a sequence", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn remove_sequence(name: web::Path<String>, pool: web::Data<PgPool>, admin: AdminUser) -> HttpResponse {
+    match delete_sequence(&pool, &name).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("failed to delete the sequence. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(name = "save a sequence step", skip(body, pool, admin), fields(admin = %admin.username))]
+pub async fn put_sequence_step(
+    path: web::Path<(String, i32)>,
+    body: web::Json<StepBody>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> HttpResponse {
+    let (name, day) = path.into_inner();
+
+    if day < 0 {
+        return HttpResponse::BadRequest().body("steps are sent 0 or more days after the enrollment");
+    }
+    if body.subject.trim().is_empty() {
+        return HttpResponse::BadRequest().body("steps need a subject");
+    }
+
+    let content = StepContent { subject: body.subject.trim(), text: &body.text, html: &body.html };
+
+    match save_step(&pool, &name, day, &content).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("failed to save the sequence step. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(name = "delete a sequence step", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn remove_sequence_step(
+    path: web::Path<(String, i32)>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> HttpResponse {
+    let (name, day) = path.into_inner();
+
+    match delete_step(&pool, &name, day).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("failed to delete the sequence step. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+/// What the scheduler does on every tick, on demand.
+#[tracing::instrument(
+    name = "send due sequence steps",
+    skip(pool, email_client, base_url, hmac_secret, admin),
+    fields(admin = %admin.username)
+)]
+pub async fn run_sequences(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    admin: AdminUser,
+) -> HttpResponse {
+    match send_due_steps(&pool, &email_client, &base_url.0, &hmac_secret.0).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "failed to send due sequence steps");
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
@@ -0,0 +1,186 @@
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse, ResponseError};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::convert::TryInto;
+use std::fmt::Formatter;
+use uuid::Uuid;
+use crate::authentication::AdminUser;
+use crate::configuration::{ReminderSettings, SunsetSettings};
+use crate::consent::fetch_consent;
+use crate::engagement::fetch_engagement;
+use crate::sunset::run_sunset;
+use crate::reminders::send_reminders;
+use crate::digest::send_digests;
+use crate::email_client::EmailClient;
+use crate::export::{stream_csv, ExportFilter};
+use crate::locales::{Locales, DEFAULT_LOCALE};
+use crate::import::{import_subscribers, read_records, ImportOptions, ImportParameters};
+use crate::routes::error_chain_fmt;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+
+#[derive(thiserror::Error)]
+pub enum ImportError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    ServerSideError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self,f)
+    }
+}
+
+impl ResponseError for ImportError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ImportError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ImportError::ServerSideError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[tracing::instrument(name = "export subscribers", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn export_subscribers(
+    filter: web::Query<ExportFilter>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(("Content-Disposition", r#"attachment; filename="subscribers.csv""#))
+        .streaming(stream_csv(pool.get_ref().clone(), filter.into_inner()))
+}
+
+#[tracing::instrument(
+    name = "import subscribers from a file",
+    skip(body, parameters, pool, email_client, base_url, locales, admin),
+    fields(admin = %admin.username)
+)]
+pub async fn import_subscribers_csv(
+    body: web::Bytes,
+    parameters: web::Query<ImportParameters>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    locales: web::Data<Locales>,
+    admin: AdminUser,
+) -> Result<HttpResponse, ImportError> {
+    let options: ImportOptions = parameters.into_inner()
+        .try_into()
+        .map_err(ImportError::ValidationError)?;
+
+    let (records, errors) = read_records(&options.format, body.as_ref()).map_err(ImportError::ValidationError)?;
+
+    let report = import_subscribers(&pool, &email_client, &base_url.0, &locales.messages(DEFAULT_LOCALE), records, errors, &options)
+        .await
+        .context("failed to import subscribers")?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[tracing::instrument(name = "get the consent record of a subscriber", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn subscriber_consent(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> HttpResponse {
+    match fetch_consent(&pool, subscriber_id.into_inner()).await {
+        Ok(Some(consent)) => HttpResponse::Ok().json(consent),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("failed to fetch the consent record. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(name = "get the engagement of a subscriber", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn subscriber_engagement(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> HttpResponse {
+    match fetch_engagement(&pool, subscriber_id.into_inner()).await {
+        Ok(Some(engagement)) => HttpResponse::Ok().json(engagement),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("failed to compute the engagement. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct SunsetParameters {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[tracing::instrument(
+    name = "apply the sunset policy",
+    skip(parameters, pool, email_client, base_url, hmac_secret, policy, admin),
+    fields(admin = %admin.username)
+)]
+pub async fn sunset_subscribers(
+    parameters: web::Query<SunsetParameters>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    policy: web::Data<SunsetSettings>,
+    admin: AdminUser,
+) -> HttpResponse {
+    match run_sunset(&pool, &email_client, &base_url.0, &hmac_secret.0, &policy, parameters.dry_run).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "failed to apply the sunset policy");
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(
+    name = "send confirmation reminders",
+    skip(pool, email_client, base_url, locales, policy, admin),
+    fields(admin = %admin.username)
+)]
+pub async fn remind_pending_subscribers(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    locales: web::Data<Locales>,
+    policy: web::Data<ReminderSettings>,
+    admin: AdminUser,
+) -> HttpResponse {
+    match send_reminders(&pool, &email_client, &base_url.0, &locales, &policy).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "failed to send confirmation reminders");
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(
+    name = "send the weekly digest",
+    skip(pool, email_client, base_url, hmac_secret, admin),
+    fields(admin = %admin.username)
+)]
+pub async fn send_weekly_digest(
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+    admin: AdminUser,
+) -> HttpResponse {
+    match send_digests(&pool, &email_client, &base_url.0, &hmac_secret.0).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "failed to send the weekly digest");
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
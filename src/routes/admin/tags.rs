@@ -0,0 +1,127 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::authentication::AdminUser;
+use crate::tags::{add_tag, count_tags, delete_topic, fetch_tags, list_topics, parse_tag, remove_tag, save_topic};
+
+#[tracing::instrument(name = "list tags", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn list_tags(pool: web::Data<PgPool>, admin: AdminUser) -> HttpResponse {
+    match count_tags(&pool).await {
+        Ok(tags) => HttpResponse::Ok().json(tags),
+        Err(e) => {
+            tracing::error!("failed to count tags. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(name = "get the tags of a subscriber", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn subscriber_tags(
+    subscriber_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> HttpResponse {
+    match fetch_tags(&pool, subscriber_id.into_inner()).await {
+        Ok(tags) => HttpResponse::Ok().json(tags),
+        Err(e) => {
+            tracing::error!("failed to fetch tags. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(name = "tag a subscriber", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn tag_subscriber(
+    path: web::Path<(Uuid, String)>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> HttpResponse {
+    let (subscriber_id, tag) = path.into_inner();
+
+    let tag = match parse_tag(&tag) {
+        Ok(tag) => tag,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    match add_tag(&pool, subscriber_id, &tag).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("failed to tag a subscriber. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(name = "untag a subscriber", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn untag_subscriber(
+    path: web::Path<(Uuid, String)>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> HttpResponse {
+    let (subscriber_id, tag) = path.into_inner();
+    let tag = tag.to_lowercase();
+
+    match remove_tag(&pool, subscriber_id, &tag).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("failed to untag a subscriber. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct TopicBody {
+    label: String,
+}
+
+#[tracing::instrument(name = "list topics", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn topic_list(pool: web::Data<PgPool>, admin: AdminUser) -> HttpResponse {
+    match list_topics(&pool).await {
+        Ok(topics) => HttpResponse::Ok().json(topics),
+        Err(e) => {
+            tracing::error!("failed to list topics. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(name = "save a topic", skip(body, pool, admin), fields(admin = %admin.username))]
+pub async fn put_topic(
+    tag: web::Path<String>,
+    body: web::Json<TopicBody>,
+    pool: web::Data<PgPool>,
+    admin: AdminUser,
+) -> HttpResponse {
+    let tag = match parse_tag(&tag) {
+        Ok(tag) => tag,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let label = body.label.trim();
+    if label.is_empty() {
+        return HttpResponse::BadRequest().body("topics need a label");
+    }
+
+    match save_topic(&pool, &tag, label).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(e) => {
+            tracing::error!("failed to save a topic. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(name = "delete a topic", skip(pool, admin), fields(admin = %admin.username))]
+pub async fn remove_topic(tag: web::Path<String>, pool: web::Data<PgPool>, admin: AdminUser) -> HttpResponse {
+    match delete_topic(&pool, &tag.to_lowercase()).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("failed to delete a topic. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
@@ -0,0 +1,96 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use crate::archive::{description, fetch_public_issue, list_public_issues, public_html, PublicIssue};
+use crate::merge::html_escape;
+use crate::startup::ApplicationBaseUrl;
+
+/// Attribute values are quoted with `"`, which `html_escape` leaves alone.
+fn attribute_escape(s: &str) -> String {
+    html_escape(s).replace('"', "&quot;")
+}
+
+#[tracing::instrument(name = "show the archive", skip(pool))]
+pub async fn archive_index(pool: web::Data<PgPool>) -> HttpResponse {
+    let issues = match list_public_issues(&pool).await {
+        Ok(issues) => issues,
+        Err(e) => {
+            tracing::error!("failed to list archived issues. {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        },
+    };
+
+    let items: String = issues
+        .iter()
+        .map(|issue| format!(
+            r#"<li><a href="/archive/{}">{}</a> <time datetime="{}">{}</time></li>"#,
+            attribute_escape(&issue.slug),
+            html_escape(&issue.title),
+            issue.published_at.to_rfc3339(),
+            issue.published_at.format("%B %-d, %Y")
+        ))
+        .collect();
+
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\" /><title>Archive</title></head>\
+            <body><h1>Archive</h1><ul>{}</ul></body></html>",
+            items
+        ))
+}
+
+#[tracing::instrument(name = "show an archived issue", skip(pool, base_url))]
+pub async fn archive_issue(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+) -> HttpResponse {
+    match fetch_public_issue(&pool, &slug).await {
+        Ok(Some(issue)) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(render_issue(&issue, &base_url.0)),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("failed to fetch the archived issue. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+/// The OpenGraph and Twitter card tags make shared links preview with the
+/// title and the start of the issue.
+fn render_issue(issue: &PublicIssue, base_url: &str) -> String {
+    let title = attribute_escape(&issue.title);
+    let description = attribute_escape(&description(&issue.text));
+    let url = attribute_escape(&format!("{}/archive/{}", base_url, issue.slug));
+
+    format!(
+        r#"<!DOCTYPE html>
+<html><head>
+<meta charset="utf-8" />
+<title>{title}</title>
+<meta name="description" content="{description}" />
+<link rel="canonical" href="{url}" />
+<meta property="og:type" content="article" />
+<meta property="og:title" content="{title}" />
+<meta property="og:description" content="{description}" />
+<meta property="og:url" content="{url}" />
+<meta property="article:published_time" content="{published_at}" />
+<meta name="twitter:card" content="summary" />
+<meta name="twitter:title" content="{title}" />
+<meta name="twitter:description" content="{description}" />
+</head><body>
+<p><a href="/archive">Archive</a></p>
+<h1>{heading}</h1>
+<p><time datetime="{published_at}">{date}</time></p>
+{body}
+</body></html>"#,
+        title = title,
+        description = description,
+        url = url,
+        published_at = issue.published_at.to_rfc3339(),
+        heading = html_escape(&issue.title),
+        date = issue.published_at.format("%B %-d, %Y"),
+        body = public_html(&issue.html, base_url),
+    )
+}
@@ -0,0 +1,90 @@
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use crate::archive::recent_public_issues;
+use crate::feeds::{atom, etag, rss, Feed, FEED_LENGTH};
+use crate::startup::{ApplicationBaseUrl, NewsletterName};
+
+#[tracing::instrument(name = "serve the rss feed", skip(request, pool, base_url, name))]
+pub async fn rss_feed(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    name: web::Data<NewsletterName>,
+) -> HttpResponse {
+    feed_response(&request, &pool, &base_url.0, &name.0, "application/rss+xml; charset=utf-8", rss).await
+}
+
+#[tracing::instrument(name = "serve the atom feed", skip(request, pool, base_url, name))]
+pub async fn atom_feed(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    name: web::Data<NewsletterName>,
+) -> HttpResponse {
+    feed_response(&request, &pool, &base_url.0, &name.0, "application/atom+xml; charset=utf-8", atom).await
+}
+
+async fn feed_response(
+    request: &HttpRequest,
+    pool: &PgPool,
+    base_url: &str,
+    title: &str,
+    content_type: &str,
+    render: fn(&Feed) -> String,
+) -> HttpResponse {
+    let issues = match recent_public_issues(pool, FEED_LENGTH).await {
+        Ok(issues) => issues,
+        Err(e) => {
+            tracing::error!("failed to fetch the issues of the feed. {:?}", e);
+            return HttpResponse::InternalServerError().finish();
+        },
+    };
+
+    let feed = Feed { title, base_url, issues: &issues };
+    let body = render(&feed);
+    let etag = etag(&body);
+    let last_modified = feed.last_modified();
+
+    let fresh = is_fresh(request, &etag, last_modified);
+
+    let mut response = if fresh { HttpResponse::NotModified() } else { HttpResponse::Ok() };
+
+    response.insert_header((header::ETAG, etag));
+    if let Some(last_modified) = last_modified {
+        response.insert_header((header::LAST_MODIFIED, http_date(last_modified)));
+    }
+
+    if fresh {
+        return response.finish();
+    }
+
+    response.content_type(content_type).body(body)
+}
+
+fn http_date(date: DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Whether the copy the client has is still current. `If-None-Match` wins
+/// over `If-Modified-Since` when both are sent.
+fn is_fresh(request: &HttpRequest, etag: &str, last_modified: Option<DateTime<Utc>>) -> bool {
+    let header = |name| request.headers().get(name).and_then(|value: &HeaderValue| value.to_str().ok());
+
+    if let Some(if_none_match) = header(header::IF_NONE_MATCH) {
+        return if_none_match
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == etag || tag == "*");
+    }
+
+    match (header(header::IF_MODIFIED_SINCE), last_modified) {
+        (Some(since), Some(last_modified)) => match DateTime::parse_from_rfc2822(since) {
+            // http dates have no sub-second part
+            Ok(since) => last_modified.timestamp() <= since.timestamp(),
+            Err(_) => false,
+        },
+        _ => false,
+    }
+}
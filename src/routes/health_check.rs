@@ -1,5 +1,40 @@
-use actix_web::HttpResponse;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use crate::circuit_breaker::CircuitState;
+use crate::email_client::EmailClient;
+use crate::outbox::queued_emails;
 
 pub async fn health_check() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+#[derive(serde::Serialize)]
+struct Readiness {
+    database: &'static str,
+    email_provider: CircuitState,
+    /// Emails waiting in the outbox for the circuit to close.
+    queued_emails: Option<i64>,
+}
+
+/// Ready when the database answers. An open circuit to the email provider is
+/// reported but does not make the application unready: signups still work,
+/// and their emails wait in the outbox.
+pub async fn readiness(pool: web::Data<PgPool>, email_client: web::Data<EmailClient>) -> HttpResponse {
+    let email_provider = email_client.circuit_breaker().state();
+
+    match queued_emails(&pool).await {
+        Ok(queued) => HttpResponse::Ok().json(Readiness {
+            database: "up",
+            email_provider,
+            queued_emails: Some(queued),
+        }),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "the database is not ready");
+            HttpResponse::ServiceUnavailable().json(Readiness {
+                database: "down",
+                email_provider,
+                queued_emails: None,
+            })
+        },
+    }
+}
@@ -0,0 +1,78 @@
+use actix_web::{web, HttpResponse};
+use std::fmt::Write;
+use crate::email_client::EmailClient;
+use crate::circuit_breaker::CircuitState;
+use crate::throttle::BucketState;
+
+/// The state of the application, in the Prometheus text format.
+pub async fn metrics(email_client: web::Data<EmailClient>) -> HttpResponse {
+    let throttle = email_client.throttle().state();
+    let circuit = email_client.circuit_breaker().state();
+    let buckets = [("second", &throttle.per_second), ("hour", &throttle.per_hour)];
+    let mut body = String::new();
+
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+        let _ = writeln!(body, "# HELP {} {}", name, help);
+        let _ = writeln!(body, "# TYPE {} {}", name, kind);
+        for (labels, value) in samples {
+            let _ = writeln!(body, "{}{} {}", name, labels, value);
+        }
+    };
+
+    let per_window = |value: fn(&BucketState) -> String| -> Vec<(String, String)> {
+        buckets
+            .iter()
+            .filter_map(|(window, bucket)| bucket.as_ref().map(|bucket| (format!("{{window=\"{}\"}}", window), value(bucket))))
+            .collect()
+    };
+
+    metric(
+        "email_throttle_limit",
+        "gauge",
+        "Messages the email provider takes per window.",
+        &per_window(|bucket| bucket.limit.to_string()),
+    );
+    metric(
+        "email_throttle_tokens",
+        "gauge",
+        "Messages that can go out now without waiting, per window. Negative after a large batch.",
+        &per_window(|bucket| format!("{:.3}", bucket.tokens)),
+    );
+    metric(
+        "email_throttle_paused_seconds",
+        "gauge",
+        "How much longer sends are paused because the email provider rate limited us.",
+        &[(String::new(), format!("{:.3}", throttle.paused_for.as_secs_f64()))],
+    );
+    metric(
+        "email_throttle_waits_total",
+        "counter",
+        "Sends that waited for the throttle.",
+        &[(String::new(), throttle.waits.to_string())],
+    );
+    metric(
+        "email_provider_rate_limited_total",
+        "counter",
+        "Responses of the email provider with status 429.",
+        &[(String::new(), throttle.rate_limited.to_string())],
+    );
+
+    let current = match circuit {
+        CircuitState::Closed { .. } => "closed",
+        CircuitState::Open { .. } => "open",
+        CircuitState::HalfOpen => "half_open",
+    };
+    metric(
+        "email_circuit_state",
+        "gauge",
+        "The state of the circuit breaker in front of the email provider.",
+        &["closed", "open", "half_open"]
+            .iter()
+            .map(|state| (format!("{{state=\"{}\"}}", state), u8::from(*state == current).to_string()))
+            .collect::<Vec<_>>(),
+    );
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
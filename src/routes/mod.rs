@@ -1,9 +1,29 @@
 mod health_check;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_stay;
+mod subscriptions_email;
 mod newsletters;
+mod tracking;
+mod unsubscribe;
+mod preferences;
+mod archive;
+mod feeds;
+mod metrics;
+mod webhooks;
+mod admin;
 
 pub use health_check::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
-pub use newsletters::*;
\ No newline at end of file
+pub use subscriptions_stay::*;
+pub use subscriptions_email::*;
+pub use newsletters::*;
+pub use tracking::*;
+pub use unsubscribe::*;
+pub use preferences::*;
+pub use archive::*;
+pub use feeds::*;
+pub use metrics::*;
+pub use webhooks::*;
+pub use admin::*;
\ No newline at end of file
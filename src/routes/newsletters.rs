@@ -1,19 +1,83 @@
 use actix_web::{HttpResponse, ResponseError};
 use actix_web::web;
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
 use crate::routes::error_chain_fmt;
 use actix_web::http::StatusCode;
 use std::fmt::Formatter;
-use actix_web::body::BoxBody;
-use std::any::TypeId;
-use crate::email_client::EmailClient;
+use crate::email_client::{Email, EmailClient, Header, MAX_BATCH_SIZE};
 use anyhow::Context;
 use crate::domain::SubscriberEmail;
+use crate::routes::generate_subscription_token;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::tracking::{add_footer, inject_pixel, list_unsubscribe_headers, personalize, pixel_url, unsubscribe_url};
+use crate::merge::{render_text, MergeFields};
+use crate::preferences::{preferences_url, Frequency};
+use crate::archive::unique_slug;
+use std::convert::TryFrom;
+use crate::segments::{audience_filter, Audience, SegmentError, SegmentFilter};
+use sqlx::Row;
+use chrono::Utc;
+use serde_json::{Map, Value};
+use uuid::Uuid;
+use futures::{StreamExt, TryStreamExt};
+use std::time::Duration;
+use secrecy::Secret;
+use std::collections::{BTreeMap, HashMap};
+use crate::locales::parse_locale;
 
 #[derive(serde::Deserialize)]
 pub struct BodyData {
     title: String,
-    content: Content
+    content: Content,
+    /// Privacy-sensitive lists can turn the open tracking pixel off.
+    #[serde(default = "default_track_opens")]
+    track_opens: bool,
+    /// Private issues are left out of the public archive.
+    #[serde(default = "default_public")]
+    public: bool,
+    /// Also send to subscribers the sunset policy moved to `inactive`.
+    #[serde(default)]
+    include_inactive: bool,
+    /// The name of a saved segment to send to.
+    segment: Option<String>,
+    /// Ad-hoc segment expressions the recipients must, or must not, match.
+    include: Option<String>,
+    exclude: Option<String>,
+    /// The issue in other languages, keyed by locale. Subscribers whose
+    /// locale has no variant get the title and content above.
+    #[serde(default)]
+    variants: HashMap<String, Variant>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Variant {
+    title: String,
+    content: Content,
+}
+
+impl BodyData {
+    /// An issue for every confirmed subscriber, with the defaults of the api.
+    pub fn new(title: String, text: String, html: String) -> Self {
+        Self {
+            title,
+            content: Content { html, text },
+            track_opens: default_track_opens(),
+            public: default_public(),
+            include_inactive: false,
+            segment: None,
+            include: None,
+            exclude: None,
+            variants: HashMap::new(),
+        }
+    }
+}
+
+fn default_track_opens() -> bool {
+    true
+}
+
+fn default_public() -> bool {
+    true
 }
 
 #[derive(serde::Deserialize)]
@@ -23,11 +87,54 @@ pub struct Content {
 }
 
 struct ConfirmedSubscriber {
-    email: SubscriberEmail
+    id: Uuid,
+    email: SubscriberEmail,
+    name: String,
+    attributes: Map<String, Value>,
+    frequency: Frequency,
+    locale: String
+}
+
+/// The variants of an issue, keyed by normalised locale. Every variant, the
+/// default one included, needs a title, a text and an html body.
+fn parse_variants(body: &BodyData) -> Result<BTreeMap<String, &Variant>, String> {
+    let is_blank = |title: &str, content: &Content| {
+        title.trim().is_empty() || content.text.trim().is_empty() || content.html.trim().is_empty()
+    };
+
+    if is_blank(&body.title, &body.content) {
+        return Err("the issue needs a title, a text and an html body".into());
+    }
+
+    let mut variants = BTreeMap::new();
+
+    for (locale, variant) in &body.variants {
+        let locale = parse_locale(locale)?;
+
+        if is_blank(&variant.title, &variant.content) {
+            return Err(format!("the {} variant needs a title, a text and an html body", locale));
+        }
+        if variants.insert(locale.clone(), variant).is_some() {
+            return Err(format!("there is more than one {} variant", locale));
+        }
+    }
+
+    Ok(variants)
+}
+
+/// The variant for a locale, or for its language (`fr` for `fr-ca`).
+fn choose_variant<'a>(variants: &BTreeMap<String, &'a Variant>, locale: &str) -> Option<(String, &'a Variant)> {
+    let language = locale.split('-').next().unwrap_or(locale);
+
+    [locale, language]
+        .into_iter()
+        .find_map(|candidate| variants.get(candidate).map(|variant| (candidate.to_string(), *variant)))
 }
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
+    #[error("{0}")]
+    ValidationError(String),
     #[error(transparent)]
     ServerSideError(#[from] anyhow::Error)
 }
@@ -41,23 +148,43 @@ impl std::fmt::Debug for PublishError {
 impl ResponseError for PublishError {
     fn status_code(&self) -> StatusCode {
         match self {
+            PublishError::ValidationError(_) => StatusCode::BAD_REQUEST,
             PublishError::ServerSideError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
 
-#[tracing::instrument(name = "get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(pool: &PgPool)
+impl From<SegmentError> for PublishError {
+    fn from(e: SegmentError) -> Self {
+        match e {
+            SegmentError::Invalid(message) => Self::ValidationError(message),
+            SegmentError::Unexpected(e) => Self::ServerSideError(anyhow::Error::new(e).context("failed to build the audience")),
+        }
+    }
+}
+
+#[tracing::instrument(name = "get confirmed subscribers", skip(pool, filter))]
+async fn get_confirmed_subscribers(pool: &PgPool, filter: &SegmentFilter)
     -> Result<Vec<Result<ConfirmedSubscriber,anyhow::Error>>,anyhow::Error> {
 
-    let confirmed_subscribers = sqlx::query!(
-            r#"SELECT email FROM subscriptions WHERE status = 'confirmed'"#
-        )
+    let sql = format!("SELECT s.id, s.email, s.name, s.attributes, s.frequency, s.locale FROM subscriptions s WHERE {}", filter.where_clause());
+
+    let confirmed_subscribers = filter.bind(sqlx::query(&sql))
         .fetch_all(pool)
         .await?
         .into_iter()
-        .map(|r| match SubscriberEmail::parse(r.email) {
-            Ok(email) => Ok(ConfirmedSubscriber {email}),
+        .map(|r| match SubscriberEmail::parse(r.try_get("email")?) {
+            Ok(email) => Ok(ConfirmedSubscriber {
+                id: r.try_get("id")?,
+                email,
+                name: r.try_get("name")?,
+                attributes: match r.try_get("attributes")? {
+                    Value::Object(attributes) => attributes,
+                    _ => Map::new(),
+                },
+                frequency: Frequency::try_from(r.try_get::<&str, _>("frequency")?).map_err(anyhow::Error::msg)?,
+                locale: r.try_get("locale")?
+            }),
             Err(err) => Err(anyhow::anyhow!(err))
         })
         .collect();
@@ -65,37 +192,424 @@ async fn get_confirmed_subscribers(pool: &PgPool)
     Ok(confirmed_subscribers)
 }
 
-#[tracing::instrument(name = "publish newsletter",  skip(body,pool,email_client))]
+#[tracing::instrument(name = "store newsletter issue", skip(transaction, pool, body))]
+async fn insert_newsletter_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    pool: &PgPool,
+    body: &BodyData,
+) -> Result<(Uuid, String), sqlx::Error> {
+    let issue_id = Uuid::new_v4();
+    let slug = unique_slug(pool, &body.title, issue_id).await?;
+
+    sqlx::query!(
+            r#"
+                INSERT INTO newsletter_issues (id, title, text_content, html_content, track_opens, published_at, slug, public)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+            issue_id, body.title, body.content.text, body.content.html, body.track_opens, Utc::now(), slug, body.public
+        )
+        .execute(&mut *transaction)
+        .await?;
+
+    Ok((issue_id, slug))
+}
+
+#[tracing::instrument(name = "store newsletter issue variants", skip(transaction, variants))]
+async fn insert_variants(
+    transaction: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    variants: &BTreeMap<String, &Variant>,
+) -> Result<(), sqlx::Error> {
+    for (locale, variant) in variants {
+        sqlx::query!(
+                r#"
+                    INSERT INTO newsletter_issue_variants (newsletter_issue_id, locale, title, text_content, html_content)
+                    VALUES ($1, $2, $3, $4, $5)
+                "#,
+                issue_id, locale, variant.title, variant.content.text, variant.content.html
+            )
+            .execute(&mut *transaction)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Every recipient gets a delivery with its own token, which the tracking
+/// urls in their copy of the issue point to. `locale` is the variant they
+/// get, if not the default one.
+#[tracing::instrument(name = "store issue delivery", skip(pool))]
+async fn insert_delivery(
+    pool: &PgPool,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    digest: bool,
+    locale: Option<&str>,
+) -> Result<(Uuid, String), sqlx::Error> {
+    let delivery_id = Uuid::new_v4();
+    let token = generate_subscription_token();
+
+    sqlx::query!(
+            r#"
+                INSERT INTO issue_deliveries (id, newsletter_issue_id, subscriber_id, token, digest, locale)
+                VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+            delivery_id, issue_id, subscriber_id, token, digest, locale
+        )
+        .execute(pool)
+        .await?;
+
+    Ok((delivery_id, token))
+}
+
+#[tracing::instrument(name = "mark issue deliveries as sent", skip(pool))]
+async fn mark_deliveries_sent(pool: &PgPool, delivery_ids: &[Uuid]) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+            r#"UPDATE issue_deliveries SET sent_at = $2 WHERE id = ANY($1)"#,
+            delivery_ids, Utc::now()
+        )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "mark issue deliveries as failed", skip(pool))]
+async fn mark_deliveries_failed(pool: &PgPool, delivery_ids: &[Uuid]) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+            r#"UPDATE issue_deliveries SET failed_at = $2 WHERE id = ANY($1)"#,
+            delivery_ids, Utc::now()
+        )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "publish newsletter",  skip(body,pool,email_client,base_url,hmac_secret))]
 pub async fn publish_newsletter(
     body: web::Json<BodyData>,
     pool: web::Data<PgPool>,
-    email_client: web::Data<EmailClient>
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>
 ) -> Result<HttpResponse,PublishError> {
-    let subscribers = get_confirmed_subscribers(&pool).await?;
+    let report = publish_issue(&pool, &email_client, &base_url.0, &hmac_secret.0, &body).await?;
 
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text
-                    )
-                    .await
-                    .with_context(|| format!("failed to send newsletter issue to {}",subscriber.email))?;
-            },
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// What happened to an issue: where it is archived, and how many copies went
+/// out, failed or wait for the weekly digest.
+#[derive(serde::Serialize, Debug)]
+pub struct PublishReport {
+    pub issue_id: Uuid,
+    pub slug: String,
+    pub sent: usize,
+    pub failed: usize,
+    pub queued: usize,
+}
+
+/// An issue on its way out, and what every copy of it needs.
+struct Publication<'a> {
+    issue_id: Uuid,
+    body: &'a BodyData,
+    variants: &'a BTreeMap<String, &'a Variant>,
+    base_url: &'a str,
+    hmac_secret: &'a Secret<String>,
+}
+
+/// How many copies went out, failed or wait for the weekly digest.
+#[derive(Default, Clone, Copy)]
+struct Tally {
+    sent: usize,
+    failed: usize,
+    queued: usize,
+}
+
+impl std::ops::Add for Tally {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            sent: self.sent + other.sent,
+            failed: self.failed + other.failed,
+            queued: self.queued + other.queued,
+        }
+    }
+}
+
+/// One subscriber's copy of the issue, ready to send.
+struct IssueCopy<'a> {
+    delivery_id: Uuid,
+    recipient: &'a SubscriberEmail,
+    subject: String,
+    html: String,
+    text: String,
+    headers: Vec<Header>,
+}
+
+impl IssueCopy<'_> {
+    fn email(&self) -> Email<'_> {
+        Email {
+            recipient: self.recipient,
+            subject: &self.subject,
+            html_body: &self.html,
+            text_body: &self.text,
+            headers: &self.headers,
+        }
+    }
+}
+
+/// Sends in batches try a copy this many times. Only the copies that failed
+/// for a transient reason are sent again.
+const MAX_BATCH_ATTEMPTS: u32 = 3;
+
+/// The wait before the first retry of a batch, which grows with each attempt.
+const BATCH_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Stores the issue and sends it to its audience, `email_client.concurrency()`
+/// copies (or batches, if the provider takes batches) at a time. A copy the
+/// provider refuses is marked as failed and the others still go out; only a
+/// database error stops the send.
+pub async fn publish_issue(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    body: &BodyData,
+) -> Result<PublishReport, PublishError> {
+    let transaction = pool.begin().await.context("failed to start a transaction")?;
+
+    publish_issue_in(transaction, pool, email_client, base_url, hmac_secret, body).await
+}
+
+/// Like `publish_issue`, but stores the issue in `transaction`, which is
+/// committed with it before the issue is sent. When the issue is rejected or
+/// cannot be stored, whatever else was done in the transaction is undone too.
+pub async fn publish_issue_in(
+    mut transaction: Transaction<'static, Postgres>,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    body: &BodyData,
+) -> Result<PublishReport, PublishError> {
+    let audience = Audience {
+        segment: body.segment.as_deref(),
+        include: body.include.as_deref(),
+        exclude: body.exclude.as_deref(),
+        include_inactive: body.include_inactive,
+    };
+
+    // an invalid issue or audience is rejected before anything is stored
+    let variants = parse_variants(body).map_err(PublishError::ValidationError)?;
+    let filter = audience_filter(pool, &audience).await?;
+
+    let (issue_id, slug) = insert_newsletter_issue(&mut transaction, pool, body)
+        .await
+        .context("failed to store newsletter issue")?;
+
+    insert_variants(&mut transaction, issue_id, &variants)
+        .await
+        .context("failed to store the variants of the issue")?;
+
+    transaction.commit().await.context("failed to commit the newsletter issue")?;
+
+    let subscribers: Vec<ConfirmedSubscriber> = get_confirmed_subscribers(pool, &filter)
+        .await?
+        .into_iter()
+        .filter_map(|subscriber| match subscriber {
+            Ok(subscriber) => Some(subscriber),
             Err(err) => {
                 tracing::warn!(
                     err.cause_chain = ?err,
                     "Skipping a confirmed subscriber. \
                     There stored contact details (email) is invalid."
-                )
+                );
+                None
             }
-        }
+        })
+        .collect();
+
+    let publication = Publication { issue_id, body, variants: &variants, base_url, hmac_secret };
+    let add = |total: Tally, tally: Tally| async move { Ok(total + tally) };
+
+    let tally = if email_client.supports_batch() {
+        futures::stream::iter(subscribers.chunks(MAX_BATCH_SIZE))
+            .map(|chunk| deliver_batch(pool, email_client, &publication, chunk))
+            .buffer_unordered(email_client.concurrency())
+            .try_fold(Tally::default(), add)
+            .await?
+    } else {
+        futures::stream::iter(&subscribers)
+            .map(|subscriber| deliver(pool, email_client, &publication, subscriber))
+            .buffer_unordered(email_client.concurrency())
+            .try_fold(Tally::default(), add)
+            .await?
+    };
+
+    Ok(PublishReport { issue_id, slug, sent: tally.sent, failed: tally.failed, queued: tally.queued })
+}
+
+/// Stores the delivery of one copy and writes the copy, or returns `None`
+/// when the subscriber reads the weekly digest.
+async fn prepare<'a>(
+    pool: &PgPool,
+    publication: &Publication<'_>,
+    subscriber: &'a ConfirmedSubscriber,
+) -> Result<Option<IssueCopy<'a>>, anyhow::Error> {
+    let Publication { issue_id, body, variants, base_url, hmac_secret } = *publication;
+
+    // weekly digest subscribers get the issue with the next digest
+    let digest = subscriber.frequency == Frequency::WeeklyDigest;
+
+    let variant = choose_variant(variants, &subscriber.locale);
+    let locale = variant.as_ref().map(|(locale, _)| locale.as_str());
+    let (title, content) = match &variant {
+        Some((_, variant)) => (&variant.title, &variant.content),
+        None => (&body.title, &body.content),
+    };
+
+    let (delivery_id, token) = insert_delivery(pool, issue_id, subscriber.id, digest, locale)
+        .await
+        .context("failed to store issue delivery")?;
+
+    if digest {
+        return Ok(None);
     }
 
-    Ok(HttpResponse::Ok().finish())
+    let fields = MergeFields {
+        name: &subscriber.name,
+        email: subscriber.email.as_ref(),
+        attributes: &subscriber.attributes
+    };
+
+    let (html, text) = personalize(
+        &content.html,
+        &content.text,
+        &fields,
+        base_url,
+        hmac_secret,
+        &token
+    );
+
+    let unsubscribe_url = unsubscribe_url(base_url, &token);
+    let (mut html, text) = add_footer(
+        &html,
+        &text,
+        &unsubscribe_url,
+        &preferences_url(base_url, hmac_secret, subscriber.id)
+    );
+
+    if body.track_opens {
+        html = inject_pixel(&html, &pixel_url(base_url, &token));
+    }
+
+    Ok(Some(IssueCopy {
+        delivery_id,
+        recipient: &subscriber.email,
+        subject: render_text(title, &fields),
+        html,
+        text,
+        headers: list_unsubscribe_headers(&unsubscribe_url),
+    }))
 }
 
+/// Sends one copy on its own.
+async fn deliver(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    publication: &Publication<'_>,
+    subscriber: &ConfirmedSubscriber,
+) -> Result<Tally, anyhow::Error> {
+    let copy = match prepare(pool, publication, subscriber).await? {
+        Some(copy) => copy,
+        None => return Ok(Tally { queued: 1, ..Tally::default() }),
+    };
+
+    let sent = email_client.send(&copy.email()).await;
+
+    if let Err(e) = sent {
+        tracing::warn!(
+            error.cause_chain = ?e,
+            "failed to send newsletter issue to {}", copy.recipient
+        );
+
+        mark_deliveries_failed(pool, &[copy.delivery_id])
+            .await
+            .context("failed to mark issue delivery as failed")?;
+
+        return Ok(Tally { failed: 1, ..Tally::default() });
+    }
+
+    mark_deliveries_sent(pool, &[copy.delivery_id])
+        .await
+        .context("failed to mark issue delivery as sent")?;
+
+    Ok(Tally { sent: 1, ..Tally::default() })
+}
+
+/// Sends the copies of up to `MAX_BATCH_SIZE` subscribers in one batch, then
+/// sends the copies that failed for a transient reason again, up to
+/// `MAX_BATCH_ATTEMPTS` times. Refused copies are marked as failed at once.
+async fn deliver_batch(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    publication: &Publication<'_>,
+    subscribers: &[ConfirmedSubscriber],
+) -> Result<Tally, anyhow::Error> {
+    let mut tally = Tally::default();
+    let mut copies = Vec::with_capacity(subscribers.len());
+
+    for subscriber in subscribers {
+        match prepare(pool, publication, subscriber).await? {
+            Some(copy) => copies.push(copy),
+            None => tally.queued += 1,
+        }
+    }
+
+    let mut attempt = 1;
+
+    while !copies.is_empty() {
+        let emails: Vec<Email> = copies.iter().map(IssueCopy::email).collect();
+        let outcomes = email_client.send_batch(&emails).await;
+
+        let mut sent = Vec::new();
+        let mut failed = Vec::new();
+        let mut retries = Vec::new();
+
+        for (copy, outcome) in copies.into_iter().zip(outcomes) {
+            match outcome {
+                Ok(()) => sent.push(copy.delivery_id),
+                Err(e) if e.is_transient() && attempt < MAX_BATCH_ATTEMPTS => {
+                    tracing::info!(error.cause_chain = ?e, attempt, "failed to send newsletter issue to {}, will retry", copy.recipient);
+                    retries.push(copy);
+                },
+                Err(e) => {
+                    tracing::warn!(error.cause_chain = ?e, "failed to send newsletter issue to {}", copy.recipient);
+                    failed.push(copy.delivery_id);
+                },
+            }
+        }
+
+        mark_deliveries_sent(pool, &sent)
+            .await
+            .context("failed to mark issue deliveries as sent")?;
+        mark_deliveries_failed(pool, &failed)
+            .await
+            .context("failed to mark issue deliveries as failed")?;
+
+        tally.sent += sent.len();
+        tally.failed += failed.len();
+
+        if !retries.is_empty() {
+            tokio::time::sleep(BATCH_RETRY_DELAY * attempt).await;
+        }
+
+        copies = retries;
+        attempt += 1;
+    }
+
+    Ok(tally)
+}
@@ -0,0 +1,248 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use uuid::Uuid;
+use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::email_change::{request_email_change, send_email_change_confirmation, EmailChangeError};
+use crate::email_client::EmailClient;
+use crate::merge::html_escape;
+use crate::preferences::{
+    chosen_topics, fetch_preferences, pause, update_preferences, verify_preferences, Frequency, PreferenceUpdate,
+    Preferences, PAUSE_DAYS,
+};
+use crate::routes::unsubscribe_subscriber;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+
+#[derive(serde::Deserialize)]
+pub struct PreferencesForm {
+    /// The button that was pressed: `save`, `change_email`, `pause`, `resume` or `unsubscribe`.
+    action: String,
+    name: Option<String>,
+    frequency: Option<String>,
+    email: Option<String>,
+    /// The topic checkboxes.
+    #[serde(flatten)]
+    fields: HashMap<String, String>,
+}
+
+/// What the page says after an update.
+enum Notice {
+    Done(String),
+    Invalid(String),
+    Conflict(String),
+}
+
+#[tracing::instrument(name = "show the preference page", skip(path, pool, hmac_secret))]
+pub async fn preferences_page(
+    path: web::Path<(Uuid, String)>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let (subscriber_id, signature) = path.into_inner();
+
+    if !verify_preferences(&hmac_secret.0, subscriber_id, &signature) {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    match fetch_preferences(&pool, subscriber_id).await {
+        Ok(Some(preferences)) => page(&preferences, None),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("failed to fetch the preferences. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+#[tracing::instrument(name = "update preferences", skip(path, form, pool, email_client, base_url, hmac_secret))]
+pub async fn update_preferences_form(
+    path: web::Path<(Uuid, String)>,
+    form: web::Form<PreferencesForm>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let (subscriber_id, signature) = path.into_inner();
+
+    if !verify_preferences(&hmac_secret.0, subscriber_id, &signature) {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    let result = async {
+        let preferences = match fetch_preferences(&pool, subscriber_id).await.context("failed to fetch the preferences")? {
+            Some(preferences) => preferences,
+            None => return Ok(None),
+        };
+
+        // unsubscribing is final: coming back takes a new, confirmed, signup
+        if preferences.status == "unsubscribed" {
+            return Ok(Some((preferences, Notice::Invalid("You have unsubscribed from this newsletter.".into()))));
+        }
+
+        let notice = apply(&pool, &email_client, &base_url.0, subscriber_id, form.into_inner()).await?;
+
+        let preferences = fetch_preferences(&pool, subscriber_id)
+            .await
+            .context("failed to fetch the preferences")?
+            .context("the subscriber disappeared")?;
+
+        Ok::<_, anyhow::Error>(Some((preferences, notice)))
+    }.await;
+
+    match result {
+        Ok(Some((preferences, notice))) => page(&preferences, Some(notice)),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "failed to update the preferences");
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+async fn apply(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    subscriber_id: Uuid,
+    form: PreferencesForm,
+) -> Result<Notice, anyhow::Error> {
+    match form.action.as_str() {
+        "save" => {
+            let name = match SubscriberName::parse(form.name.unwrap_or_default()) {
+                Ok(name) => name,
+                Err(e) => return Ok(Notice::Invalid(e)),
+            };
+            let frequency = match Frequency::try_from(form.frequency.as_deref().unwrap_or("every_issue")) {
+                Ok(frequency) => frequency,
+                Err(e) => return Ok(Notice::Invalid(e)),
+            };
+            let update = PreferenceUpdate { name, frequency, topics: chosen_topics(&form.fields) };
+
+            update_preferences(pool, subscriber_id, &update)
+                .await
+                .context("failed to update the preferences")?;
+
+            Ok(Notice::Done("Your preferences have been saved.".into()))
+        },
+        "change_email" => {
+            let new_email = match SubscriberEmail::parse(form.email.unwrap_or_default().trim().to_string()) {
+                Ok(email) => email,
+                Err(e) => return Ok(Notice::Invalid(e)),
+            };
+
+            let token = match request_email_change(pool, subscriber_id, &new_email).await {
+                Ok(token) => token,
+                Err(e @ EmailChangeError::Taken(_)) => return Ok(Notice::Conflict(e.to_string())),
+                Err(e) => return Err(anyhow::Error::new(e).context("failed to store the email change")),
+            };
+
+            send_email_change_confirmation(pool, &email_client.interactive(), &new_email, base_url, &token)
+                .await
+                .context("failed to send the email change confirmation")?;
+
+            Ok(Notice::Done(format!(
+                "We sent a confirmation link to {}. Your address changes once you follow it.",
+                new_email
+            )))
+        },
+        "pause" => {
+            let until = Utc::now() + Duration::days(PAUSE_DAYS);
+
+            pause(pool, subscriber_id, Some(until)).await.context("failed to pause the newsletter")?;
+
+            Ok(Notice::Done(format!("The newsletter is paused until {}.", until.format("%B %-d, %Y"))))
+        },
+        "resume" => {
+            pause(pool, subscriber_id, None).await.context("failed to resume the newsletter")?;
+
+            Ok(Notice::Done("The newsletter is no longer paused.".into()))
+        },
+        "unsubscribe" => {
+            let mut transaction = pool.begin().await.context("failed to start a transaction")?;
+            unsubscribe_subscriber(&mut transaction, subscriber_id).await?;
+            transaction.commit().await.context("failed to commit the unsubscribe")?;
+
+            Ok(Notice::Done("You have been unsubscribed from this newsletter.".into()))
+        },
+        other => Ok(Notice::Invalid(format!("{} is not something this page can do.", other))),
+    }
+}
+
+fn page(preferences: &Preferences, notice: Option<Notice>) -> HttpResponse {
+    let (mut response, notice) = match notice {
+        Some(Notice::Invalid(message)) => (HttpResponse::BadRequest(), format!("<p><strong>{}</strong></p>", html_escape(&message))),
+        Some(Notice::Conflict(message)) => (HttpResponse::Conflict(), format!("<p><strong>{}</strong></p>", html_escape(&message))),
+        Some(Notice::Done(message)) => (HttpResponse::Ok(), format!("<p>{}</p>", html_escape(&message))),
+        None => (HttpResponse::Ok(), String::new()),
+    };
+
+    response
+        .content_type("text/html; charset=utf-8")
+        .body(render_page(preferences, &notice))
+}
+
+fn render_page(preferences: &Preferences, notice: &str) -> String {
+    if preferences.status == "unsubscribed" {
+        return format!(
+            "<html><body><h1>Your newsletter preferences</h1>{}<p>{} is unsubscribed from this newsletter.</p></body></html>",
+            notice, html_escape(&preferences.email)
+        );
+    }
+
+    let topics: String = preferences.topics
+        .iter()
+        .map(|topic| format!(
+            r#"<label><input type="checkbox" name="topic_{}"{} /> {}</label><br />"#,
+            html_escape(&topic.tag), if topic.selected { " checked" } else { "" }, html_escape(&topic.label)
+        ))
+        .collect();
+    let topics = if topics.is_empty() {
+        String::new()
+    } else {
+        format!("<fieldset><legend>Topics</legend>{}</fieldset>", topics)
+    };
+
+    let frequency = |value: Frequency, label: &str| format!(
+        r#"<label><input type="radio" name="frequency" value="{}"{} /> {}</label><br />"#,
+        value.as_str(), if preferences.frequency == value { " checked" } else { "" }, label
+    );
+
+    let pause = match preferences.paused_until.filter(|_| preferences.is_paused()) {
+        Some(until) => format!(
+            r#"<p>The newsletter is paused until {}.</p><button name="action" value="resume">Resume now</button>"#,
+            until.format("%B %-d, %Y")
+        ),
+        None => format!(r#"<button name="action" value="pause">Pause for {} days</button>"#, PAUSE_DAYS),
+    };
+
+    format!(
+        r#"<html><body>
+<h1>Your newsletter preferences</h1>
+{notice}
+<p>For {email}</p>
+<form method="post">
+<label>Name <input type="text" name="name" value="{name}" /></label>
+{topics}
+<fieldset><legend>Frequency</legend>{every_issue}{weekly_digest}</fieldset>
+<button name="action" value="save">Save</button>
+</form>
+<form method="post">
+<label>New email address <input type="email" name="email" /></label>
+<button name="action" value="change_email">Change my address</button>
+</form>
+<form method="post">{pause}</form>
+<form method="post"><button name="action" value="unsubscribe">Unsubscribe</button></form>
+</body></html>"#,
+        notice = notice,
+        email = html_escape(&preferences.email),
+        name = html_escape(&preferences.name),
+        topics = topics,
+        every_issue = frequency(Frequency::EveryIssue, "Every issue"),
+        weekly_digest = frequency(Frequency::WeeklyDigest, "A weekly digest"),
+        pause = pause,
+    )
+}
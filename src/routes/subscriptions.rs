@@ -1,15 +1,22 @@
-use actix_web::{HttpResponse, web, ResponseError};
+use actix_web::{HttpRequest, HttpResponse, web, ResponseError};
 use sqlx::{PgPool, Transaction, Postgres};
 use chrono::Utc;
 use uuid::Uuid;
-use crate::{domain::{NewSubscriber, SubscriberName, SubscriberEmail}, email_client::{EmailClient}};
+use crate::{domain::{NewSubscriber, SubscriberName, SubscriberEmail}, email_client::EmailClient};
 use std::convert::{TryFrom, TryInto};
 use crate::startup::ApplicationBaseUrl;
+use crate::consent::{client_ip, user_agent, SignupConsent};
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng,Rng};
 use std::fmt::Formatter;
 use actix_web::http::StatusCode;
 use anyhow::Context;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use crate::attributes::fetch_schema;
+use crate::locales::{Locales, Messages};
+use actix_web::http::header::ACCEPT_LANGUAGE;
+use crate::outbox::send_or_enqueue;
 
 pub struct StoreTokenError(sqlx::Error);
 
@@ -93,6 +100,15 @@ impl ResponseError for SubscribeError {
 pub struct FormData {
     name: String,
     email: String,
+    /// Identifies the signup form (and its version) the subscriber used, for the consent record.
+    form: Option<String>,
+    form_version: Option<String>,
+    /// The language of the emails, when the form lets subscribers choose.
+    /// `Accept-Language` decides otherwise.
+    locale: Option<String>,
+    /// Any other field, kept when it is a declared attribute.
+    #[serde(flatten)]
+    attributes: HashMap<String, String>,
 }
 
 impl std::fmt::Display for StoreTokenError {
@@ -128,7 +144,7 @@ impl TryFrom<FormData> for NewSubscriber {
     }
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
 
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
@@ -139,15 +155,33 @@ fn generate_subscription_token() -> String {
 
 #[tracing::instrument(
     name = "adding a new subscriber",
-    skip(form,pool,email_client,base_url),
+    skip(form,request,pool,email_client,base_url,locales),
 )]
 pub async fn subscribe(
     form: web::Form<FormData>,
+    request: HttpRequest,
     pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
-    base_url: web::Data<ApplicationBaseUrl>
+    base_url: web::Data<ApplicationBaseUrl>,
+    locales: web::Data<Locales>
 ) -> Result<HttpResponse, SubscribeError> {
 
+    let consent = SignupConsent {
+        ip: client_ip(&request),
+        user_agent: user_agent(&request),
+        form: form.form.clone(),
+        form_version: form.form_version.clone(),
+    };
+
+    let accept_language = request.headers().get(ACCEPT_LANGUAGE).and_then(|value| value.to_str().ok());
+    let locale = locales.negotiate(form.locale.as_deref(), accept_language);
+
+    let attributes = fetch_schema(&pool)
+        .await
+        .context("failed to fetch the attribute definitions")?
+        .from_form(&form.attributes)
+        .map_err(SubscribeError::ValidationError)?;
+
     let new_subscriber = form.0.try_into()
         .map_err(SubscribeError::ValidationError)?;
 
@@ -156,7 +190,7 @@ pub async fn subscribe(
         .context("failed to acquire a postgres connection from the pool")?;
 
     // create a subscriber record
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
+    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber, &consent, attributes, &locale)
         .await
         .context("failed to insert subscriber id")?;
 
@@ -170,7 +204,18 @@ pub async fn subscribe(
         .await
         .context("failed to commit")?;
 
-    send_confirmation_email(&email_client, &new_subscriber, &base_url.0, &subscription_token)
+    let messages = locales.messages(&locale);
+
+    // the subscriber is waiting: a long wait for the throttle queues the email
+    send_confirmation_email(
+        &pool,
+        &email_client.interactive(),
+        &messages,
+        ConfirmationEmail::Welcome,
+        &new_subscriber,
+        &base_url.0,
+        &subscription_token
+    )
         .await
         .context("failed to send confirmation email")?;
 
@@ -192,42 +237,71 @@ pub async fn store_token(
         )
         .execute(transaction)
         .await
-        .map_err(|e| StoreTokenError(e))?;
+        .map_err(StoreTokenError)?;
 
     Ok(())
 }
 
 
 
-#[tracing::instrument(
-    name = "send a confirmation email to a new subscriber",
-    skip(email_client,new_subscriber,base_url,subscription_token)
-)]
-async fn send_confirmation_email(email_client: &EmailClient, new_subscriber: &NewSubscriber, base_url: &str,subscription_token: &str) -> Result<(), reqwest::Error> {
+/// The emails that carry a confirmation link.
+#[derive(Clone, Copy, Debug)]
+pub enum ConfirmationEmail {
+    /// Sent at signup.
+    Welcome,
+    /// Sent again to subscribers who did not confirm.
+    Reminder,
+}
+
+impl ConfirmationEmail {
+    fn message_prefix(self) -> &'static str {
+        match self {
+            ConfirmationEmail::Welcome => "confirmation",
+            ConfirmationEmail::Reminder => "reminder",
+        }
+    }
+}
+
+/// The subject, html and text body of a confirmation email.
+fn confirmation_email(
+    messages: &Messages<'_>,
+    kind: ConfirmationEmail,
+    new_subscriber: &NewSubscriber,
+    base_url: &str,
+    subscription_token: &str
+) -> (String, String, String) {
     // as I don't have post-map api
     let confirmation_link = format!("{}/subscriptions/confirm?subscription_token={}",base_url,subscription_token);
 
     println!("send_confirmation_email link => {}",confirmation_link);
 
-    let html_body = format!(
-        "Welcome to our newsletter! <br />\
-        Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
-    );
-
-    let text_body = format!(
-        "Welcome to our newsletter!\n Visit {} to confirm your subscription.",
-        confirmation_link
-    );
-
-    email_client
-        .send_email(
-            &new_subscriber.email,
-            "Welcome!",
-            &html_body,
-            &text_body
-        )
-        .await
+    let prefix = kind.message_prefix();
+    let args = [("link", confirmation_link.as_str()), ("name", new_subscriber.name.as_ref())];
+
+    (
+        messages.text(&format!("{}.subject", prefix), &args),
+        messages.html(&format!("{}.html", prefix), &args),
+        messages.text(&format!("{}.text", prefix), &args)
+    )
+}
+
+/// While the email provider is down, the email waits in the outbox.
+#[tracing::instrument(
+    name = "send a confirmation email to a new subscriber",
+    skip(pool,email_client,messages,new_subscriber,base_url,subscription_token)
+)]
+pub async fn send_confirmation_email(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    messages: &Messages<'_>,
+    kind: ConfirmationEmail,
+    new_subscriber: &NewSubscriber,
+    base_url: &str,
+    subscription_token: &str
+) -> Result<(), anyhow::Error> {
+    let (subject, html_body, text_body) = confirmation_email(messages, kind, new_subscriber, base_url, subscription_token);
+
+    send_or_enqueue(pool, email_client, &new_subscriber.email, &subject, &html_body, &text_body).await
 }
 
 pub fn parse_subscriber(form: FormData) -> Result<NewSubscriber, String> {
@@ -239,17 +313,30 @@ pub fn parse_subscriber(form: FormData) -> Result<NewSubscriber, String> {
 
 #[tracing::instrument(
     name = "saving new subscriber details in the database",
-    skip(transaction,new_subscriber)
+    skip(transaction,new_subscriber,consent,attributes)
 )]
-pub async fn insert_subscriber(transaction: &mut Transaction<'_,Postgres>, new_subscriber: &NewSubscriber) -> Result<Uuid,sqlx::Error> {
+pub async fn insert_subscriber(
+    transaction: &mut Transaction<'_,Postgres>,
+    new_subscriber: &NewSubscriber,
+    consent: &SignupConsent,
+    attributes: Map<String, Value>,
+    locale: &str
+) -> Result<Uuid,sqlx::Error> {
     let uuid = Uuid::new_v4();
+    let now = Utc::now();
 
     sqlx::query!(
             r#"
-                INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-                VALUES ($1,$2,$3,$4, 'pending_confirmation')
+                INSERT INTO subscriptions (
+                    id, email, name, subscribed_at, status,
+                    consent_at, consent_ip, consent_user_agent, signup_form, signup_form_version,
+                    attributes, locale
+                )
+                VALUES ($1,$2,$3,$4, 'pending_confirmation', $5, $6, $7, $8, $9, $10, $11)
             "#,
-            uuid, new_subscriber.email.as_ref(), new_subscriber.name.as_ref(), Utc::now()
+            uuid, new_subscriber.email.as_ref(), new_subscriber.name.as_ref(), now,
+            now, consent.ip, consent.user_agent, consent.form, consent.form_version,
+            Value::Object(attributes), locale
         )
         .execute(transaction)
         .await?;
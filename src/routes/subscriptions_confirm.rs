@@ -1,6 +1,10 @@
-use actix_web::{HttpResponse, web};
+use actix_web::{HttpRequest, HttpResponse, web};
+use chrono::Utc;
 use sqlx::PgPool;
 use uuid::Uuid;
+use crate::consent::client_ip;
+use crate::locales::{subscriber_locale, Locales};
+use crate::sequences::enroll;
 
 #[derive(serde::Deserialize)]
 pub struct Parameters {
@@ -9,9 +13,14 @@ pub struct Parameters {
 
 #[tracing::instrument(
     name = "confirm a pending subscriber",
-    skip(parameters,pool)
+    skip(parameters,request,pool,locales)
 )]
-pub async fn confirm(parameters: web::Query<Parameters>, pool : web::Data<PgPool>) -> HttpResponse {
+pub async fn confirm(
+    parameters: web::Query<Parameters>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    locales: web::Data<Locales>
+) -> HttpResponse {
     let subscriber_id = match get_subscriber_id_from_token(&pool,&parameters.subscription_token).await {
         Ok(id) => id,
         Err(_) => return HttpResponse::InternalServerError().finish()
@@ -20,11 +29,18 @@ pub async fn confirm(parameters: web::Query<Parameters>, pool : web::Data<PgPool
     match subscriber_id {
         None => HttpResponse::NotFound().finish(),
         Some(subscriber_id) => {
-            if confirm_subscription(&pool, subscriber_id).await.is_err() {
+            if confirm_subscription(&pool, subscriber_id, client_ip(&request)).await.is_err() {
                 return HttpResponse::InternalServerError().finish();
             }
 
-            HttpResponse::Ok().finish()
+            let locale = match subscriber_locale(&pool, subscriber_id).await {
+                Ok(locale) => locale,
+                Err(_) => return HttpResponse::InternalServerError().finish()
+            };
+
+            HttpResponse::Ok()
+                .content_type("text/html; charset=utf-8")
+                .body(locales.messages(&locale).html("confirmed.page", &[]))
         }
     }
 }
@@ -50,19 +66,29 @@ pub async fn get_subscriber_id_from_token(pool: &PgPool, subscription_token: &st
 
 #[tracing::instrument(
     name = "confirm users subscription",
-    skip(pool)
+    skip(pool, ip)
 )]
-pub async fn confirm_subscription(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-            r#"UPDATE subscriptions SET status = 'confirmed' WHERE id = $1"#,
-            subscriber_id
+pub async fn confirm_subscription(pool: &PgPool, subscriber_id: Uuid, ip: Option<String>) -> Result<(), sqlx::Error> {
+    // only the first click counts, so that following the link again does not
+    // overwrite when and where the subscription was confirmed
+    let confirmed = sqlx::query!(
+            r#"
+                UPDATE subscriptions SET status = 'confirmed', confirmed_at = $2, confirmed_ip = $3
+                WHERE id = $1 AND status = 'pending_confirmation'
+            "#,
+            subscriber_id, Utc::now(), ip
         )
         .execute(pool)
         .await
         .map_err(|e| {
             tracing::error!("failed to execute query. {:?}",e);
             e
-        })?;
+        })?
+        .rows_affected();
+
+    if confirmed > 0 {
+        enroll(pool, subscriber_id).await?;
+    }
 
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,24 @@
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use crate::email_change::{confirm_email_change, EmailChangeError};
+use crate::merge::html_escape;
+
+#[derive(serde::Deserialize)]
+pub struct EmailChangeParameters {
+    token: String,
+}
+
+#[tracing::instrument(name = "confirm a new subscriber address", skip(parameters, pool))]
+pub async fn confirm_new_email(parameters: web::Query<EmailChangeParameters>, pool: web::Data<PgPool>) -> HttpResponse {
+    match confirm_email_change(&pool, &parameters.token).await {
+        Ok(Some(email)) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(format!("<p>Thanks! The newsletter will now be sent to {}.</p>", html_escape(&email))),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e @ EmailChangeError::Taken(_)) => HttpResponse::Conflict().body(e.to_string()),
+        Err(EmailChangeError::Unexpected(e)) => {
+            tracing::error!("failed to change the subscriber address. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
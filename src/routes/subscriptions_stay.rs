@@ -0,0 +1,31 @@
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::engagement::mark_engaged;
+use crate::startup::HmacSecret;
+use crate::sunset::verify_stay_subscribed;
+
+/// The link of the re-engagement email: counts as engagement, which keeps the
+/// subscriber from being moved to `inactive`.
+#[tracing::instrument(name = "keep a subscriber subscribed", skip(path, pool, hmac_secret))]
+pub async fn stay_subscribed(
+    path: web::Path<(Uuid, String)>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let (subscriber_id, signature) = path.into_inner();
+
+    if !verify_stay_subscribed(&hmac_secret.0, subscriber_id, &signature) {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    if let Err(e) = mark_engaged(pool.get_ref(), subscriber_id, Utc::now()).await {
+        tracing::error!("failed to keep the subscriber subscribed. {:?}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body("<p>Thanks! You will keep receiving our newsletter.</p>")
+}
@@ -0,0 +1,53 @@
+use actix_web::{web, HttpRequest, HttpResponse};
+use sqlx::PgPool;
+use crate::consent::user_agent;
+use crate::startup::HmacSecret;
+use crate::tracking::{record_click, record_open, verify, PIXEL};
+
+#[derive(serde::Deserialize)]
+pub struct ClickParameters {
+    url: String,
+}
+
+/// Serves the tracking pixel. The image is returned whatever the outcome, so
+/// that mail clients never show a broken image.
+#[tracing::instrument(name = "track an open", skip(delivery_token, request, pool))]
+pub async fn track_open(delivery_token: web::Path<String>, request: HttpRequest, pool: web::Data<PgPool>) -> HttpResponse {
+    match record_open(&pool, &delivery_token, user_agent(&request)).await {
+        Ok(true) => {},
+        Ok(false) => tracing::warn!("an open was tracked for an unknown delivery token"),
+        Err(e) => tracing::error!("failed to record an open. {:?}", e),
+    }
+
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(("Cache-Control", "no-store, no-cache, must-revalidate"))
+        .body(PIXEL)
+}
+
+/// Logs the click and redirects to the destination of a tracked link. Links
+/// with a bad signature are rejected rather than followed.
+#[tracing::instrument(name = "track a click", skip(path, parameters, request, pool, hmac_secret))]
+pub async fn track_click(
+    path: web::Path<(String, String)>,
+    parameters: web::Query<ClickParameters>,
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    let (delivery_token, signature) = path.into_inner();
+
+    if !verify(&hmac_secret.0, &delivery_token, &parameters.url, &signature) {
+        return HttpResponse::BadRequest().finish();
+    }
+
+    match record_click(&pool, &delivery_token, &parameters.url, user_agent(&request)).await {
+        Ok(true) => {},
+        Ok(false) => tracing::warn!("a click was tracked for an unknown delivery token"),
+        Err(e) => tracing::error!("failed to record a click. {:?}", e),
+    }
+
+    HttpResponse::Found()
+        .insert_header(("Location", parameters.url.as_str()))
+        .finish()
+}
@@ -0,0 +1,113 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+use crate::locales::{subscriber_locale, Locales};
+use crate::sequences::stop_sequences;
+use crate::suppression::suppress;
+
+/// Asks the recipient of a delivery to confirm the unsubscribe, so that link
+/// scanners following the link do not unsubscribe anyone.
+#[tracing::instrument(name = "show the unsubscribe page", skip(delivery_token, pool, locales))]
+pub async fn unsubscribe_page(delivery_token: web::Path<String>, pool: web::Data<PgPool>, locales: web::Data<Locales>) -> HttpResponse {
+    match delivery_locale(&pool, &delivery_token).await {
+        Ok(Some(locale)) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(locales.messages(&locale).html("unsubscribe.page", &[])),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("failed to show the unsubscribe page. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+/// Unsubscribes the recipient of a delivery and attributes the unsubscribe
+/// to its issue. The address is suppressed so that imports do not bring it back.
+/// Both the form of the unsubscribe page and one-click unsubscribes
+/// (RFC 8058) post here.
+#[tracing::instrument(name = "unsubscribe a subscriber", skip(delivery_token, pool, locales))]
+pub async fn unsubscribe(delivery_token: web::Path<String>, pool: web::Data<PgPool>, locales: web::Data<Locales>) -> HttpResponse {
+    match unsubscribe_delivery(&pool, &delivery_token).await {
+        Ok(Some(locale)) => HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(locales.messages(&locale).html("unsubscribed.page", &[])),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            tracing::error!("failed to unsubscribe. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+/// Returns the locale of the recipient, or `None` when there is no such delivery.
+async fn delivery_locale(pool: &PgPool, delivery_token: &str) -> Result<Option<String>, anyhow::Error> {
+    let delivery = sqlx::query!(r#"SELECT subscriber_id FROM issue_deliveries WHERE token = $1"#, delivery_token)
+        .fetch_optional(pool)
+        .await
+        .context("failed to fetch the delivery")?;
+
+    match delivery {
+        Some(delivery) => {
+            let locale = subscriber_locale(pool, delivery.subscriber_id).await.context("failed to get the locale")?;
+            Ok(Some(locale))
+        },
+        None => Ok(None),
+    }
+}
+
+/// Returns the locale of the subscriber, or `None` when there is no such delivery.
+async fn unsubscribe_delivery(pool: &PgPool, delivery_token: &str) -> Result<Option<String>, anyhow::Error> {
+    let mut transaction = pool.begin().await.context("failed to start a transaction")?;
+
+    let delivery = sqlx::query!(
+            r#"
+                UPDATE issue_deliveries SET unsubscribed_at = COALESCE(unsubscribed_at, $2)
+                WHERE token = $1
+                RETURNING subscriber_id
+            "#,
+            delivery_token, Utc::now()
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .context("failed to attribute the unsubscribe to the delivery")?;
+
+    let subscriber_id = match delivery {
+        Some(delivery) => delivery.subscriber_id,
+        None => return Ok(None),
+    };
+
+    unsubscribe_subscriber(&mut transaction, subscriber_id).await?;
+
+    transaction.commit().await.context("failed to commit the unsubscribe")?;
+
+    let locale = subscriber_locale(pool, subscriber_id).await.context("failed to get the locale")?;
+
+    Ok(Some(locale))
+}
+
+/// Unsubscribes and suppresses the subscriber, and stops their sequences.
+pub async fn unsubscribe_subscriber(transaction: &mut Transaction<'_, Postgres>, subscriber_id: Uuid) -> Result<(), anyhow::Error> {
+    let subscriber = sqlx::query!(
+            r#"
+                UPDATE subscriptions SET status = 'unsubscribed', unsubscribed_at = COALESCE(unsubscribed_at, $2)
+                WHERE id = $1
+                RETURNING email
+            "#,
+            subscriber_id, Utc::now()
+        )
+        .fetch_one(&mut *transaction)
+        .await
+        .context("failed to unsubscribe the subscriber")?;
+
+    suppress(&mut *transaction, &subscriber.email, "unsubscribed")
+        .await
+        .context("failed to suppress the address")?;
+
+    stop_sequences(&mut *transaction, subscriber_id)
+        .await
+        .context("failed to stop the sequences")?;
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+use crate::authentication::AdminUser;
+use crate::routes::unsubscribe_subscriber;
+use crate::sequences::stop_sequences;
+use crate::suppression::suppress;
+
+/// The fields of a Postmark bounce or spam complaint webhook that we use.
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+pub struct PostmarkEvent {
+    record_type: String,
+    email: String,
+    /// The kind of bounce, e.g. `HardBounce` or `SoftBounce`.
+    #[serde(rename = "Type")]
+    kind: Option<String>,
+}
+
+/// Records bounces and spam complaints reported by Postmark against the
+/// last issue sent to the address. Hard bounces suppress the address and end
+/// its subscription, and a complaint also unsubscribes it. Other record types
+/// are ignored.
+#[tracing::instrument(name = "handle a postmark webhook", skip(event, pool, admin), fields(admin = %admin.username, record_type = %event.record_type))]
+pub async fn postmark_webhook(event: web::Json<PostmarkEvent>, pool: web::Data<PgPool>, admin: AdminUser) -> HttpResponse {
+    match record_event(&pool, &event).await {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => {
+            tracing::error!("failed to record the postmark event. {:?}", e);
+            HttpResponse::InternalServerError().finish()
+        },
+    }
+}
+
+async fn record_event(pool: &PgPool, event: &PostmarkEvent) -> Result<(), anyhow::Error> {
+    let mut transaction = pool.begin().await.context("failed to start a transaction")?;
+
+    match event.record_type.as_str() {
+        "Bounce" => {
+            if let Some((delivery_id, _)) = last_delivery(&mut transaction, &event.email).await? {
+                sqlx::query!(
+                        r#"UPDATE issue_deliveries SET bounced_at = COALESCE(bounced_at, $2) WHERE id = $1"#,
+                        delivery_id, Utc::now()
+                    )
+                    .execute(&mut transaction)
+                    .await
+                    .context("failed to record the bounce")?;
+            }
+
+            if event.kind.as_deref() == Some("HardBounce") {
+                suppress(&mut transaction, &event.email, "bounced")
+                    .await
+                    .context("failed to suppress the address")?;
+
+                let bounced = sqlx::query!(
+                        r#"
+                            UPDATE subscriptions SET status = 'bounced'
+                            WHERE lower(email) = lower($1) AND status NOT IN ('unsubscribed', 'bounced')
+                            RETURNING id
+                        "#,
+                        event.email
+                    )
+                    .fetch_optional(&mut transaction)
+                    .await
+                    .context("failed to end the subscription")?;
+
+                if let Some(subscriber) = bounced {
+                    stop_sequences(&mut transaction, subscriber.id)
+                        .await
+                        .context("failed to stop the sequences")?;
+                }
+            }
+        },
+        "SpamComplaint" => match last_delivery(&mut transaction, &event.email).await? {
+            Some((delivery_id, subscriber_id)) => {
+                sqlx::query!(
+                        r#"UPDATE issue_deliveries SET complained_at = COALESCE(complained_at, $2) WHERE id = $1"#,
+                        delivery_id, Utc::now()
+                    )
+                    .execute(&mut transaction)
+                    .await
+                    .context("failed to record the complaint")?;
+
+                unsubscribe_subscriber(&mut transaction, subscriber_id).await?;
+            },
+            None => suppress(&mut transaction, &event.email, "complained")
+                .await
+                .context("failed to suppress the address")?,
+        },
+        _ => tracing::info!("ignoring the postmark event"),
+    }
+
+    transaction.commit().await.context("failed to commit the postmark event")?;
+
+    Ok(())
+}
+
+/// The last delivery sent to `email`, with its subscriber.
+async fn last_delivery(transaction: &mut Transaction<'_, Postgres>, email: &str) -> Result<Option<(Uuid, Uuid)>, anyhow::Error> {
+    let delivery = sqlx::query!(
+            r#"
+                SELECT d.id, d.subscriber_id
+                FROM issue_deliveries d
+                JOIN subscriptions s ON s.id = d.subscriber_id
+                WHERE lower(s.email) = lower($1) AND d.sent_at IS NOT NULL
+                ORDER BY d.sent_at DESC
+                LIMIT 1
+            "#,
+            email
+        )
+        .fetch_optional(&mut *transaction)
+        .await
+        .context("failed to find the delivery")?;
+
+    Ok(delivery.map(|d| (d.id, d.subscriber_id)))
+}
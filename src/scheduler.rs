@@ -0,0 +1,66 @@
+//! Work that `serve` runs in the background, every
+//! `scheduler.poll_interval_seconds`. Each job picks up whatever is due, so a
+//! missed tick (or a restart) only delays it. Jobs that send emails lock
+//! themselves (see `job_lock`), so that several instances, or a tick and an
+//! admin request, do not send the same email twice.
+
+use sqlx::PgPool;
+use crate::configuration::Settings;
+use crate::email_client::EmailClient;
+use crate::feed_watcher::watch_feed;
+use crate::locales::Locales;
+use crate::outbox::flush_outbox;
+use crate::reminders::send_reminders;
+use crate::sequences::send_due_steps;
+use crate::startup::get_connection_pool;
+
+pub async fn run_until_stopped(config: &Settings, email_client: EmailClient) -> Result<(), anyhow::Error> {
+    let pool = get_connection_pool(&config.database);
+    let locales = config.locales.catalogs()?;
+    let mut interval = tokio::time::interval(config.scheduler.poll_interval());
+
+    loop {
+        interval.tick().await;
+        run_due_jobs(&pool, &email_client, &locales, config).await;
+    }
+}
+
+/// Failures are logged and retried on the next tick.
+#[tracing::instrument(name = "run scheduled jobs", skip_all)]
+async fn run_due_jobs(pool: &PgPool, email_client: &EmailClient, locales: &Locales, config: &Settings) {
+    match flush_outbox(pool, email_client).await {
+        Ok(report) if report.sent > 0 || report.failed > 0 => {
+            tracing::info!(sent = report.sent, failed = report.failed, "sent queued emails")
+        },
+        Ok(_) => {},
+        Err(e) => tracing::error!(error.cause_chain = ?e, "failed to flush the outbox"),
+    }
+
+    match send_reminders(pool, email_client, &config.application.base_url, locales, &config.reminders).await {
+        Ok(report) if report.sent > 0 || report.failed > 0 => {
+            tracing::info!(sent = report.sent, failed = report.failed, "sent confirmation reminders")
+        },
+        Ok(_) => {},
+        Err(e) => tracing::error!(error.cause_chain = ?e, "failed to send confirmation reminders"),
+    }
+
+    match send_due_steps(pool, email_client, &config.application.base_url, &config.application.hmac_secret).await {
+        Ok(report) if report.sent > 0 || report.failed > 0 => {
+            tracing::info!(sent = report.sent, failed = report.failed, "sent sequence steps")
+        },
+        Ok(_) => {},
+        Err(e) => tracing::error!(error.cause_chain = ?e, "failed to send sequence steps"),
+    }
+
+    let feed_watcher = &config.feed_watcher;
+    match watch_feed(pool, email_client, &config.application.base_url, &config.application.hmac_secret, feed_watcher).await {
+        Ok(report) if report.new_entries > 0 => tracing::info!(
+            drafted = report.drafted,
+            published = report.published,
+            failed = report.failed,
+            "turned new feed entries into issues"
+        ),
+        Ok(_) => {},
+        Err(e) => tracing::error!(error.cause_chain = ?e, "failed to watch the feed"),
+    }
+}
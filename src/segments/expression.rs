@@ -0,0 +1,365 @@
+//! The segment language. Terms are combined with `AND`, `OR`, `NOT` and
+//! parentheses:
+//!
+//! - `confirmed`, `inactive`, `pending`, `unsubscribed`, `bounced` or `status:<status>`
+//! - `tag:<tag>`
+//! - `subscribed_at >= 2022-01-01`
+//! - `<attribute> = "value"`, or a bool attribute on its own
+//!
+//! Comparisons are `=`, `!=`, `<`, `<=`, `>` and `>=`.
+
+use crate::tags::parse_tag;
+
+const STATUSES: &[&str] = &["pending_confirmation", "confirmed", "unsubscribed", "inactive", "bounced"];
+
+/// Expressions come from api requests and are parsed, compiled and dropped
+/// recursively, so their size and nesting are bounded.
+const MAX_LENGTH: usize = 4096;
+const MAX_TOKENS: usize = 512;
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    pub fn as_sql(&self) -> &'static str {
+        match self {
+            Comparison::Equal => "=",
+            Comparison::NotEqual => "<>",
+            Comparison::Less => "<",
+            Comparison::LessOrEqual => "<=",
+            Comparison::Greater => ">",
+            Comparison::GreaterOrEqual => ">=",
+        }
+    }
+
+    pub fn is_ordering(&self) -> bool {
+        !matches!(self, Comparison::Equal | Comparison::NotEqual)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Expression {
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    Status(String),
+    Tag(String),
+    /// A bare name that is not a status: a bool attribute.
+    Flag(String),
+    Compare { field: String, comparison: Comparison, value: String },
+}
+
+impl Expression {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if s.len() > MAX_LENGTH {
+            return Err(format!("the expression is longer than {} characters", MAX_LENGTH));
+        }
+
+        let tokens = tokenize(s)?;
+        if tokens.len() > MAX_TOKENS {
+            return Err(format!("the expression has more than {} terms and operators", MAX_TOKENS));
+        }
+
+        let mut parser = Parser { tokens, position: 0, depth: 0 };
+
+        let expression = parser.or()?;
+
+        match parser.peek() {
+            None => Ok(expression),
+            Some(token) => Err(format!("unexpected {}", token)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Comparison(Comparison),
+    Word(String),
+    Quoted(String),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::Comparison(c) => write!(f, "'{}'", c.as_sql()),
+            Token::Word(w) => write!(f, "'{}'", w),
+            Token::Quoted(q) => write!(f, "\"{}\"", q),
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | ':' | '.' | '+')
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            },
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            },
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            },
+            '"' => {
+                chars.next();
+                let mut quoted = String::new();
+
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => quoted.push(escaped),
+                            None => return Err("unterminated string".into()),
+                        },
+                        Some(c) => quoted.push(c),
+                        None => return Err("unterminated string".into()),
+                    }
+                }
+
+                tokens.push(Token::Quoted(quoted));
+            },
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let or_equal = chars.next_if_eq(&'=').is_some();
+
+                let comparison = match (c, or_equal) {
+                    ('=', _) => Comparison::Equal,
+                    ('!', true) => Comparison::NotEqual,
+                    ('<', false) => Comparison::Less,
+                    ('<', true) => Comparison::LessOrEqual,
+                    ('>', false) => Comparison::Greater,
+                    ('>', true) => Comparison::GreaterOrEqual,
+                    _ => return Err("unexpected '!'. use 'NOT' or '!='".into()),
+                };
+
+                tokens.push(Token::Comparison(comparison));
+            },
+            c if is_word_char(c) => {
+                let mut word = String::new();
+
+                while let Some(c) = chars.next_if(|c| is_word_char(*c)) {
+                    word.push(c);
+                }
+
+                tokens.push(Token::Word(word));
+            },
+            other => return Err(format!("unexpected '{}'", other)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// A recursive descent parser. `NOT` binds tighter than `AND`, which binds
+/// tighter than `OR`.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// `NOT`s and parentheses we are in.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn next_is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword))
+    }
+
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, String>) -> Result<T, String> {
+        if self.depth >= MAX_DEPTH {
+            return Err(format!("the expression is nested more than {} levels deep", MAX_DEPTH));
+        }
+
+        self.depth += 1;
+        let parsed = parse(self);
+        self.depth -= 1;
+
+        parsed
+    }
+
+    fn or(&mut self) -> Result<Expression, String> {
+        let mut expression = self.and()?;
+
+        while self.next_is_keyword("or") {
+            self.next();
+            expression = Expression::Or(Box::new(expression), Box::new(self.and()?));
+        }
+
+        Ok(expression)
+    }
+
+    fn and(&mut self) -> Result<Expression, String> {
+        let mut expression = self.not()?;
+
+        while self.next_is_keyword("and") {
+            self.next();
+            expression = Expression::And(Box::new(expression), Box::new(self.not()?));
+        }
+
+        Ok(expression)
+    }
+
+    fn not(&mut self) -> Result<Expression, String> {
+        if self.next_is_keyword("not") {
+            self.next();
+            return Ok(Expression::Not(Box::new(self.nested(Self::not)?)));
+        }
+
+        self.term()
+    }
+
+    fn term(&mut self) -> Result<Expression, String> {
+        match self.next() {
+            Some(Token::Open) => {
+                let expression = self.nested(Self::or)?;
+
+                match self.next() {
+                    Some(Token::Close) => Ok(expression),
+                    Some(token) => Err(format!("expected ')', found {}", token)),
+                    None => Err("missing ')'".into()),
+                }
+            },
+            Some(Token::Word(word)) => {
+                if let Some(Token::Comparison(comparison)) = self.peek().cloned() {
+                    self.next();
+
+                    let value = match self.next() {
+                        Some(Token::Word(value)) | Some(Token::Quoted(value)) => value,
+                        Some(token) => return Err(format!("expected a value after {}, found {}", word, token)),
+                        None => return Err(format!("expected a value after {}", word)),
+                    };
+
+                    return Ok(Expression::Compare { field: word, comparison, value });
+                }
+
+                word_term(word)
+            },
+            Some(token) => Err(format!("unexpected {}", token)),
+            None => Err("unexpected end of expression".into()),
+        }
+    }
+}
+
+fn word_term(word: String) -> Result<Expression, String> {
+    if let Some(tag) = word.strip_prefix("tag:") {
+        return Ok(Expression::Tag(parse_tag(tag)?));
+    }
+
+    let status = word.strip_prefix("status:").unwrap_or(&word);
+    let status = if status == "pending" { "pending_confirmation" } else { status };
+
+    if STATUSES.contains(&status) {
+        return Ok(Expression::Status(status.to_string()));
+    }
+    if word.starts_with("status:") {
+        return Err(format!("{} is not a status", status));
+    }
+    if ["and", "or", "not"].iter().any(|k| word.eq_ignore_ascii_case(k)) {
+        return Err(format!("unexpected '{}'", word));
+    }
+
+    Ok(Expression::Flag(word))
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_err;
+    use crate::segments::expression::{Comparison, Expression};
+
+    fn tag(t: &str) -> Box<Expression> {
+        Box::new(Expression::Tag(t.into()))
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_which_binds_tighter_than_or() {
+        assert_eq!(
+            Expression::parse("confirmed AND tag:beta AND NOT tag:staff or tag:vip").unwrap(),
+            Expression::Or(
+                Box::new(Expression::And(
+                    Box::new(Expression::And(Box::new(Expression::Status("confirmed".into())), tag("beta"))),
+                    Box::new(Expression::Not(tag("staff"))),
+                )),
+                tag("vip"),
+            )
+        );
+    }
+
+    #[test]
+    fn parentheses_group_terms() {
+        assert_eq!(
+            Expression::parse("NOT (tag:a OR tag:b)").unwrap(),
+            Expression::Not(Box::new(Expression::Or(tag("a"), tag("b"))))
+        );
+    }
+
+    #[test]
+    fn comparisons_take_words_and_quoted_strings() {
+        assert_eq!(
+            Expression::parse(r#"company != "Smith & Sons""#).unwrap(),
+            Expression::Compare { field: "company".into(), comparison: Comparison::NotEqual, value: "Smith & Sons".into() }
+        );
+        assert_eq!(
+            Expression::parse("subscribed_at>=2022-01-01").unwrap(),
+            Expression::Compare { field: "subscribed_at".into(), comparison: Comparison::GreaterOrEqual, value: "2022-01-01".into() }
+        );
+    }
+
+    #[test]
+    fn statuses_have_a_short_and_a_long_form() {
+        assert_eq!(Expression::parse("pending").unwrap(), Expression::Status("pending_confirmation".into()));
+        assert_eq!(Expression::parse("status:inactive").unwrap(), Expression::Status("inactive".into()));
+        assert_err!(Expression::parse("status:deleted"));
+    }
+
+    #[test]
+    fn malformed_expressions_are_rejected() {
+        assert_err!(Expression::parse(""));
+        assert_err!(Expression::parse("tag:beta AND"));
+        assert_err!(Expression::parse("(tag:beta"));
+        assert_err!(Expression::parse("tag:beta tag:staff"));
+        assert_err!(Expression::parse("company ="));
+        assert_err!(Expression::parse(r#"company = "unterminated"#));
+    }
+
+    #[test]
+    fn deeply_nested_and_very_long_expressions_are_rejected() {
+        // too long, too many tokens, too deep
+        assert_err!(Expression::parse(&format!("{}confirmed", "NOT ".repeat(50_000))));
+        assert_err!(Expression::parse(&vec!["tag:a"; 300].join(" OR ")));
+        assert_err!(Expression::parse(&format!("{}confirmed", "NOT ".repeat(100))));
+        assert_err!(Expression::parse(&format!("{}confirmed{}", "(".repeat(100), ")".repeat(100))));
+
+        let nested = format!("{}confirmed{}", "(NOT ".repeat(30), ")".repeat(30));
+        assert!(Expression::parse(&nested).is_ok());
+    }
+}
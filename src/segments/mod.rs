@@ -0,0 +1,326 @@
+//! Saved segments, and the sql filter that segment expressions compile to.
+//! Every value of an expression is bound as a parameter; only the shape of
+//! the query comes from the expression.
+
+mod expression;
+
+pub use expression::Expression;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::postgres::PgArguments;
+use sqlx::query::Query;
+use sqlx::{PgPool, Postgres};
+use crate::attributes::{fetch_schema, AttributeKind, AttributeSchema};
+use expression::Comparison;
+
+#[derive(thiserror::Error, Debug)]
+pub enum SegmentError {
+    #[error("{0}")]
+    Invalid(String),
+    #[error(transparent)]
+    Unexpected(#[from] sqlx::Error),
+}
+
+#[derive(Serialize)]
+pub struct Segment {
+    pub name: String,
+    pub expression: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Who an issue goes to: confirmed subscribers (and inactive ones, when asked
+/// for) who have not paused the newsletter, narrowed down by a saved segment
+/// and ad-hoc filters.
+#[derive(Debug, Default)]
+pub struct Audience<'a> {
+    pub segment: Option<&'a str>,
+    pub include: Option<&'a str>,
+    pub exclude: Option<&'a str>,
+    pub include_inactive: bool,
+}
+
+#[derive(Debug)]
+enum Bind {
+    Text(String),
+    Json(Value),
+    Date(NaiveDate),
+    Float(f64),
+}
+
+/// Conditions on `subscriptions s`, all of which must hold.
+#[derive(Debug, Default)]
+pub struct SegmentFilter {
+    conditions: Vec<String>,
+    binds: Vec<Bind>,
+}
+
+impl SegmentFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn include(&mut self, expression: &Expression, schema: &AttributeSchema) -> Result<(), String> {
+        let condition = self.compile(expression, schema)?;
+        self.conditions.push(condition);
+
+        Ok(())
+    }
+
+    pub fn exclude(&mut self, expression: &Expression, schema: &AttributeSchema) -> Result<(), String> {
+        let condition = self.compile(expression, schema)?;
+        self.conditions.push(format!("NOT {}", condition));
+
+        Ok(())
+    }
+
+    pub fn where_clause(&self) -> String {
+        if self.conditions.is_empty() {
+            return "TRUE".into();
+        }
+
+        self.conditions.join(" AND ")
+    }
+
+    /// Binds the parameters of `where_clause`, which must be the only ones of the query.
+    pub fn bind<'q>(&self, mut query: Query<'q, Postgres, PgArguments>) -> Query<'q, Postgres, PgArguments> {
+        for bind in &self.binds {
+            query = match bind {
+                Bind::Text(text) => query.bind(text.clone()),
+                Bind::Json(json) => query.bind(json.clone()),
+                Bind::Date(date) => query.bind(*date),
+                Bind::Float(float) => query.bind(*float),
+            };
+        }
+
+        query
+    }
+
+    fn parameter(&mut self, bind: Bind) -> String {
+        self.binds.push(bind);
+        format!("${}", self.binds.len())
+    }
+
+    /// Every condition is true or false, never null, so that `NOT` behaves.
+    fn compile(&mut self, expression: &Expression, schema: &AttributeSchema) -> Result<String, String> {
+        match expression {
+            Expression::And(left, right) => Ok(format!(
+                "({} AND {})", self.compile(left, schema)?, self.compile(right, schema)?
+            )),
+            Expression::Or(left, right) => Ok(format!(
+                "({} OR {})", self.compile(left, schema)?, self.compile(right, schema)?
+            )),
+            Expression::Not(expression) => Ok(format!("NOT {}", self.compile(expression, schema)?)),
+            Expression::Status(status) => Ok(format!("s.status = {}", self.parameter(Bind::Text(status.clone())))),
+            Expression::Tag(tag) => Ok(format!(
+                "EXISTS (SELECT 1 FROM subscriber_tags t WHERE t.subscriber_id = s.id AND t.tag = {})",
+                self.parameter(Bind::Text(tag.clone()))
+            )),
+            Expression::Flag(name) => match schema.kind_of(name) {
+                Some(AttributeKind::Bool) => self.compare_attribute(name, AttributeKind::Bool, Comparison::Equal, "true"),
+                Some(kind) => Err(format!("{} is a {} attribute, compare it to a value", name, kind.as_str())),
+                None => Err(format!("{} is neither a status nor a declared attribute", name)),
+            },
+            Expression::Compare { field, comparison, value } if field == "subscribed_at" => {
+                let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                    .map_err(|_| format!("{} is not a date (YYYY-MM-DD)", value))?;
+
+                Ok(format!(
+                    "(s.subscribed_at AT TIME ZONE 'UTC')::date {} {}",
+                    comparison.as_sql(), self.parameter(Bind::Date(date))
+                ))
+            },
+            Expression::Compare { field, comparison, value } => {
+                let kind = schema.kind_of(field).ok_or_else(|| format!("{} is not a declared attribute", field))?;
+
+                self.compare_attribute(field, kind, *comparison, value)
+            },
+        }
+    }
+
+    fn compare_attribute(&mut self, attribute: &str, kind: AttributeKind, comparison: Comparison, raw: &str) -> Result<String, String> {
+        if kind == AttributeKind::Bool && comparison.is_ordering() {
+            return Err(format!("{} is a bool attribute, use '=' or '!='", attribute));
+        }
+
+        let value = kind.parse(raw).map_err(|e| format!("{}: {}", attribute, e))?;
+        let name = self.parameter(Bind::Text(attribute.to_string()));
+
+        if !comparison.is_ordering() {
+            let operator = if comparison == Comparison::Equal { "IS NOT DISTINCT FROM" } else { "IS DISTINCT FROM" };
+
+            return Ok(format!("(s.attributes -> {}::text) {} {}::jsonb", name, operator, self.parameter(Bind::Json(value))));
+        }
+
+        match kind {
+            AttributeKind::Number => {
+                let number = value.as_f64().unwrap_or_default();
+
+                Ok(format!(
+                    "COALESCE(CASE WHEN jsonb_typeof(s.attributes -> {0}::text) = 'number' \
+                    THEN (s.attributes ->> {0}::text)::float8 END {1} {2}::float8, false)",
+                    name, comparison.as_sql(), self.parameter(Bind::Float(number))
+                ))
+            },
+            // dates are stored as YYYY-MM-DD, which sorts like a date
+            _ => {
+                let text = value.as_str().unwrap_or_default().to_string();
+
+                Ok(format!(
+                    "COALESCE((s.attributes ->> {}::text) {} {}::text, false)",
+                    name, comparison.as_sql(), self.parameter(Bind::Text(text))
+                ))
+            },
+        }
+    }
+}
+
+/// Segment names appear in urls, so they follow the same rules as tags.
+pub fn parse_segment_name(name: &str) -> Result<String, String> {
+    crate::tags::parse_tag(name).map_err(|_| format!("{} is not a valid segment name. use letters, digits, '_' and '-'", name))
+}
+
+/// Parses and compiles an expression, to reject it before it is saved.
+pub fn validate_expression(expression: &str, schema: &AttributeSchema) -> Result<(), String> {
+    SegmentFilter::new().include(&Expression::parse(expression)?, schema)
+}
+
+#[tracing::instrument(name = "build the audience of an issue", skip(pool))]
+pub async fn audience_filter(pool: &PgPool, audience: &Audience<'_>) -> Result<SegmentFilter, SegmentError> {
+    let schema = fetch_schema(pool).await?;
+    let mut filter = SegmentFilter::new();
+
+    let statuses = if audience.include_inactive { "confirmed OR inactive" } else { "confirmed" };
+    filter.include(&Expression::parse(statuses).map_err(SegmentError::Invalid)?, &schema)
+        .map_err(SegmentError::Invalid)?;
+    filter.conditions.push("(s.paused_until IS NULL OR s.paused_until <= now())".into());
+
+    if let Some(name) = audience.segment {
+        let segment = fetch_segment(pool, name)
+            .await?
+            .ok_or_else(|| SegmentError::Invalid(format!("there is no segment named {}", name)))?;
+
+        Expression::parse(&segment.expression)
+            .and_then(|expression| filter.include(&expression, &schema))
+            .map_err(|e| SegmentError::Invalid(format!("segment {}: {}", name, e)))?;
+    }
+
+    if let Some(include) = audience.include {
+        Expression::parse(include)
+            .and_then(|expression| filter.include(&expression, &schema))
+            .map_err(|e| SegmentError::Invalid(format!("include: {}", e)))?;
+    }
+
+    if let Some(exclude) = audience.exclude {
+        Expression::parse(exclude)
+            .and_then(|expression| filter.exclude(&expression, &schema))
+            .map_err(|e| SegmentError::Invalid(format!("exclude: {}", e)))?;
+    }
+
+    Ok(filter)
+}
+
+#[tracing::instrument(name = "count the subscribers of a filter", skip(pool))]
+pub async fn count_subscribers(pool: &PgPool, filter: &SegmentFilter) -> Result<i64, sqlx::Error> {
+    use sqlx::Row;
+
+    let sql = format!("SELECT count(*) FROM subscriptions s WHERE {}", filter.where_clause());
+
+    filter.bind(sqlx::query(&sql))
+        .fetch_one(pool)
+        .await?
+        .try_get(0)
+}
+
+#[tracing::instrument(name = "list segments", skip(pool))]
+pub async fn list_segments(pool: &PgPool) -> Result<Vec<Segment>, sqlx::Error> {
+    sqlx::query_as!(Segment, r#"SELECT name, expression, created_at, updated_at FROM segments ORDER BY name"#)
+        .fetch_all(pool)
+        .await
+}
+
+#[tracing::instrument(name = "fetch a segment", skip(pool))]
+pub async fn fetch_segment(pool: &PgPool, name: &str) -> Result<Option<Segment>, sqlx::Error> {
+    sqlx::query_as!(
+            Segment,
+            r#"SELECT name, expression, created_at, updated_at FROM segments WHERE name = $1"#,
+            name
+        )
+        .fetch_optional(pool)
+        .await
+}
+
+/// Creates the segment, or replaces its expression.
+#[tracing::instrument(name = "save a segment", skip(pool))]
+pub async fn save_segment(pool: &PgPool, name: &str, expression: &str) -> Result<Segment, sqlx::Error> {
+    let now = Utc::now();
+
+    sqlx::query_as!(
+            Segment,
+            r#"
+                INSERT INTO segments (name, expression, created_at, updated_at)
+                VALUES ($1, $2, $3, $3)
+                ON CONFLICT (name) DO UPDATE SET expression = EXCLUDED.expression, updated_at = EXCLUDED.updated_at
+                RETURNING name, expression, created_at, updated_at
+            "#,
+            name, expression, now
+        )
+        .fetch_one(pool)
+        .await
+}
+
+#[tracing::instrument(name = "delete a segment", skip(pool))]
+pub async fn delete_segment(pool: &PgPool, name: &str) -> Result<bool, sqlx::Error> {
+    let deleted = sqlx::query!(r#"DELETE FROM segments WHERE name = $1"#, name)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(deleted > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use crate::attributes::{AttributeDefinition, AttributeKind, AttributeSchema};
+    use crate::segments::{validate_expression, Expression, SegmentFilter};
+
+    fn schema() -> AttributeSchema {
+        AttributeSchema::new(vec![
+            AttributeDefinition { name: "seats".into(), kind: AttributeKind::Number },
+            AttributeDefinition { name: "beta".into(), kind: AttributeKind::Bool },
+        ])
+    }
+
+    #[test]
+    fn values_are_bound_as_parameters() {
+        let mut filter = SegmentFilter::new();
+
+        filter.include(&Expression::parse("confirmed AND tag:beta").unwrap(), &schema()).unwrap();
+        filter.exclude(&Expression::parse("tag:staff").unwrap(), &schema()).unwrap();
+
+        assert_eq!(
+            filter.where_clause(),
+            "(s.status = $1 AND EXISTS (SELECT 1 FROM subscriber_tags t WHERE t.subscriber_id = s.id AND t.tag = $2)) \
+            AND NOT EXISTS (SELECT 1 FROM subscriber_tags t WHERE t.subscriber_id = s.id AND t.tag = $3)"
+        );
+        assert_eq!(filter.binds.len(), 3);
+    }
+
+    #[test]
+    fn attributes_must_be_declared_and_compared_to_their_type() {
+        assert_ok!(validate_expression("seats >= 10 AND beta AND subscribed_at < 2022-01-01", &schema()));
+        assert_err!(validate_expression("plan = pro", &schema()));
+        assert_err!(validate_expression("seats > many", &schema()));
+        assert_err!(validate_expression("beta > true", &schema()));
+        assert_err!(validate_expression("seats", &schema()));
+        assert_err!(validate_expression("subscribed_at > yesterday", &schema()));
+    }
+
+    #[test]
+    fn an_empty_filter_matches_everyone() {
+        assert_eq!(SegmentFilter::new().where_clause(), "TRUE");
+    }
+}
@@ -0,0 +1,308 @@
+//! Drip sequences: onboarding emails sent a number of days after a
+//! subscriber confirms. Subscribers are enrolled in every active sequence
+//! when they confirm, and the scheduler sends each of them the next step that
+//! is due. Steps are stored in the database, so admins edit them without a
+//! deploy.
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use secrecy::Secret;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sqlx::{PgExecutor, PgPool};
+use uuid::Uuid;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::job_lock::{try_lock, Job};
+use crate::merge::{render_html, render_text, MergeFields};
+use crate::preferences::preferences_url;
+use crate::outbox::send_or_enqueue;
+
+#[derive(Serialize)]
+pub struct Sequence {
+    pub name: String,
+    /// Inactive sequences neither enroll new subscribers nor send steps.
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Serialize)]
+pub struct Step {
+    /// Days after the enrollment the step is sent.
+    pub day: i32,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+    pub updated_at: DateTime<Utc>,
+}
+
+pub struct StepContent<'a> {
+    pub subject: &'a str,
+    pub text: &'a str,
+    pub html: &'a str,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct SequenceReport {
+    pub sent: usize,
+    pub failed: usize,
+}
+
+pub fn parse_sequence_name(name: &str) -> Result<String, String> {
+    crate::tags::parse_tag(name).map_err(|_| format!("{} is not a valid sequence name. use letters, digits, '_' and '-'", name))
+}
+
+#[tracing::instrument(name = "list sequences", skip(pool))]
+pub async fn list_sequences(pool: &PgPool) -> Result<Vec<Sequence>, sqlx::Error> {
+    let rows = sqlx::query!(r#"SELECT name, active, created_at, updated_at FROM sequences ORDER BY name"#)
+        .fetch_all(pool)
+        .await?;
+
+    let mut sequences = Vec::with_capacity(rows.len());
+
+    for row in rows {
+        sequences.push(Sequence {
+            steps: fetch_steps(pool, &row.name).await?,
+            name: row.name,
+            active: row.active,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        });
+    }
+
+    Ok(sequences)
+}
+
+#[tracing::instrument(name = "fetch a sequence", skip(pool))]
+pub async fn fetch_sequence(pool: &PgPool, name: &str) -> Result<Option<Sequence>, sqlx::Error> {
+    let row = sqlx::query!(r#"SELECT name, active, created_at, updated_at FROM sequences WHERE name = $1"#, name)
+        .fetch_optional(pool)
+        .await?;
+
+    match row {
+        Some(row) => Ok(Some(Sequence {
+            steps: fetch_steps(pool, &row.name).await?,
+            name: row.name,
+            active: row.active,
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+        })),
+        None => Ok(None),
+    }
+}
+
+async fn fetch_steps(pool: &PgPool, sequence: &str) -> Result<Vec<Step>, sqlx::Error> {
+    sqlx::query_as!(
+            Step,
+            r#"
+                SELECT day, subject, text_content AS text, html_content AS html, updated_at
+                FROM sequence_steps
+                WHERE sequence = $1
+                ORDER BY day
+            "#,
+            sequence
+        )
+        .fetch_all(pool)
+        .await
+}
+
+#[tracing::instrument(name = "save a sequence", skip(pool))]
+pub async fn save_sequence(pool: &PgPool, name: &str, active: bool) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+            r#"
+                INSERT INTO sequences (name, active, created_at, updated_at)
+                VALUES ($1, $2, $3, $3)
+                ON CONFLICT (name) DO UPDATE SET active = EXCLUDED.active, updated_at = EXCLUDED.updated_at
+            "#,
+            name, active, Utc::now()
+        )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Also forgets who is enrolled in it.
+#[tracing::instrument(name = "delete a sequence", skip(pool))]
+pub async fn delete_sequence(pool: &PgPool, name: &str) -> Result<bool, sqlx::Error> {
+    let deleted = sqlx::query!(r#"DELETE FROM sequences WHERE name = $1"#, name)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(deleted > 0)
+}
+
+/// Returns false when there is no such sequence. Subscribers who are past
+/// `day` already do not get a step added behind them.
+#[tracing::instrument(name = "save a sequence step", skip(pool, content))]
+pub async fn save_step(pool: &PgPool, sequence: &str, day: i32, content: &StepContent<'_>) -> Result<bool, sqlx::Error> {
+    let saved = sqlx::query!(
+            r#"
+                INSERT INTO sequence_steps (sequence, day, subject, text_content, html_content, updated_at)
+                SELECT name, $2, $3, $4, $5, $6 FROM sequences WHERE name = $1
+                ON CONFLICT (sequence, day) DO UPDATE SET
+                    subject = EXCLUDED.subject,
+                    text_content = EXCLUDED.text_content,
+                    html_content = EXCLUDED.html_content,
+                    updated_at = EXCLUDED.updated_at
+            "#,
+            sequence, day, content.subject, content.text, content.html, Utc::now()
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(saved > 0)
+}
+
+#[tracing::instrument(name = "delete a sequence step", skip(pool))]
+pub async fn delete_step(pool: &PgPool, sequence: &str, day: i32) -> Result<bool, sqlx::Error> {
+    let deleted = sqlx::query!(r#"DELETE FROM sequence_steps WHERE sequence = $1 AND day = $2"#, sequence, day)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(deleted > 0)
+}
+
+/// Enrolls a subscriber who just confirmed in every active sequence.
+#[tracing::instrument(name = "enroll a subscriber in the sequences", skip(executor))]
+pub async fn enroll<'e>(executor: impl PgExecutor<'e>, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+            r#"
+                INSERT INTO sequence_enrollments (subscriber_id, sequence, enrolled_at)
+                SELECT $1, name, $2 FROM sequences WHERE active
+                ON CONFLICT DO NOTHING
+            "#,
+            subscriber_id, Utc::now()
+        )
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "stop the sequences of a subscriber", skip(executor))]
+pub async fn stop_sequences<'e>(executor: impl PgExecutor<'e>, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+            r#"
+                UPDATE sequence_enrollments SET stopped_at = $2
+                WHERE subscriber_id = $1 AND stopped_at IS NULL
+            "#,
+            subscriber_id, Utc::now()
+        )
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+/// Sends every enrolled subscriber the next step that is due, at most one per
+/// sequence and run, so that a late run does not send several steps at once.
+/// Does nothing while another run is under way.
+#[tracing::instrument(name = "send due sequence steps", skip(pool, email_client, base_url, hmac_secret))]
+pub async fn send_due_steps(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+) -> Result<SequenceReport, anyhow::Error> {
+    let _lock = match try_lock(pool, Job::Sequences).await.context("failed to lock the job")? {
+        Some(lock) => lock,
+        None => return Ok(SequenceReport::default()),
+    };
+
+    let due = sqlx::query!(
+            r#"
+                SELECT DISTINCT ON (e.subscriber_id, e.sequence)
+                    e.subscriber_id, e.sequence, st.day, st.subject, st.text_content, st.html_content,
+                    s.email, s.name, s.attributes
+                FROM sequence_enrollments e
+                JOIN sequences q ON q.name = e.sequence
+                JOIN subscriptions s ON s.id = e.subscriber_id
+                JOIN sequence_steps st ON st.sequence = e.sequence AND st.day > COALESCE(e.sent_through_day, -1)
+                WHERE e.stopped_at IS NULL AND q.active
+                    AND s.status = 'confirmed'
+                    AND (s.paused_until IS NULL OR s.paused_until <= now())
+                    AND e.enrolled_at + make_interval(days => st.day) <= now()
+                ORDER BY e.subscriber_id, e.sequence, st.day
+            "#
+        )
+        .fetch_all(pool)
+        .await
+        .context("failed to find due sequence steps")?;
+
+    let mut report = SequenceReport::default();
+
+    for step in due {
+        let attributes = match step.attributes {
+            Value::Object(attributes) => attributes,
+            _ => Map::new(),
+        };
+        let fields = MergeFields { name: &step.name, email: &step.email, attributes: &attributes };
+        let content = StepContent { subject: &step.subject, text: &step.text_content, html: &step.html_content };
+
+        let sent = send_step(
+            pool,
+            email_client,
+            &step.email,
+            &fields,
+            &content,
+            &preferences_url(base_url, hmac_secret, step.subscriber_id),
+        ).await;
+
+        match sent {
+            Ok(()) => report.sent += 1,
+            Err(e) => {
+                tracing::warn!(error.cause_chain = ?e, "failed to send a sequence step");
+                report.failed += 1;
+            },
+        }
+
+        // moved past whether or not the provider took the email, so that an
+        // address it rejects is not retried on every run. while it is down the
+        // step waits in the outbox
+        sqlx::query!(
+                r#"
+                    UPDATE sequence_enrollments SET sent_through_day = $3, last_sent_at = $4
+                    WHERE subscriber_id = $1 AND sequence = $2
+                "#,
+                step.subscriber_id, step.sequence, step.day, Utc::now()
+            )
+            .execute(pool)
+            .await
+            .context("failed to record the sequence progress")?;
+    }
+
+    Ok(report)
+}
+
+async fn send_step(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    email: &str,
+    fields: &MergeFields<'_>,
+    content: &StepContent<'_>,
+    preferences_url: &str,
+) -> Result<(), anyhow::Error> {
+    let recipient = SubscriberEmail::parse(email.to_string()).map_err(anyhow::Error::msg)?;
+
+    let subject = render_text(content.subject, fields);
+    let html_body = format!(
+        r#"{}<p><a href="{}">Manage your preferences or unsubscribe</a>.</p>"#,
+        render_html(content.html, fields), preferences_url
+    );
+    let text_body = format!(
+        "{}\n\nManage your preferences or unsubscribe: {}",
+        render_text(content.text, fields), preferences_url
+    );
+
+    send_or_enqueue(pool, email_client, &recipient, &subject, &html_body, &text_body)
+        .await
+        .with_context(|| format!("failed to send a sequence step to {}", recipient))?;
+
+    Ok(())
+}
@@ -1,37 +1,31 @@
-use std::net::TcpListener;
+use std::net::{IpAddr, TcpListener};
 use actix_web::{HttpServer, web, App};
 use actix_web::dev::Server;
 use sqlx::postgres::PgPoolOptions;
-use crate::configuration::Settings;
-use crate::routes::{subscribe,health_check,confirm,publish_newsletter};
+use crate::configuration::{FeedWatcherSettings, ReminderSettings, Settings, SunsetSettings};
+use crate::routes::{subscribe,health_check,confirm,publish_newsletter,export_subscribers,import_subscribers_csv,data_subject_access,data_subject_erasure,subscriber_consent,track_open,track_click,unsubscribe,unsubscribe_page,issue_stats,issue_stats_csv,subscriber_growth_report,stay_subscribed,subscriber_engagement,sunset_subscribers,attribute_definitions,declare_attribute,delete_attribute,update_subscriber_attributes,list_tags,subscriber_tags,tag_subscriber,untag_subscriber,segment_list,segment_details,put_segment,remove_segment,topic_list,put_topic,remove_topic,preferences_page,update_preferences_form,send_weekly_digest,confirm_new_email,remind_pending_subscribers,sequence_list,sequence_details,put_sequence,remove_sequence,put_sequence_step,remove_sequence_step,run_sequences,archive_index,archive_issue,issue_visibility,rss_feed,atom_feed,draft_list,publish_draft_issue,remove_draft,run_feed_watcher,metrics,readiness,postmark_webhook};
 use sqlx::PgPool;
 use tracing_actix_web::TracingLogger;
 use crate::email_client::{EmailClient};
 use crate::configuration::DatabaseSettings;
 use actix_web::web::Data;
+use secrecy::Secret;
+use anyhow::Context;
+use crate::locales::Locales;
 
 pub struct Application {
     port: u16,
-    server: Server
+    server: Server,
+    email_client: EmailClient
 }
 
 impl Application {
-    pub async fn build(config: &Settings) -> Result<Self, std::io::Error> {
+    pub async fn build(config: &Settings) -> Result<Self, anyhow::Error> {
         let connection_pool = get_connection_pool(&config.database);
 
-        let sender_email = config
-            .email_client
-            .sender()
-            .expect("invalid sender email address.");
+        let email_client = config.email_client.client();
 
-        let timeout = config.email_client.timeout();
-
-        let email_client = EmailClient::new(
-                config.email_client.base_url.clone(),
-                sender_email,
-                config.email_client.authorization_token.clone(),
-                timeout
-            );
+        let locales = config.locales.catalogs().context("failed to load the message catalogs")?;
 
         let address = format!("{}:{}",&config.application.host,&config.application.port);
 
@@ -39,15 +33,33 @@ impl Application {
 
         let port = listener.local_addr().unwrap().port();
 
-        let server = run(listener, connection_pool, email_client, format!("{}",&config.application.base_url))?;
-
-        Ok(Self {port, server})
+        let server = run(
+            listener,
+            connection_pool,
+            email_client.clone(),
+            config.application.base_url.clone(),
+            config.application.hmac_secret.clone(),
+            config.application.newsletter_name.clone(),
+            config.application.trusted_proxies.clone(),
+            config.sunset.clone(),
+            config.reminders.clone(),
+            config.feed_watcher.clone(),
+            locales
+        )?;
+
+        Ok(Self {port, server, email_client})
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// The client the server sends with. Its clones share the throttle and
+    /// the circuit breaker.
+    pub fn email_client(&self) -> &EmailClient {
+        &self.email_client
+    }
+
     pub async fn run_until_stopped(self) -> Result<(),std::io::Error> {
         self.server.await
     }
@@ -55,27 +67,125 @@ impl Application {
 
 pub struct ApplicationBaseUrl(pub String);
 
+pub struct NewsletterName(pub String);
+
+#[derive(Clone)]
+pub struct HmacSecret(pub Secret<String>);
+
+pub struct TrustedProxies(pub Vec<IpAddr>);
+
+/// Csv imports are read into memory, so keep them to a sane size.
+const MAX_IMPORT_SIZE: usize = 16 * 1024 * 1024;
+
 pub fn get_connection_pool(conf: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new()
         .connect_timeout(std::time::Duration::from_secs(2))
         .connect_lazy_with(conf.with_db())
 }
 
-fn run(listener: TcpListener, connection_pool: PgPool, email_client: EmailClient, base_url: String) -> Result<Server, std::io::Error> {
+#[allow(clippy::too_many_arguments)]
+fn run(
+    listener: TcpListener,
+    connection_pool: PgPool,
+    email_client: EmailClient,
+    base_url: String,
+    hmac_secret: Secret<String>,
+    newsletter_name: String,
+    trusted_proxies: Vec<IpAddr>,
+    sunset: SunsetSettings,
+    reminders: ReminderSettings,
+    feed_watcher: FeedWatcherSettings,
+    locales: Locales
+) -> Result<Server, std::io::Error> {
     let db_pool = web::Data::new(connection_pool);
     let email_client = web::Data::new(email_client);
     let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let hmac_secret = Data::new(HmacSecret(hmac_secret));
+    let newsletter_name = Data::new(NewsletterName(newsletter_name));
+    let trusted_proxies = Data::new(TrustedProxies(trusted_proxies));
+    let sunset = Data::new(sunset);
+    let reminders = Data::new(reminders);
+    let feed_watcher = Data::new(feed_watcher);
+    let locales = Data::new(locales);
 
     let server = HttpServer::new(move || {
         App::new()
             .wrap(TracingLogger::default())
             .route("/health_check",web::get().to(health_check))
+            .route("/health_check/ready",web::get().to(readiness))
+            .route("/metrics",web::get().to(metrics))
             .route("/subscriptions",web::post().to(subscribe))
             .route("/subscriptions/confirm",web::get().to(confirm))
+            .route("/subscriptions/stay/{subscriber_id}/{signature}",web::get().to(stay_subscribed))
+            .route("/subscriptions/email/confirm",web::get().to(confirm_new_email))
             .route("/newsletters",web::post().to(publish_newsletter))
+            .route("/o/{delivery_token}",web::get().to(track_open))
+            .route("/r/{delivery_token}/{signature}",web::get().to(track_click))
+            .route("/unsubscribe/{delivery_token}",web::get().to(unsubscribe_page))
+            .route("/unsubscribe/{delivery_token}",web::post().to(unsubscribe))
+            .route("/archive",web::get().to(archive_index))
+            .route("/archive/{slug}",web::get().to(archive_issue))
+            .route("/feed.rss",web::get().to(rss_feed))
+            .route("/feed.atom",web::get().to(atom_feed))
+            .route("/preferences/{subscriber_id}/{signature}",web::get().to(preferences_page))
+            .route("/preferences/{subscriber_id}/{signature}",web::post().to(update_preferences_form))
+            .route("/webhooks/postmark",web::post().to(postmark_webhook))
+            .service(
+                web::scope("/admin")
+                    .route("/subscribers/export",web::get().to(export_subscribers))
+                    .route("/subscribers/{subscriber_id}/consent",web::get().to(subscriber_consent))
+                    .route("/subscribers/{subscriber_id}/engagement",web::get().to(subscriber_engagement))
+                    .route("/subscribers/{subscriber_id}/attributes",web::put().to(update_subscriber_attributes))
+                    .route("/subscribers/{subscriber_id}/tags",web::get().to(subscriber_tags))
+                    .route("/subscribers/{subscriber_id}/tags/{tag}",web::put().to(tag_subscriber))
+                    .route("/subscribers/{subscriber_id}/tags/{tag}",web::delete().to(untag_subscriber))
+                    .route("/subscribers/sunset",web::post().to(sunset_subscribers))
+                    .route("/subscribers/reminders",web::post().to(remind_pending_subscribers))
+                    .route("/tags",web::get().to(list_tags))
+                    .route("/topics",web::get().to(topic_list))
+                    .route("/topics/{tag}",web::put().to(put_topic))
+                    .route("/topics/{tag}",web::delete().to(remove_topic))
+                    .route("/digests/weekly",web::post().to(send_weekly_digest))
+                    .route("/segments",web::get().to(segment_list))
+                    .route("/segments/{name}",web::get().to(segment_details))
+                    .route("/segments/{name}",web::put().to(put_segment))
+                    .route("/segments/{name}",web::delete().to(remove_segment))
+                    .route("/sequences",web::get().to(sequence_list))
+                    .route("/sequences/run",web::post().to(run_sequences))
+                    .route("/sequences/{name}",web::get().to(sequence_details))
+                    .route("/sequences/{name}",web::put().to(put_sequence))
+                    .route("/sequences/{name}",web::delete().to(remove_sequence))
+                    .route("/sequences/{name}/steps/{day}",web::put().to(put_sequence_step))
+                    .route("/sequences/{name}/steps/{day}",web::delete().to(remove_sequence_step))
+                    .route("/attributes",web::get().to(attribute_definitions))
+                    .route("/attributes",web::post().to(declare_attribute))
+                    .route("/attributes/{name}",web::delete().to(delete_attribute))
+                    .route("/analytics/subscribers",web::get().to(subscriber_growth_report))
+                    .route("/issues/{issue_id}/stats",web::get().to(issue_stats))
+                    .route("/issues/{issue_id}/stats.csv",web::get().to(issue_stats_csv))
+                    .route("/issues/{issue_id}/visibility",web::put().to(issue_visibility))
+                    .route("/drafts",web::get().to(draft_list))
+                    .route("/drafts/{draft_id}",web::delete().to(remove_draft))
+                    .route("/drafts/{draft_id}/publish",web::post().to(publish_draft_issue))
+                    .route("/feed_watcher/run",web::post().to(run_feed_watcher))
+                    .route("/data_subjects/access",web::get().to(data_subject_access))
+                    .route("/data_subjects/erasure",web::post().to(data_subject_erasure))
+                    .service(
+                        web::resource("/subscribers/import")
+                            .app_data(web::PayloadConfig::new(MAX_IMPORT_SIZE))
+                            .route(web::post().to(import_subscribers_csv))
+                    )
+            )
             .app_data(db_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(newsletter_name.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(sunset.clone())
+            .app_data(reminders.clone())
+            .app_data(feed_watcher.clone())
+            .app_data(locales.clone())
         })
         .listen(listener)?
         .run();
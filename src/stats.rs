@@ -0,0 +1,153 @@
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Serialize)]
+pub struct IssueStats {
+    pub issue_id: Uuid,
+    pub title: String,
+    pub recipients: i64,
+    pub sent: i64,
+    pub failed: i64,
+    pub bounced: i64,
+    pub complaints: i64,
+    pub unique_opens: i64,
+    pub unique_clicks: i64,
+    pub unsubscribes: i64,
+    pub links: Vec<LinkStats>,
+}
+
+#[derive(Serialize)]
+pub struct LinkStats {
+    pub url: String,
+    pub clicks: i64,
+    pub unique_clicks: i64,
+}
+
+#[tracing::instrument(name = "compute issue statistics", skip(pool))]
+pub async fn fetch_issue_stats(pool: &PgPool, issue_id: Uuid) -> Result<Option<IssueStats>, sqlx::Error> {
+    let issue = sqlx::query!(
+            r#"
+                SELECT
+                    i.title,
+                    count(d.id) AS "recipients!",
+                    count(d.sent_at) AS "sent!",
+                    count(d.failed_at) AS "failed!",
+                    count(d.bounced_at) AS "bounced!",
+                    count(d.complained_at) AS "complaints!",
+                    count(d.first_opened_at) AS "unique_opens!",
+                    count(d.unsubscribed_at) AS "unsubscribes!",
+                    (
+                        SELECT count(DISTINCT c.delivery_id)
+                        FROM link_clicks c
+                        JOIN issue_deliveries cd ON cd.id = c.delivery_id
+                        WHERE cd.newsletter_issue_id = i.id
+                    ) AS "unique_clicks!"
+                FROM newsletter_issues i
+                LEFT JOIN issue_deliveries d ON d.newsletter_issue_id = i.id
+                WHERE i.id = $1
+                GROUP BY i.id
+            "#,
+            issue_id
+        )
+        .fetch_optional(pool)
+        .await?;
+
+    let issue = match issue {
+        Some(issue) => issue,
+        None => return Ok(None),
+    };
+
+    let links = sqlx::query_as!(
+            LinkStats,
+            r#"
+                SELECT c.url, count(*) AS "clicks!", count(DISTINCT c.delivery_id) AS "unique_clicks!"
+                FROM link_clicks c
+                JOIN issue_deliveries d ON d.id = c.delivery_id
+                WHERE d.newsletter_issue_id = $1
+                GROUP BY c.url
+                ORDER BY count(*) DESC, c.url
+            "#,
+            issue_id
+        )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(Some(IssueStats {
+        issue_id,
+        title: issue.title,
+        recipients: issue.recipients,
+        sent: issue.sent,
+        failed: issue.failed,
+        bounced: issue.bounced,
+        complaints: issue.complaints,
+        unique_opens: issue.unique_opens,
+        unique_clicks: issue.unique_clicks,
+        unsubscribes: issue.unsubscribes,
+        links,
+    }))
+}
+
+/// One `metric,url,value` row per figure; `url` is only set for the per-link
+/// click counts.
+pub fn write_csv<W: std::io::Write>(writer: W, stats: &IssueStats) -> Result<(), csv::Error> {
+    let mut writer = csv::Writer::from_writer(writer);
+
+    writer.write_record(["metric", "url", "value"])?;
+
+    let totals = [
+        ("recipients", stats.recipients),
+        ("sent", stats.sent),
+        ("failed", stats.failed),
+        ("bounced", stats.bounced),
+        ("complaints", stats.complaints),
+        ("unique_opens", stats.unique_opens),
+        ("unique_clicks", stats.unique_clicks),
+        ("unsubscribes", stats.unsubscribes),
+    ];
+
+    for (metric, value) in totals {
+        writer.write_record([metric, "", &value.to_string()])?;
+    }
+
+    for link in &stats.links {
+        writer.write_record(["link_clicks", &link.url, &link.clicks.to_string()])?;
+        writer.write_record(["link_unique_clicks", &link.url, &link.unique_clicks.to_string()])?;
+    }
+
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+    use crate::stats::{write_csv, IssueStats, LinkStats};
+
+    #[test]
+    fn the_csv_has_one_row_per_figure() {
+        let stats = IssueStats {
+            issue_id: Uuid::new_v4(),
+            title: "Issue #1".into(),
+            recipients: 3,
+            sent: 3,
+            failed: 0,
+            bounced: 0,
+            complaints: 0,
+            unique_opens: 2,
+            unique_clicks: 1,
+            unsubscribes: 1,
+            links: vec![LinkStats { url: "https://example.com".into(), clicks: 2, unique_clicks: 1 }],
+        };
+
+        let mut csv = vec![];
+        write_csv(&mut csv, &stats).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[1], "recipients,,3");
+        assert_eq!(lines[9], "link_clicks,https://example.com,2");
+    }
+}
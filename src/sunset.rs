@@ -0,0 +1,183 @@
+//! The sunset policy: subscribers who ignored the last
+//! `inactive_after_issues` issues get a re-engagement email, and are moved to
+//! `inactive` when they ignore that too for `grace_period_days`. Inactive
+//! subscribers only receive issues sent with `include_inactive`.
+
+use anyhow::Context;
+use chrono::{Duration, Utc};
+use secrecy::Secret;
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+use crate::configuration::SunsetSettings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::preferences::preferences_url;
+use crate::signing;
+
+#[derive(Serialize, Debug)]
+pub struct SunsetReport {
+    pub dry_run: bool,
+    pub reengagement_sent: usize,
+    pub reengagement_failed: usize,
+    pub deactivated: usize,
+}
+
+/// The link of the re-engagement email. It is signed, so that nobody can keep
+/// someone else's subscription alive.
+pub fn stay_subscribed_url(base_url: &str, secret: &Secret<String>, subscriber_id: Uuid) -> String {
+    let subscriber_id = subscriber_id.to_string();
+    let signature = signing::sign(secret, &["stay", &subscriber_id]);
+
+    format!("{}/subscriptions/stay/{}/{}", base_url, subscriber_id, signature)
+}
+
+pub fn verify_stay_subscribed(secret: &Secret<String>, subscriber_id: Uuid, signature: &str) -> bool {
+    signing::verify(secret, &["stay", &subscriber_id.to_string()], signature)
+}
+
+#[tracing::instrument(name = "apply the sunset policy", skip(pool, email_client, base_url, hmac_secret))]
+pub async fn run_sunset(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    policy: &SunsetSettings,
+    dry_run: bool,
+) -> Result<SunsetReport, anyhow::Error> {
+    // deactivate first, so that subscribers who are sent a re-engagement
+    // email below get their full grace period
+    let grace_period_ended = Utc::now() - Duration::days(policy.grace_period_days);
+
+    let deactivated = if dry_run {
+        sqlx::query!(
+                r#"
+                    SELECT count(*) AS "count!" FROM subscriptions
+                    WHERE status = 'confirmed' AND reengagement_sent_at < $1
+                "#,
+                grace_period_ended
+            )
+            .fetch_one(pool)
+            .await
+            .context("failed to count subscribers to deactivate")?
+            .count as usize
+    } else {
+        sqlx::query!(
+                r#"
+                    UPDATE subscriptions SET status = 'inactive'
+                    WHERE status = 'confirmed' AND reengagement_sent_at < $1
+                "#,
+                grace_period_ended
+            )
+            .execute(pool)
+            .await
+            .context("failed to deactivate subscribers")?
+            .rows_affected() as usize
+    };
+
+    let candidates = sqlx::query!(
+            r#"
+                SELECT s.id, s.email FROM subscriptions s
+                WHERE s.status = 'confirmed'
+                    AND s.reengagement_sent_at IS NULL
+                    AND (s.paused_until IS NULL OR s.paused_until <= now())
+                    AND (
+                        SELECT count(*) FROM issue_deliveries d
+                        WHERE d.subscriber_id = s.id AND d.sent_at > COALESCE(s.last_engaged_at, '-infinity')
+                    ) >= $1
+            "#,
+            policy.inactive_after_issues
+        )
+        .fetch_all(pool)
+        .await
+        .context("failed to find subscribers to re-engage")?;
+
+    let mut report = SunsetReport { dry_run, reengagement_sent: 0, reengagement_failed: 0, deactivated };
+
+    if dry_run {
+        report.reengagement_sent = candidates.len();
+        return Ok(report);
+    }
+
+    for candidate in candidates {
+        let sent = send_reengagement_email(
+            pool,
+            email_client,
+            &stay_subscribed_url(base_url, hmac_secret, candidate.id),
+            &preferences_url(base_url, hmac_secret, candidate.id),
+            candidate.id,
+            candidate.email,
+            policy,
+        ).await;
+
+        match sent {
+            Ok(()) => report.reengagement_sent += 1,
+            Err(e) => {
+                tracing::warn!(error.cause_chain = ?e, "failed to send a re-engagement email");
+                report.reengagement_failed += 1;
+            },
+        }
+    }
+
+    Ok(report)
+}
+
+async fn send_reengagement_email(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    stay_url: &str,
+    preferences_url: &str,
+    subscriber_id: Uuid,
+    email: String,
+    policy: &SunsetSettings,
+) -> Result<(), anyhow::Error> {
+    let recipient = SubscriberEmail::parse(email).map_err(anyhow::Error::msg)?;
+
+    let html_body = format!(
+        "We have not seen you in a while. <br />\
+        Click <a href=\"{}\">here</a> to keep receiving our newsletter. \
+        Otherwise we will stop sending it to you in {} days. <br />\
+        You can also <a href=\"{}\">pause it or change how often you get it</a>.",
+        stay_url, policy.grace_period_days, preferences_url
+    );
+    let text_body = format!(
+        "We have not seen you in a while.\nVisit {} to keep receiving our newsletter. \
+        Otherwise we will stop sending it to you in {} days.\n\
+        You can also pause it or change how often you get it: {}",
+        stay_url, policy.grace_period_days, preferences_url
+    );
+
+    email_client
+        .send_email(&recipient, "Do you still want our newsletter?", &html_body, &text_body)
+        .await
+        .with_context(|| format!("failed to send the re-engagement email to {}", recipient))?;
+
+    sqlx::query!(
+            r#"UPDATE subscriptions SET reengagement_sent_at = $2 WHERE id = $1"#,
+            subscriber_id, Utc::now()
+        )
+        .execute(pool)
+        .await
+        .context("failed to record the re-engagement email")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+    use uuid::Uuid;
+    use crate::sunset::{stay_subscribed_url, verify_stay_subscribed};
+
+    #[test]
+    fn stay_subscribed_links_only_work_for_their_subscriber() {
+        let secret = Secret::new("secret".to_string());
+        let subscriber_id = Uuid::new_v4();
+
+        let url = stay_subscribed_url("https://news.example.com", &secret, subscriber_id);
+        let signature = url.rsplit('/').next().unwrap();
+
+        assert!(verify_stay_subscribed(&secret, subscriber_id, signature));
+        assert!(!verify_stay_subscribed(&secret, Uuid::new_v4(), signature));
+    }
+}
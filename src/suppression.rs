@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use sqlx::{PgExecutor, PgPool};
+
+/// Suppressions are keyed by a hash of the normalised address, so that the
+/// list can outlive the subscriber's data.
+pub fn hash_email(email: &str) -> String {
+    let normalised = email.trim().to_lowercase();
+
+    hex::encode(Sha256::digest(normalised.as_bytes()))
+}
+
+#[tracing::instrument(name = "suppress an email address", skip(executor, email))]
+pub async fn suppress<'e>(executor: impl PgExecutor<'e>, email: &str, reason: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+            r#"
+                INSERT INTO suppressions (email_hash, reason, created_at)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (email_hash) DO NOTHING
+            "#,
+            hash_email(email), reason, Utc::now()
+        )
+        .execute(executor)
+        .await?;
+
+    Ok(())
+}
+
+/// Returns the suppression reason of every address in `emails` that is suppressed,
+/// keyed by the address hash.
+#[tracing::instrument(name = "look up suppressed email addresses", skip(pool, emails))]
+pub async fn suppressed(pool: &PgPool, emails: &[String]) -> Result<HashMap<String, String>, sqlx::Error> {
+    let hashes: Vec<String> = emails.iter().map(|e| hash_email(e)).collect();
+
+    let rows = sqlx::query!(
+            r#"SELECT email_hash, reason FROM suppressions WHERE email_hash = ANY($1)"#,
+            &hashes
+        )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(rows.into_iter().map(|r| (r.email_hash, r.reason)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::suppression::hash_email;
+
+    #[test]
+    fn hashes_ignore_case_and_surrounding_whitespace() {
+        assert_eq!(hash_email("Ursula@Example.com "), hash_email("ursula@example.com"));
+    }
+
+    #[test]
+    fn hashes_do_not_contain_the_address() {
+        assert!(!hash_email("ursula@example.com").contains("ursula"));
+        assert_eq!(hash_email("ursula@example.com").len(), 64);
+    }
+}
@@ -0,0 +1,150 @@
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub subscribers: i64,
+}
+
+/// Tags are lowercased, and made of letters, digits, `_` and `-`, so that
+/// segment expressions can refer to them as `tag:beta-testers`.
+pub fn parse_tag(tag: &str) -> Result<String, String> {
+    let tag = tag.trim().to_lowercase();
+
+    let is_valid = tag.chars().next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if !is_valid {
+        return Err(format!("{} is not a valid tag. use letters, digits, '_' and '-'", tag));
+    }
+
+    Ok(tag)
+}
+
+/// Returns false when the subscriber does not exist.
+#[tracing::instrument(name = "tag a subscriber", skip(pool))]
+pub async fn add_tag(pool: &PgPool, subscriber_id: Uuid, tag: &str) -> Result<bool, sqlx::Error> {
+    let tagged = sqlx::query!(
+            r#"
+                INSERT INTO subscriber_tags (subscriber_id, tag, created_at)
+                SELECT id, $2, $3 FROM subscriptions WHERE id = $1
+                ON CONFLICT DO NOTHING
+            "#,
+            subscriber_id, tag, Utc::now()
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    if tagged > 0 {
+        return Ok(true);
+    }
+
+    // already tagged, or no such subscriber
+    let exists = sqlx::query!(r#"SELECT 1 AS "one!" FROM subscriptions WHERE id = $1"#, subscriber_id)
+        .fetch_optional(pool)
+        .await?
+        .is_some();
+
+    Ok(exists)
+}
+
+#[tracing::instrument(name = "untag a subscriber", skip(pool))]
+pub async fn remove_tag(pool: &PgPool, subscriber_id: Uuid, tag: &str) -> Result<bool, sqlx::Error> {
+    let removed = sqlx::query!(
+            r#"DELETE FROM subscriber_tags WHERE subscriber_id = $1 AND tag = $2"#,
+            subscriber_id, tag
+        )
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(removed > 0)
+}
+
+#[tracing::instrument(name = "fetch the tags of a subscriber", skip(pool))]
+pub async fn fetch_tags(pool: &PgPool, subscriber_id: Uuid) -> Result<Vec<String>, sqlx::Error> {
+    let tags = sqlx::query!(
+            r#"SELECT tag FROM subscriber_tags WHERE subscriber_id = $1 ORDER BY tag"#,
+            subscriber_id
+        )
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .map(|r| r.tag)
+        .collect();
+
+    Ok(tags)
+}
+
+#[tracing::instrument(name = "count subscribers per tag", skip(pool))]
+pub async fn count_tags(pool: &PgPool) -> Result<Vec<TagCount>, sqlx::Error> {
+    sqlx::query_as!(
+            TagCount,
+            r#"SELECT tag, count(*) AS "subscribers!" FROM subscriber_tags GROUP BY tag ORDER BY tag"#
+        )
+        .fetch_all(pool)
+        .await
+}
+
+/// A tag that subscribers can choose themselves from the preference page.
+#[derive(Serialize)]
+pub struct Topic {
+    pub tag: String,
+    pub label: String,
+}
+
+#[tracing::instrument(name = "list topics", skip(pool))]
+pub async fn list_topics(pool: &PgPool) -> Result<Vec<Topic>, sqlx::Error> {
+    sqlx::query_as!(Topic, r#"SELECT tag, label FROM topics ORDER BY label"#)
+        .fetch_all(pool)
+        .await
+}
+
+/// Offers a tag as a topic, or renames it.
+#[tracing::instrument(name = "save a topic", skip(pool))]
+pub async fn save_topic(pool: &PgPool, tag: &str, label: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+            r#"
+                INSERT INTO topics (tag, label, created_at) VALUES ($1, $2, $3)
+                ON CONFLICT (tag) DO UPDATE SET label = EXCLUDED.label
+            "#,
+            tag, label, Utc::now()
+        )
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Stops offering the topic. Subscribers keep the tag.
+#[tracing::instrument(name = "delete a topic", skip(pool))]
+pub async fn delete_topic(pool: &PgPool, tag: &str) -> Result<bool, sqlx::Error> {
+    let deleted = sqlx::query!(r#"DELETE FROM topics WHERE tag = $1"#, tag)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(deleted > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_err;
+    use crate::tags::parse_tag;
+
+    #[test]
+    fn tags_are_lowercased() {
+        assert_eq!(parse_tag(" Beta-Testers "), Ok("beta-testers".to_string()));
+    }
+
+    #[test]
+    fn tags_cannot_contain_spaces_or_punctuation() {
+        assert_err!(parse_tag("beta testers"));
+        assert_err!(parse_tag("tag:beta"));
+        assert_err!(parse_tag(""));
+    }
+}
@@ -0,0 +1,255 @@
+//! A throttle in front of the email provider, which only takes so many
+//! messages a second and an hour. Every send waits for the throttle, which
+//! keeps a token bucket per quota; a `429 Too Many Requests` from the provider
+//! pauses all sends for as long as its `Retry-After` asks. Sends someone is
+//! waiting on give up instead of waiting long (see `acquire_within`).
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The longest pause a `Retry-After` can ask for.
+const MAX_PAUSE: Duration = Duration::from_secs(60 * 60);
+
+pub struct Throttle {
+    state: Mutex<State>,
+}
+
+struct State {
+    per_second: Option<Bucket>,
+    per_hour: Option<Bucket>,
+    paused_until: Option<Instant>,
+    waits: u64,
+    rate_limited: u64,
+}
+
+/// Holds up to `capacity` tokens, one per message, and gets `rate` tokens
+/// back every second.
+struct Bucket {
+    capacity: f64,
+    rate: f64,
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    fn new(limit: u32, window: Duration, now: Instant) -> Self {
+        let capacity = f64::from(limit);
+
+        Self { capacity, rate: capacity / window.as_secs_f64(), tokens: capacity, updated_at: now }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated_at).as_secs_f64();
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated_at = now;
+    }
+
+    /// How long until the bucket can take `messages`. More messages than the
+    /// bucket holds go out once it is full, and leave it in debt.
+    fn wait_for(&self, messages: f64) -> Duration {
+        let missing = messages.min(self.capacity) - self.tokens;
+
+        if missing > 0.0 {
+            Duration::from_secs_f64(missing / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+/// The throttle at one point in time, for the metrics.
+pub struct ThrottleState {
+    pub per_second: Option<BucketState>,
+    pub per_hour: Option<BucketState>,
+    /// How much longer sends are paused after a `429`.
+    pub paused_for: Duration,
+    /// Sends that had to wait for the throttle.
+    pub waits: u64,
+    /// `429`s from the provider.
+    pub rate_limited: u64,
+}
+
+pub struct BucketState {
+    pub limit: u32,
+    pub tokens: f64,
+}
+
+impl Throttle {
+    /// At most `per_second` and `per_hour` messages; 0 is no limit.
+    pub fn new(per_second: u32, per_hour: u32) -> Self {
+        let now = Instant::now();
+        let bucket = |limit: u32, window: Duration| (limit > 0).then(|| Bucket::new(limit, window, now));
+
+        Self {
+            state: Mutex::new(State {
+                per_second: bucket(per_second, Duration::from_secs(1)),
+                per_hour: bucket(per_hour, Duration::from_secs(60 * 60)),
+                paused_until: None,
+                waits: 0,
+                rate_limited: 0,
+            }),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(0, 0)
+    }
+
+    /// Waits until `messages` can go out, and counts them against the quotas.
+    pub async fn acquire(&self, messages: usize) {
+        self.wait(messages, None).await;
+    }
+
+    /// Like `acquire`, but gives up, without counting the messages, if they
+    /// cannot go out within `max_wait`. Returns whether they can go out.
+    pub async fn acquire_within(&self, messages: usize, max_wait: Duration) -> bool {
+        self.wait(messages, Some(Instant::now() + max_wait)).await
+    }
+
+    async fn wait(&self, messages: usize, deadline: Option<Instant>) -> bool {
+        let mut waited = false;
+
+        loop {
+            let now = Instant::now();
+            let wait = match self.state.lock().unwrap().reserve(messages as f64, now) {
+                Ok(()) => return true,
+                Err(wait) => wait,
+            };
+
+            if deadline.is_some_and(|deadline| now + wait > deadline) {
+                return false;
+            }
+
+            if !waited {
+                waited = true;
+                self.state.lock().unwrap().waits += 1;
+            }
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// The provider answered `429`: nothing goes out for `retry_after`.
+    pub fn rate_limited(&self, retry_after: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let until = Instant::now() + retry_after.min(MAX_PAUSE);
+
+        tracing::warn!(retry_after = ?retry_after, "the email provider is rate limiting, pausing sends");
+
+        state.paused_until = Some(state.paused_until.map_or(until, |paused_until| paused_until.max(until)));
+        state.rate_limited += 1;
+    }
+
+    pub fn state(&self) -> ThrottleState {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.refill(now);
+
+        let bucket_state = |bucket: &Bucket| BucketState { limit: bucket.capacity as u32, tokens: bucket.tokens };
+
+        ThrottleState {
+            per_second: state.per_second.as_ref().map(bucket_state),
+            per_hour: state.per_hour.as_ref().map(bucket_state),
+            paused_for: state.paused_until.map_or(Duration::ZERO, |until| until.saturating_duration_since(now)),
+            waits: state.waits,
+            rate_limited: state.rate_limited,
+        }
+    }
+}
+
+impl State {
+    fn buckets(&mut self) -> impl Iterator<Item = &mut Bucket> {
+        self.per_second.iter_mut().chain(self.per_hour.iter_mut())
+    }
+
+    fn refill(&mut self, now: Instant) {
+        self.buckets().for_each(|bucket| bucket.refill(now));
+    }
+
+    /// Takes the tokens for `messages`, or says how long to wait for them.
+    fn reserve(&mut self, messages: f64, now: Instant) -> Result<(), Duration> {
+        if let Some(until) = self.paused_until {
+            if until > now {
+                return Err(until - now);
+            }
+            self.paused_until = None;
+        }
+
+        self.refill(now);
+
+        let wait = self.buckets().map(|bucket| bucket.wait_for(messages)).max().unwrap_or_default();
+        if wait > Duration::ZERO {
+            return Err(wait);
+        }
+
+        self.buckets().for_each(|bucket| bucket.tokens -= messages);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use crate::throttle::Throttle;
+
+    fn reserve(throttle: &Throttle, messages: f64, now: Instant) -> Result<(), Duration> {
+        throttle.state.lock().unwrap().reserve(messages, now)
+    }
+
+    #[test]
+    fn messages_wait_for_the_per_second_quota() {
+        let throttle = Throttle::new(2, 0);
+        let now = Instant::now();
+
+        assert_eq!(reserve(&throttle, 1.0, now), Ok(()));
+        assert_eq!(reserve(&throttle, 1.0, now), Ok(()));
+        assert_eq!(reserve(&throttle, 1.0, now), Err(Duration::from_millis(500)));
+        assert_eq!(reserve(&throttle, 1.0, now + Duration::from_millis(500)), Ok(()));
+    }
+
+    #[test]
+    fn the_hourly_quota_holds_even_when_the_second_one_allows_more() {
+        let throttle = Throttle::new(10, 20);
+        let now = Instant::now();
+
+        assert_eq!(reserve(&throttle, 10.0, now), Ok(()));
+        assert_eq!(reserve(&throttle, 10.0, now + Duration::from_secs(1)), Ok(()));
+
+        let wait = reserve(&throttle, 1.0, now + Duration::from_secs(2)).unwrap_err();
+        assert!(wait > Duration::from_secs(170), "waited only {:?}", wait);
+    }
+
+    #[test]
+    fn batches_larger_than_the_quota_go_out_when_the_bucket_is_full() {
+        let throttle = Throttle::new(10, 0);
+        let now = Instant::now();
+
+        assert_eq!(reserve(&throttle, 30.0, now), Ok(()));
+        // the batch is paid back before anything else goes out
+        assert_eq!(reserve(&throttle, 1.0, now), Err(Duration::from_millis(2100)));
+    }
+
+    #[test]
+    fn a_429_pauses_every_send() {
+        let throttle = Throttle::unlimited();
+
+        throttle.rate_limited(Duration::from_secs(2));
+
+        let wait = reserve(&throttle, 1.0, Instant::now()).unwrap_err();
+        assert!(wait > Duration::from_secs(1) && wait <= Duration::from_secs(2));
+        assert_eq!(reserve(&throttle, 1.0, Instant::now() + Duration::from_secs(2)), Ok(()));
+        assert_eq!(throttle.state().rate_limited, 1);
+    }
+
+    #[tokio::test]
+    async fn acquire_within_gives_up_on_long_waits_without_taking_tokens() {
+        let throttle = Throttle::new(1, 0);
+
+        assert!(throttle.acquire_within(1, Duration::ZERO).await);
+        assert!(!throttle.acquire_within(1, Duration::from_millis(100)).await);
+        assert!(throttle.acquire_within(1, Duration::from_secs(2)).await);
+
+        throttle.rate_limited(Duration::from_secs(60 * 60));
+        assert!(!throttle.acquire_within(1, Duration::from_secs(2)).await);
+    }
+}
@@ -0,0 +1,321 @@
+use chrono::Utc;
+use reqwest::Url;
+use secrecy::Secret;
+use sqlx::PgPool;
+use crate::email_client::Header;
+use crate::engagement::mark_engaged;
+use crate::merge::{render_html, render_text, MergeFields};
+use crate::signing;
+
+/// A transparent 1x1 gif.
+pub const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+pub fn pixel_url(base_url: &str, delivery_token: &str) -> String {
+    format!("{}/o/{}", base_url, delivery_token)
+}
+
+pub fn unsubscribe_url(base_url: &str, delivery_token: &str) -> String {
+    format!("{}/unsubscribe/{}", base_url, delivery_token)
+}
+
+/// The RFC 8058 one-click unsubscribe headers: mail clients `POST`
+/// `List-Unsubscribe=One-Click` to `unsubscribe_url`.
+pub fn list_unsubscribe_headers(unsubscribe_url: &str) -> Vec<Header> {
+    vec![
+        Header { name: "List-Unsubscribe", value: format!("<{}>", unsubscribe_url) },
+        Header { name: "List-Unsubscribe-Post", value: "List-Unsubscribe=One-Click".into() },
+    ]
+}
+
+/// Inserts `fragment` at the end of the body of an html email.
+fn append_to_body(html: &str, fragment: &str) -> String {
+    match html.to_ascii_lowercase().rfind("</body>") {
+        Some(position) => format!("{}{}{}", &html[..position], fragment, &html[position..]),
+        None => format!("{}{}", html, fragment),
+    }
+}
+
+/// Adds the tracking pixel at the end of the body of an html email.
+pub fn inject_pixel(html: &str, pixel_url: &str) -> String {
+    let pixel = format!(
+        r#"<img src="{}" width="1" height="1" alt="" style="display:none" />"#,
+        pixel_url
+    );
+
+    append_to_body(html, &pixel)
+}
+
+/// Adds the preferences and unsubscribe links to both bodies of an issue.
+pub fn add_footer(html: &str, text: &str, unsubscribe_url: &str, preferences_url: &str) -> (String, String) {
+    let html = append_to_body(
+        html,
+        &format!(
+            r#"<p><a href="{}">Manage your preferences</a> or <a href="{}">unsubscribe</a> from this newsletter.</p>"#,
+            preferences_url, unsubscribe_url
+        )
+    );
+    let text = format!(
+        "{}\n\nManage your preferences: {}\nUnsubscribe from this newsletter: {}",
+        text, preferences_url, unsubscribe_url
+    );
+
+    (html, text)
+}
+
+/// Merges the recipient's fields into both bodies of an issue, and points the
+/// links of the html body at the click tracking redirect of their delivery.
+pub fn personalize(
+    html: &str,
+    text: &str,
+    fields: &MergeFields,
+    base_url: &str,
+    secret: &Secret<String>,
+    delivery_token: &str,
+) -> (String, String) {
+    let html = rewrite_links(&render_html(html, fields), base_url, |url| {
+        redirect_url(base_url, secret, delivery_token, url)
+    });
+
+    (html, render_text(text, fields))
+}
+
+/// Links that are not worth tracking, or that must keep working exactly as
+/// written: anything but http(s), and our own unsubscribe and preference links.
+fn is_tracked(url: &str, base_url: &str) -> bool {
+    let is_http = ["http://", "https://"]
+        .iter()
+        .any(|scheme| url.get(..scheme.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme)));
+    let is_ours = ["/unsubscribe/", "/preferences/"]
+        .iter()
+        .any(|path| url.strip_prefix(base_url).is_some_and(|rest| rest.starts_with(path)));
+
+    is_http && !is_ours
+}
+
+/// Calls `rewrite` with the destination of every `href` in the html and
+/// replaces it with the result. Links that are not tracked are left alone.
+pub fn rewrite_links(html: &str, base_url: &str, rewrite: impl Fn(&str) -> String) -> String {
+    let lowercase = html.to_ascii_lowercase();
+    let mut rewritten = String::with_capacity(html.len());
+    let mut position = 0;
+
+    while let Some(offset) = lowercase[position..].find("href=") {
+        let value_start = position + offset + "href=".len();
+
+        let quote = match html[value_start..].chars().next() {
+            Some(quote @ ('"' | '\'')) => quote,
+            _ => {
+                rewritten.push_str(&html[position..value_start]);
+                position = value_start;
+                continue;
+            },
+        };
+
+        let url_start = value_start + 1;
+        let url_end = match html[url_start..].find(quote) {
+            Some(length) => url_start + length,
+            None => break,
+        };
+
+        rewritten.push_str(&html[position..url_start]);
+
+        // hrefs are html-escaped, the destination we sign is not
+        let url = html[url_start..url_end].replace("&amp;", "&");
+        if is_tracked(&url, base_url) {
+            rewritten.push_str(&rewrite(&url));
+        } else {
+            rewritten.push_str(&html[url_start..url_end]);
+        }
+
+        position = url_end;
+    }
+
+    rewritten.push_str(&html[position..]);
+
+    rewritten
+}
+
+/// The signature ties the destination to the delivery, so that the redirect
+/// route cannot be used to send people anywhere else.
+pub fn sign(secret: &Secret<String>, delivery_token: &str, url: &str) -> String {
+    signing::sign(secret, &[delivery_token, url])
+}
+
+pub fn verify(secret: &Secret<String>, delivery_token: &str, url: &str, signature: &str) -> bool {
+    signing::verify(secret, &[delivery_token, url], signature)
+}
+
+pub fn redirect_url(base_url: &str, secret: &Secret<String>, delivery_token: &str, url: &str) -> String {
+    let redirect = format!("{}/r/{}/{}", base_url, delivery_token, sign(secret, delivery_token, url));
+
+    match Url::parse_with_params(&redirect, &[("url", url)]) {
+        Ok(redirect) => redirect.to_string(),
+        // the base url is validated at startup, keep the link working regardless
+        Err(_) => url.to_string(),
+    }
+}
+
+/// Records a click on a tracked link of the delivery with this token.
+#[tracing::instrument(name = "record a click", skip(pool, delivery_token, user_agent))]
+pub async fn record_click(
+    pool: &PgPool,
+    delivery_token: &str,
+    url: &str,
+    user_agent: Option<String>,
+) -> Result<bool, sqlx::Error> {
+    let now = Utc::now();
+    let mut transaction = pool.begin().await?;
+
+    let delivery = sqlx::query!(
+            r#"SELECT id, subscriber_id FROM issue_deliveries WHERE token = $1"#,
+            delivery_token
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    let delivery = match delivery {
+        Some(delivery) => delivery,
+        None => return Ok(false),
+    };
+
+    sqlx::query!(
+            r#"INSERT INTO link_clicks (delivery_id, url, clicked_at, user_agent) VALUES ($1, $2, $3, $4)"#,
+            delivery.id, url, now, user_agent
+        )
+        .execute(&mut transaction)
+        .await?;
+
+    mark_engaged(&mut transaction, delivery.subscriber_id, now).await?;
+
+    transaction.commit().await?;
+
+    Ok(true)
+}
+
+/// Records an open of the delivery with this token. Returns false when the
+/// token is unknown.
+#[tracing::instrument(name = "record an open", skip(pool, delivery_token, user_agent))]
+pub async fn record_open(pool: &PgPool, delivery_token: &str, user_agent: Option<String>) -> Result<bool, sqlx::Error> {
+    let now = Utc::now();
+    let mut transaction = pool.begin().await?;
+
+    let delivery = sqlx::query!(
+            r#"
+                UPDATE issue_deliveries
+                SET open_count = open_count + 1, first_opened_at = COALESCE(first_opened_at, $2)
+                WHERE token = $1
+                RETURNING id, subscriber_id
+            "#,
+            delivery_token, now
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+
+    let delivery = match delivery {
+        Some(delivery) => delivery,
+        None => return Ok(false),
+    };
+
+    sqlx::query!(
+            r#"INSERT INTO delivery_opens (delivery_id, opened_at, user_agent) VALUES ($1, $2, $3)"#,
+            delivery.id, now, user_agent
+        )
+        .execute(&mut transaction)
+        .await?;
+
+    mark_engaged(&mut transaction, delivery.subscriber_id, now).await?;
+
+    transaction.commit().await?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+    use crate::tracking::{add_footer, inject_pixel, redirect_url, rewrite_links, sign, verify};
+
+    #[test]
+    fn the_pixel_goes_before_the_closing_body_tag() {
+        let html = inject_pixel("<html><BODY><p>hi</p></BODY></html>", "https://example.com/o/abc");
+
+        assert!(html.ends_with(r#"<img src="https://example.com/o/abc" width="1" height="1" alt="" style="display:none" /></BODY></html>"#));
+    }
+
+    #[test]
+    fn the_pixel_is_appended_to_html_fragments() {
+        let html = inject_pixel("<p>hi</p>", "https://example.com/o/abc");
+
+        assert!(html.starts_with("<p>hi</p><img"));
+    }
+
+    #[test]
+    fn the_footer_is_added_to_both_bodies() {
+        let (html, text) = add_footer(
+            "<p>hi</p>",
+            "hi",
+            "https://example.com/unsubscribe/abc",
+            "https://example.com/preferences/123/sig"
+        );
+
+        assert!(html.ends_with(r#"<a href="https://example.com/unsubscribe/abc">unsubscribe</a> from this newsletter.</p>"#));
+        assert!(html.contains(r#"<a href="https://example.com/preferences/123/sig">Manage your preferences</a>"#));
+        assert!(text.ends_with("https://example.com/unsubscribe/abc"));
+        assert!(text.contains("Manage your preferences: https://example.com/preferences/123/sig"));
+    }
+
+    #[test]
+    fn http_links_are_rewritten() {
+        let html = rewrite_links(
+            r#"<a href="https://example.com/?a=1&amp;b=2">read</a>"#,
+            "https://news.example.com",
+            |url| format!("tracked:{}", url)
+        );
+
+        assert_eq!(html, r#"<a href="tracked:https://example.com/?a=1&b=2">read</a>"#);
+    }
+
+    #[test]
+    fn mailto_anchor_and_our_own_unsubscribe_and_preference_links_are_left_alone() {
+        let html = r##"<a HREF='mailto:editor@example.com'>mail</a>
+            <a href="https://news.example.com/unsubscribe/abc">unsubscribe</a>
+            <a href="https://news.example.com/preferences/abc/def">preferences</a>
+            <a href="#top">top</a>"##;
+
+        assert_eq!(rewrite_links(html, "https://news.example.com", |_| "tracked".into()), html);
+    }
+
+    #[test]
+    fn other_links_that_mention_unsubscribe_are_rewritten() {
+        let html = r#"<a href="https://example.com/blog/how-to-unsubscribe">read</a>
+            <a href="https://news.example.com/archive/unsubscribe-stats">read</a>"#;
+
+        let rewritten = rewrite_links(html, "https://news.example.com", |_| "tracked".into());
+
+        assert_eq!(rewritten.matches(r#"href="tracked""#).count(), 2);
+    }
+
+    #[test]
+    fn signatures_only_match_their_delivery_and_url() {
+        let secret = Secret::new("secret".to_string());
+        let signature = sign(&secret, "token", "https://example.com");
+
+        assert!(verify(&secret, "token", "https://example.com", &signature));
+        assert!(!verify(&secret, "token", "https://evil.example.com", &signature));
+        assert!(!verify(&secret, "other-token", "https://example.com", &signature));
+        assert!(!verify(&secret, "token", "https://example.com", "not-hex"));
+    }
+
+    #[test]
+    fn redirect_urls_carry_the_destination() {
+        let secret = Secret::new("secret".to_string());
+        let url = redirect_url("https://news.example.com", &secret, "token", "https://example.com/?a=1&b=2");
+
+        assert!(url.starts_with("https://news.example.com/r/token/"));
+        assert!(url.ends_with("?url=https%3A%2F%2Fexample.com%2F%3Fa%3D1%26b%3D2"));
+    }
+}
@@ -0,0 +1,72 @@
+use crate::helpers::spawn_app;
+use chrono::{Duration, Utc};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn get_subscriber_growth(app: &crate::helpers::TestApp, query: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/admin/analytics/subscribers?{}", &app.address, query))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+#[tokio::test]
+async fn growth_counts_signups_confirmations_and_unsubscribes_per_period() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into()).await;
+    app.post_subscriptions("name=nora&email=nora%40example.com".into()).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html).await.unwrap().error_for_status().unwrap();
+
+    let today = Utc::today().naive_utc();
+    let yesterday = today - Duration::days(1);
+    let response = get_subscriber_growth(&app, &format!("interval=day&from={}&to={}", yesterday, today)).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let report: serde_json::Value = response.json().await.unwrap();
+    let series = report["series"].as_array().unwrap();
+
+    assert_eq!(series.len(), 2);
+    assert_eq!(series[0]["signups"], 0);
+    assert_eq!(series[1]["period_start"], today.to_string());
+    assert_eq!(series[1]["signups"], 2);
+    assert_eq!(series[1]["confirmations"], 1);
+    assert_eq!(series[1]["confirmation_rate"], 0.5);
+    assert_eq!(report["totals"]["net_growth"], 1);
+}
+
+#[tokio::test]
+async fn growth_can_be_downloaded_as_csv() {
+    let app = spawn_app().await;
+
+    let today = Utc::today().naive_utc();
+    let response = get_subscriber_growth(&app, &format!("interval=month&from={}&to={}&format=csv", today, today)).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("text/csv; charset=utf-8", response.headers()["Content-Type"]);
+
+    let body = response.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(lines[0], "period_start,signups,confirmations,unsubscribes,confirmation_rate,net_growth");
+    assert_eq!(lines.len(), 2);
+}
+
+#[tokio::test]
+async fn unknown_intervals_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = get_subscriber_growth(&app, "interval=hourly").await;
+
+    assert_eq!(400, response.status().as_u16());
+}
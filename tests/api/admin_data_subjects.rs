@@ -0,0 +1,116 @@
+use crate::helpers::spawn_app;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn access_returns_the_subscription_its_tokens_and_consent() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into()).await;
+
+    let response = app.get_data_subject_access("ursula_le_guin@gmail.com").await;
+    assert_eq!(200, response.status().as_u16());
+
+    let data: serde_json::Value = response.json().await.unwrap();
+    let subscription = &data["subscriptions"][0];
+
+    assert_eq!(subscription["name"], "le guin");
+    assert_eq!(subscription["status"], "pending_confirmation");
+    assert_eq!(subscription["consent"]["source"], "signup");
+    assert_eq!(subscription["tokens"].as_array().unwrap().len(), 1);
+    assert!(data["suppression"].is_null());
+}
+
+#[tokio::test]
+async fn erasure_deletes_the_subscription_and_suppresses_the_address() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into()).await;
+
+    let response = app.post_data_subject_erasure("ursula_le_guin@gmail.com").await;
+    assert_eq!(200, response.status().as_u16());
+
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["subscriptions"], 1);
+    assert_eq!(report["tokens"], 1);
+
+    let remaining = sqlx::query!("SELECT count(*) AS count FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.count, Some(0));
+
+    let response = app.post_subscribers_import(
+        "mode=pending",
+        "email,name\nursula_le_guin@gmail.com,Ursula\n",
+    ).await;
+    let report: serde_json::Value = response.json().await.unwrap();
+
+    assert_eq!(report["imported"], 0);
+    assert_eq!(report["errors"][0]["error"], "suppressed (erased)");
+}
+
+#[tokio::test]
+async fn every_request_is_written_to_the_audit_log() {
+    let app = spawn_app().await;
+
+    app.get_data_subject_access("ursula_le_guin@gmail.com").await;
+    app.post_data_subject_erasure("ursula_le_guin@gmail.com").await;
+
+    let entries = sqlx::query!("SELECT action, actor, subject_hash FROM audit_log ORDER BY created_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+
+    let actions: Vec<_> = entries.iter().map(|e| e.action.as_str()).collect();
+    assert_eq!(actions, vec!["access", "erasure"]);
+    assert!(entries.iter().all(|e| e.actor == app.test_user.username && !e.subject_hash.contains("ursula")));
+}
+
+#[tokio::test]
+async fn invalid_email_addresses_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app.get_data_subject_access("not-an-email").await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn erasure_also_deletes_the_delivery_history() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscribers_import(
+        "mode=confirmed&consent=double%20opt-in%20on%20the%20old%20site",
+        "email,name\nursula@example.com,Ursula\n",
+    ).await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": { "text": "text", "html": "<p>html</p>" }
+    })).await;
+
+    let data: serde_json::Value = app.get_data_subject_access("ursula@example.com").await.json().await.unwrap();
+    assert_eq!(data["subscriptions"][0]["deliveries"][0]["issue_title"], "Newsletter title");
+
+    let report: serde_json::Value = app.post_data_subject_erasure("ursula@example.com").await.json().await.unwrap();
+    assert_eq!(report["deliveries"], 1);
+}
@@ -0,0 +1,323 @@
+use crate::helpers::spawn_app;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn requests_without_credentials_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/admin/subscribers/export", &app.address))
+        .await
+        .expect("failed to execute request");
+
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(r#"Basic realm="admin""#, response.headers()["WWW-Authenticate"]);
+}
+
+#[tokio::test]
+async fn requests_with_a_wrong_password_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/subscribers/export", &app.address))
+        .basic_auth(&app.test_user.username, Some("not-the-password"))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    assert_eq!(401, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn export_can_be_filtered_by_status() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into()).await;
+    app.post_subscribers_import(
+        "mode=confirmed&consent=signed%20up%20at%20a%20conference",
+        "email,name\nnora@example.com,Nora\n",
+    ).await;
+
+    let response = app.get_subscribers_export("status=confirmed").await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!("text/csv; charset=utf-8", response.headers()["Content-Type"]);
+
+    let body = response.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], "email,name,status,subscribed_at");
+    assert!(lines[1].starts_with("nora@example.com,Nora,confirmed,"));
+}
+
+#[tokio::test]
+async fn a_dry_run_reports_row_errors_without_importing_anything() {
+    let app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let csv = "email,name\n\
+        ursula@example.com,Ursula\n\
+        not-an-email,Nora\n\
+        ged@example.com,\n\
+        ursula@example.com,Ursula again\n";
+
+    let response = app.post_subscribers_import("dry_run=true", csv).await;
+
+    assert_eq!(200, response.status().as_u16());
+
+    let report: serde_json::Value = response.json().await.unwrap();
+
+    assert_eq!(report["dry_run"], true);
+    assert_eq!(report["total_rows"], 4);
+    assert_eq!(report["imported"], 1);
+
+    let failed_rows: Vec<_> = report["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["row"].as_u64().unwrap())
+        .collect();
+    assert_eq!(failed_rows, vec![3, 4, 5]);
+
+    let saved = sqlx::query!("SELECT count(*) AS count FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, Some(0));
+}
+
+#[tokio::test]
+async fn addresses_that_differ_only_in_case_are_already_subscribed() {
+    let app = spawn_app().await;
+    let consent = "mode=confirmed&consent=signed%20up%20at%20a%20conference";
+
+    app.post_subscribers_import(consent, "email,name\nnora@example.com,Nora\n").await;
+
+    let csv = "email,name\n\
+        Nora@Example.com,Nora\n\
+        Ursula@example.com,Ursula\n\
+        ursula@EXAMPLE.com,Ursula again\n";
+
+    let response = app.post_subscribers_import(consent, csv).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+
+    let failed_rows: Vec<_> = report["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| (e["row"].as_u64().unwrap(), e["error"].as_str().unwrap()))
+        .collect();
+    assert_eq!(failed_rows, vec![(2, "already subscribed"), (4, "already subscribed")]);
+
+    let saved = sqlx::query!("SELECT count(*) AS count FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, Some(2));
+}
+
+#[tokio::test]
+async fn importing_as_pending_sends_confirmation_emails() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let csv = "email,name\nursula@example.com,Ursula\nnora@example.com,Nora\n";
+
+    let response = app.post_subscribers_import("mode=pending", csv).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT status, source FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(saved.len(), 2);
+    assert!(saved.iter().all(|s| s.status == "pending_confirmation" && s.source == "csv_import"));
+}
+
+#[tokio::test]
+async fn importing_as_confirmed_records_the_consent_attestation() {
+    let app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_subscribers_import(
+        "mode=confirmed&consent=double%20opt-in%20on%20the%20old%20site",
+        "email,name\nursula@example.com,Ursula\n",
+    ).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT status, consent_attestation FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.consent_attestation.as_deref(), Some("double opt-in on the old site"));
+}
+
+#[tokio::test]
+async fn importing_as_confirmed_without_consent_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = app.post_subscribers_import("mode=confirmed", "email,name\nursula@example.com,Ursula\n").await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn cleaned_mailchimp_members_are_skipped_by_later_imports() {
+    let app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let cleaned = "Email Address,First Name,Last Name\nursula@example.com,Ursula,Le Guin\n";
+    let response = app.post_subscribers_import("format=mailchimp&status=cleaned", cleaned).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["suppressed"], 1);
+
+    let response = app.post_subscribers_import(
+        "mode=confirmed&consent=double%20opt-in%20on%20the%20old%20site",
+        "email,name\nursula@example.com,Ursula\nnora@example.com,Nora\n",
+    ).await;
+    let report: serde_json::Value = response.json().await.unwrap();
+
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["errors"][0]["row"], 2);
+}
+
+#[tokio::test]
+async fn unsubscribed_and_cleaned_rows_end_existing_subscriptions() {
+    let app = spawn_app().await;
+
+    let response = app.post_subscribers_import(
+        "mode=confirmed&consent=double%20opt-in%20on%20the%20old%20site",
+        "email,name\nursula@example.com,Ursula\nnora@example.com,Nora\n",
+    ).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let unsubscribed = "Email Address,First Name,Last Name\nUrsula@example.com,Ursula,Le Guin\n";
+    let cleaned = "Email Address,First Name,Last Name\nnora@example.com,Nora,Jemisin\n";
+    for (status, csv) in [("unsubscribed", unsubscribed), ("cleaned", cleaned)] {
+        let dry_run: serde_json::Value = app
+            .post_subscribers_import(&format!("format=mailchimp&status={}&dry_run=true", status), csv)
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(dry_run["unsubscribed"], 1);
+
+        let report: serde_json::Value = app
+            .post_subscribers_import(&format!("format=mailchimp&status={}", status), csv)
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(report["suppressed"], 1);
+        assert_eq!(report["unsubscribed"], 1);
+    }
+
+    let saved = sqlx::query!("SELECT email, status, unsubscribed_at FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved[0].email, "nora@example.com");
+    assert_eq!(saved[0].status, "bounced");
+    assert!(saved[0].unsubscribed_at.is_none());
+    assert_eq!(saved[1].status, "unsubscribed");
+    assert!(saved[1].unsubscribed_at.is_some());
+
+    // already ended, so a second import has nothing to unsubscribe
+    let report: serde_json::Value = app
+        .post_subscribers_import("format=mailchimp&status=unsubscribed", unsubscribed)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["unsubscribed"], 0);
+}
+
+#[tokio::test]
+async fn substack_readers_are_imported_as_confirmed() {
+    let app = spawn_app().await;
+
+    let csv = "email,active_subscription,email_disabled,created_at\n\
+        ursula@example.com,false,false,2021-11-02T09:12:44.000Z\n";
+
+    let response = app.post_subscribers_import("format=substack", csv).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let saved = sqlx::query!("SELECT status, source FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert_eq!(saved.status, "confirmed");
+    assert_eq!(saved.source, "substack");
+}
+
+#[tokio::test]
+async fn the_consent_record_of_a_subscriber_is_exposed_to_admins() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&form=footer".into()).await;
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = app.get_subscriber_consent(&saved.id.to_string()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let consent: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(consent["source"], "signup");
+    assert_eq!(consent["signup_form"], "footer");
+    assert_eq!(consent["consent_ip"], "127.0.0.1");
+    assert!(consent["confirmed_at"].is_null());
+}
+
+#[tokio::test]
+async fn the_consent_record_of_an_unknown_subscriber_is_not_found() {
+    let app = spawn_app().await;
+
+    let response = app.get_subscriber_consent("a5d3fb36-6b4f-4c9d-9e0e-6f5ef6f9b0b1").await;
+
+    assert_eq!(404, response.status().as_u16());
+}
@@ -0,0 +1,92 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::{spawn_app, TestApp};
+
+async fn publish(app: &TestApp, title: &str, public: bool) -> serde_json::Value {
+    app.post_newsletters(serde_json::json!({
+            "title": title,
+            "public": public,
+            "content": {
+                "text": "Hello {{ name | reader }}, this is what happened this week.",
+                "html": r#"<p>Hello {{ name | reader }}, read <a href="https://example.com/post">the post</a>.</p>"#,
+            }
+        }))
+        .await
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn get(app: &TestApp, path: &str) -> reqwest::Response {
+    reqwest::get(format!("{}{}", &app.address, path)).await.unwrap()
+}
+
+#[tokio::test]
+async fn sent_issues_are_listed_and_rendered_without_recipient_links() {
+    let app = spawn_app().await;
+    app.post_subscribers_import("mode=confirmed&consent=imported", "email,name\nursula@example.com,Ursula\n").await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let issue = publish(&app, "Issue #1: Hello world", true).await;
+    assert_eq!(issue["slug"], "issue-1-hello-world");
+
+    let index = get(&app, "/archive").await.text().await.unwrap();
+    assert!(index.contains(r#"href="/archive/issue-1-hello-world""#));
+
+    let response = get(&app, "/archive/issue-1-hello-world").await;
+    assert_eq!(200, response.status().as_u16());
+    let page = response.text().await.unwrap();
+
+    assert!(page.contains("Hello reader"));
+    assert!(!page.contains("Ursula"));
+    assert!(page.contains(r#"<a href="https://example.com/post">the post</a>"#));
+    assert!(!page.contains("/r/") && !page.contains("/o/") && !page.contains("/unsubscribe/") && !page.contains("/preferences/"));
+
+    assert!(page.contains(r#"<meta property="og:title" content="Issue #1: Hello world" />"#));
+    assert!(page.contains(r#"<meta property="og:url" content="http://127.0.0.1/archive/issue-1-hello-world" />"#));
+    assert!(page.contains(r#"<meta name="twitter:description" content="Hello reader, this is what happened this week." />"#));
+}
+
+#[tokio::test]
+async fn private_issues_are_not_in_the_archive() {
+    let app = spawn_app().await;
+
+    publish(&app, "Members only", false).await;
+
+    assert!(!get(&app, "/archive").await.text().await.unwrap().contains("members-only"));
+    assert_eq!(404, get(&app, "/archive/members-only").await.status().as_u16());
+}
+
+#[tokio::test]
+async fn issues_can_be_made_private_after_they_are_sent() {
+    let app = spawn_app().await;
+    let issue = publish(&app, "Oops", true).await;
+
+    let response = reqwest::Client::new()
+        .put(format!("{}/admin/issues/{}/visibility", &app.address, issue["issue_id"].as_str().unwrap()))
+        .json(&serde_json::json!({ "public": false }))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(204, response.status().as_u16());
+
+    assert_eq!(404, get(&app, "/archive/oops").await.status().as_u16());
+}
+
+#[tokio::test]
+async fn issues_with_the_same_title_get_different_slugs() {
+    let app = spawn_app().await;
+
+    let first = publish(&app, "Weekly notes", true).await;
+    let second = publish(&app, "Weekly notes", true).await;
+
+    assert_eq!(first["slug"], "weekly-notes");
+    assert_ne!(first["slug"], second["slug"]);
+    let slug = second["slug"].as_str().unwrap();
+    assert_eq!(200, get(&app, &format!("/archive/{}", slug)).await.status().as_u16());
+}
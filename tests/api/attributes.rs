@@ -0,0 +1,158 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+async fn declare_attribute(app: &TestApp, name: &str, kind: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/admin/attributes", &app.address))
+        .json(&serde_json::json!({ "name": name, "type": kind }))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+async fn put_subscriber_attributes(app: &TestApp, subscriber_id: uuid::Uuid, body: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new()
+        .put(format!("{}/admin/subscribers/{}/attributes", &app.address, subscriber_id))
+        .json(&body)
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+async fn stored_attributes(app: &TestApp) -> serde_json::Value {
+    sqlx::query!("SELECT attributes FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .attributes
+}
+
+#[tokio::test]
+async fn declared_attributes_are_listed() {
+    let app = spawn_app().await;
+
+    assert_eq!(200, declare_attribute(&app, "company", "string").await.status().as_u16());
+    assert_eq!(200, declare_attribute(&app, "seats", "number").await.status().as_u16());
+
+    let definitions: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/admin/attributes", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        definitions,
+        serde_json::json!([{ "name": "company", "type": "string" }, { "name": "seats", "type": "number" }])
+    );
+}
+
+#[tokio::test]
+async fn invalid_attribute_definitions_are_rejected() {
+    let app = spawn_app().await;
+
+    assert_eq!(400, declare_attribute(&app, "email", "string").await.status().as_u16());
+    assert_eq!(400, declare_attribute(&app, "Company Name", "string").await.status().as_u16());
+    assert_eq!(400, declare_attribute(&app, "company", "text").await.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribe_stores_declared_form_fields_as_typed_attributes() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    declare_attribute(&app, "company", "string").await;
+    declare_attribute(&app, "seats", "number").await;
+
+    let response = app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&company=Earthsea&seats=3&utm_source=twitter".into()
+    ).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(stored_attributes(&app).await, serde_json::json!({ "company": "Earthsea", "seats": 3 }));
+}
+
+#[tokio::test]
+async fn subscribe_returns_400_when_a_form_field_has_the_wrong_type() {
+    let app = spawn_app().await;
+
+    declare_attribute(&app, "seats", "number").await;
+
+    let response = app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&seats=a%20few".into()
+    ).await;
+
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn admins_can_update_and_clear_the_attributes_of_a_subscriber() {
+    let app = spawn_app().await;
+
+    declare_attribute(&app, "company", "string").await;
+    declare_attribute(&app, "beta", "bool").await;
+    app.post_subscribers_import("mode=confirmed&consent=imported", "email,name\nursula@example.com,Ursula\n").await;
+
+    let subscriber_id = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+
+    let response = put_subscriber_attributes(&app, subscriber_id, serde_json::json!({ "company": "Earthsea", "beta": true })).await;
+    assert_eq!(204, response.status().as_u16());
+
+    put_subscriber_attributes(&app, subscriber_id, serde_json::json!({ "beta": null })).await;
+    assert_eq!(stored_attributes(&app).await, serde_json::json!({ "company": "Earthsea" }));
+
+    let response = put_subscriber_attributes(&app, subscriber_id, serde_json::json!({ "beta": "yes" })).await;
+    assert_eq!(400, response.status().as_u16());
+
+    let response = put_subscriber_attributes(&app, subscriber_id, serde_json::json!({ "plan": "pro" })).await;
+    assert_eq!(400, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn attributes_are_merged_into_issues() {
+    let app = spawn_app().await;
+
+    declare_attribute(&app, "company", "string").await;
+    app.post_subscribers_import("mode=confirmed&consent=imported", "email,name\nursula@example.com,Ursula\n").await;
+    sqlx::query!(r#"UPDATE subscriptions SET attributes = '{"company": "Earth & Sea"}'"#)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "News for {{ company }}",
+        "content": {
+            "text": "Hi {{ name }} of {{ company }}, on the {{ plan | free }} plan",
+            "html": "<p>Hi {{ name }} of {{ company }}</p>",
+        }
+    })).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+    assert_eq!(body["subject"], "News for Earth & Sea");
+    assert!(body["text_body"].as_str().unwrap().starts_with("Hi Ursula of Earth & Sea, on the free plan"));
+    assert!(body["html_body"].as_str().unwrap().starts_with("<p>Hi Ursula of Earth &amp; Sea</p>"));
+}
@@ -0,0 +1,181 @@
+use std::time::Duration;
+use newsletter::domain::SubscriberEmail;
+use newsletter::email_client::EmailClient;
+use newsletter::outbox::flush_outbox;
+use secrecy::Secret;
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::{spawn_app_with, TestApp};
+
+/// An app whose circuit opens after `threshold` failed sends, for a minute.
+async fn spawn_app_with_circuit(threshold: u32) -> TestApp {
+    spawn_app_with(|config| {
+        config.email_client.circuit_failure_threshold = threshold;
+        config.email_client.circuit_open_seconds = 60;
+    }).await
+}
+
+async fn subscribe(app: &TestApp, name: &str) -> u16 {
+    app.post_subscriptions(format!("name={}&email={}%40example.com", name, name))
+        .await
+        .status()
+        .as_u16()
+}
+
+async fn readiness(app: &TestApp) -> serde_json::Value {
+    reqwest::get(format!("{}/health_check/ready", &app.address))
+        .await
+        .expect("failed to execute request")
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn signups_are_queued_once_the_circuit_opens() {
+    let app = spawn_app_with_circuit(2).await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    assert_eq!(subscribe(&app, "anne").await, 500);
+    assert_eq!(subscribe(&app, "bob").await, 500);
+    // the provider is not called again
+    assert_eq!(subscribe(&app, "carol").await, 200);
+
+    let queued = sqlx::query!("SELECT recipient FROM email_outbox")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].recipient, "carol@example.com");
+
+    let readiness = readiness(&app).await;
+    assert_eq!(readiness["database"], "up");
+    assert_eq!(readiness["email_provider"]["circuit"], "open");
+    assert_eq!(readiness["queued_emails"], 1);
+}
+
+#[tokio::test]
+async fn queued_emails_are_sent_when_the_provider_is_back() {
+    let app = spawn_app_with_circuit(1).await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+
+    subscribe(&app, "anne").await;
+    assert_eq!(subscribe(&app, "bob").await, 200);
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // a client of its own, whose circuit is closed
+    let email_client = EmailClient::new(
+        app.email_server.uri(),
+        SubscriberEmail::parse("newsletter@example.com".into()).unwrap(),
+        Secret::new("token".into()),
+        Duration::from_secs(2),
+    );
+    let report = flush_outbox(&app.db_pool, &email_client).await.unwrap();
+
+    assert_eq!(report.sent, 1);
+    let sent = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let sent: serde_json::Value = serde_json::from_slice(&sent.body).unwrap();
+    assert_eq!(sent["to"], "bob@example.com");
+
+    let readiness = readiness(&app).await;
+    assert_eq!(readiness["queued_emails"], 0);
+}
+
+async fn post_admin(app: &TestApp, path: &str) -> serde_json::Value {
+    reqwest::Client::new()
+        .post(format!("{}/admin/{}", &app.address, path))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request")
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn reminders_and_sequence_steps_are_queued_once_the_circuit_opens() {
+    let app = spawn_app_with_circuit(1).await;
+    let client = reqwest::Client::new();
+
+    for (url, body) in [
+        ("sequences/welcome", serde_json::json!({"active": true})),
+        ("sequences/welcome/steps/0", serde_json::json!({"subject": "Welcome", "text": "Hi", "html": "<p>Hi</p>"})),
+    ] {
+        client.put(format!("{}/admin/{}", &app.address, url))
+            .json(&body)
+            .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .up_to_n_times(2)
+        .mount(&app.email_server)
+        .await;
+
+    // anne stays pending, bob confirms and is enrolled in the sequence
+    subscribe(&app, "anne").await;
+    subscribe(&app, "bob").await;
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    reqwest::get(app.get_confirmation_links(&email_request).html).await.unwrap().error_for_status().unwrap();
+    sqlx::query!(
+            "UPDATE subscriptions SET subscribed_at = now() - interval '10 days' WHERE email = 'anne@example.com'"
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    assert_eq!(subscribe(&app, "carol").await, 500);
+
+    assert_eq!(post_admin(&app, "subscribers/reminders").await["sent"], 1);
+    assert_eq!(post_admin(&app, "sequences/run").await["sent"], 1);
+
+    let queued = sqlx::query!("SELECT recipient, subject FROM email_outbox ORDER BY recipient")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.len(), 2);
+    assert_eq!(queued[0].recipient, "anne@example.com");
+    assert_eq!(queued[1].recipient, "bob@example.com");
+    assert_eq!(queued[1].subject, "Welcome");
+}
+
+#[tokio::test]
+async fn readiness_reports_a_closed_circuit_at_first() {
+    let app = spawn_app_with_circuit(5).await;
+
+    let readiness = readiness(&app).await;
+
+    assert_eq!(readiness["database"], "up");
+    assert_eq!(readiness["email_provider"]["circuit"], "closed");
+    assert_eq!(readiness["email_provider"]["consecutive_failures"], 0);
+}
@@ -0,0 +1,111 @@
+use crate::helpers::{spawn_app, TestApp};
+use crate::preferences::{post_form, preferences_link};
+
+/// Requests a change to `new_email` and returns the confirmation link sent to it.
+async fn request_change(app: &TestApp, link: &reqwest::Url, new_email: &str) -> reqwest::Url {
+    let response = post_form(link, &[("action", "change_email"), ("email", new_email)]).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+    assert_eq!(body["to"], new_email);
+    app.find_link(body["text_body"].as_str().unwrap(), "/subscriptions/email/confirm")
+}
+
+async fn subscriber_email(app: &TestApp) -> String {
+    sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email
+}
+
+#[tokio::test]
+async fn the_address_only_changes_once_the_new_one_is_confirmed() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+
+    let confirmation_link = request_change(&app, &link, "ursula@newjob.example.com").await;
+    assert_eq!(subscriber_email(&app).await, "ursula@example.com");
+
+    let response = reqwest::get(confirmation_link.clone()).await.unwrap();
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(subscriber_email(&app).await, "ursula@newjob.example.com");
+
+    // the token is spent
+    let response = reqwest::get(confirmation_link).await.unwrap();
+    assert_eq!(404, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn invalid_addresses_are_rejected() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+
+    let response = post_form(&link, &[("action", "change_email"), ("email", "not-an-address")]).await;
+
+    assert_eq!(400, response.status().as_u16());
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn addresses_that_are_already_subscribed_are_a_conflict() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+    app.post_subscribers_import("mode=confirmed&consent=imported", "email,name\nle.guin@example.com,Le Guin\n").await;
+
+    let response = post_form(&link, &[("action", "change_email"), ("email", "Le.Guin@example.com")]).await;
+
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn confirming_an_address_that_subscribed_in_the_meantime_is_a_conflict() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+
+    let confirmation_link = request_change(&app, &link, "ursula@newjob.example.com").await;
+    app.post_subscriptions("name=Ursula&email=ursula%40newjob.example.com".into()).await;
+
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    assert_eq!(409, response.status().as_u16());
+    let emails = sqlx::query!("SELECT email FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(emails[0].email, "ursula@example.com");
+    assert_eq!(emails[1].email, "ursula@newjob.example.com");
+}
+
+#[tokio::test]
+async fn confirming_an_address_that_subscribed_in_the_meantime_in_other_capitals_is_a_conflict() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+
+    let confirmation_link = request_change(&app, &link, "ursula@newjob.example.com").await;
+    app.post_subscriptions("name=Ursula&email=Ursula%40NewJob.example.com".into()).await;
+
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    assert_eq!(409, response.status().as_u16());
+    let moved = sqlx::query!(r#"SELECT count(*) AS "count!" FROM subscriptions WHERE email = 'ursula@newjob.example.com'"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(moved.count, 0);
+}
+
+#[tokio::test]
+async fn only_the_latest_change_can_be_confirmed() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+
+    let first = request_change(&app, &link, "ursula@typo.example.com").await;
+    let second = request_change(&app, &link, "ursula@newjob.example.com").await;
+
+    assert_eq!(404, reqwest::get(first).await.unwrap().status().as_u16());
+    assert_eq!(200, reqwest::get(second).await.unwrap().status().as_u16());
+    assert_eq!(subscriber_email(&app).await, "ursula@newjob.example.com");
+}
@@ -0,0 +1,233 @@
+use newsletter::configuration::FeedWatcherMode;
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+fn atom(entries: &[(&str, &str)]) -> String {
+    let entries: String = entries
+        .iter()
+        .map(|(id, title)| format!(
+            r#"<entry><id>urn:post:{id}</id><title>{title}</title>
+            <link href="https://blog.example.com/{id}" /><summary>All about {title}.</summary></entry>"#,
+            id = id, title = title
+        ))
+        .collect();
+
+    format!(r#"<?xml version="1.0" encoding="utf-8"?><feed xmlns="http://www.w3.org/2005/Atom"><title>Blog</title>{}</feed>"#, entries)
+}
+
+fn rss(items: &[(&str, &str)]) -> String {
+    let items: String = items
+        .iter()
+        .map(|(id, title)| format!(
+            "<item><guid>{id}</guid><title>{title}</title><link>https://blog.example.com/{id}</link></item>",
+            id = id, title = title
+        ))
+        .collect();
+
+    format!(r#"<rss version="2.0"><channel><title>Blog</title>{}</channel></rss>"#, items)
+}
+
+async fn serve_feed(feed_server: &MockServer, body: String) {
+    feed_server.reset().await;
+
+    Mock::given(path("/blog.atom"))
+        .and(method("GET"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(body))
+        .mount(feed_server)
+        .await;
+}
+
+async fn run_feed_watcher(app: &TestApp) -> serde_json::Value {
+    reqwest::Client::new()
+        .post(format!("{}/admin/feed_watcher/run", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request")
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn list_drafts(app: &TestApp) -> Vec<serde_json::Value> {
+    reqwest::Client::new()
+        .get(format!("{}/admin/drafts", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request")
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn publish_draft(app: &TestApp, draft_id: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/admin/drafts/{}/publish", &app.address, draft_id))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+/// Signs up and confirms a subscriber, as a subscriber would.
+async fn confirm_subscriber(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into()).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html).await.unwrap().error_for_status().unwrap();
+}
+
+async fn emails(app: &TestApp) -> Vec<serde_json::Value> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn new_feed_entries_become_drafts_that_can_be_published() {
+    let feed_server = MockServer::start().await;
+    serve_feed(&feed_server, atom(&[("1", "Hello world")])).await;
+    let source = format!("{}/blog.atom", feed_server.uri());
+    let app = spawn_app_with(|config| config.feed_watcher.source = Some(source)).await;
+    confirm_subscriber(&app).await;
+
+    // the entries already in the feed are only remembered
+    let report = run_feed_watcher(&app).await;
+    assert_eq!(report["new_entries"], 0);
+    assert!(list_drafts(&app).await.is_empty());
+
+    serve_feed(&feed_server, atom(&[("2", "Faster builds"), ("1", "Hello world")])).await;
+
+    let report = run_feed_watcher(&app).await;
+    assert_eq!(report["new_entries"], 1);
+    assert_eq!(report["drafted"], 1);
+    assert_eq!(emails(&app).await.len(), 1, "drafts are not sent");
+
+    let drafts = list_drafts(&app).await;
+    assert_eq!(drafts.len(), 1);
+    assert_eq!(drafts[0]["title"], "Faster builds");
+    assert_eq!(drafts[0]["source"], "https://blog.example.com/2");
+    assert!(drafts[0]["html"].as_str().unwrap().contains(r#"<a href="https://blog.example.com/2">Read more</a>"#));
+    assert!(drafts[0]["text"].as_str().unwrap().contains("All about Faster builds."));
+
+    let draft_id = drafts[0]["id"].as_str().unwrap();
+    let response = publish_draft(&app, draft_id).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let email = emails(&app).await.pop().unwrap();
+    assert_eq!(email["subject"], "Faster builds");
+    assert!(email["html_body"].as_str().unwrap().contains("All about Faster builds."));
+
+    assert!(list_drafts(&app).await.is_empty());
+    assert_eq!(404, publish_draft(&app, draft_id).await.status().as_u16());
+
+    // nothing new the next time around
+    let report = run_feed_watcher(&app).await;
+    assert_eq!(report["new_entries"], 0);
+}
+
+#[tokio::test]
+async fn a_draft_that_cannot_be_published_is_kept() {
+    let feed_server = MockServer::start().await;
+    serve_feed(&feed_server, atom(&[("1", "Hello world")])).await;
+    let source = format!("{}/blog.atom", feed_server.uri());
+    let app = spawn_app_with(|config| config.feed_watcher.source = Some(source)).await;
+    confirm_subscriber(&app).await;
+
+    run_feed_watcher(&app).await;
+    serve_feed(&feed_server, atom(&[("2", "Faster builds"), ("1", "Hello world")])).await;
+    run_feed_watcher(&app).await;
+
+    // an issue needs a title
+    sqlx::query!("UPDATE issue_drafts SET title = ''")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let drafts = list_drafts(&app).await;
+    let draft_id = drafts[0]["id"].as_str().unwrap();
+    assert_eq!(400, publish_draft(&app, draft_id).await.status().as_u16());
+
+    let drafts = list_drafts(&app).await;
+    assert_eq!(drafts.len(), 1);
+    assert_eq!(drafts[0]["id"], draft_id);
+    assert_eq!(emails(&app).await.len(), 1, "only the confirmation was sent");
+
+    let issues = sqlx::query!(r#"SELECT count(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.count, 0);
+}
+
+#[tokio::test]
+async fn new_entries_of_a_local_feed_are_sent_in_send_mode() {
+    let feed_file = std::env::temp_dir().join(format!("{}.rss", Uuid::new_v4()));
+    std::fs::write(&feed_file, rss(&[("1", "Hello world")])).unwrap();
+    let source = feed_file.to_str().unwrap().to_string();
+    let app = spawn_app_with(|config| {
+        config.feed_watcher.source = Some(source);
+        config.feed_watcher.mode = FeedWatcherMode::Send;
+    }).await;
+    confirm_subscriber(&app).await;
+
+    run_feed_watcher(&app).await;
+    std::fs::write(&feed_file, rss(&[("3", "Third post"), ("2", "Second post"), ("1", "Hello world")])).unwrap();
+
+    let report = run_feed_watcher(&app).await;
+    assert_eq!(report["published"], 2);
+
+    // the confirmation email, then the new posts in the order they were written
+    let subjects: Vec<_> = emails(&app).await.into_iter().skip(1).map(|email| email["subject"].clone()).collect();
+    assert_eq!(subjects, vec!["Second post", "Third post"]);
+
+    let report = run_feed_watcher(&app).await;
+    assert_eq!(report["published"], 0);
+    assert_eq!(emails(&app).await.len(), 3);
+
+    std::fs::remove_file(feed_file).unwrap();
+}
+
+#[tokio::test]
+async fn the_feed_watcher_cannot_run_without_a_feed() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/feed_watcher/run", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    assert_eq!(409, response.status().as_u16());
+}
+
+#[tokio::test]
+async fn deleting_an_unknown_draft_is_not_found() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .delete(format!("{}/admin/drafts/{}", &app.address, Uuid::new_v4()))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    assert_eq!(404, response.status().as_u16());
+}
@@ -0,0 +1,84 @@
+use crate::helpers::{spawn_app, TestApp};
+
+async fn publish(app: &TestApp, title: &str, public: bool) -> serde_json::Value {
+    app.post_newsletters(serde_json::json!({
+            "title": title,
+            "public": public,
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn get_feed(app: &TestApp, path: &str, headers: &[(&str, &str)]) -> reqwest::Response {
+    let mut request = reqwest::Client::new().get(format!("{}{}", &app.address, path));
+
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+
+    request.send().await.expect("failed to execute request")
+}
+
+#[tokio::test]
+async fn feeds_carry_the_public_issues_with_their_content() {
+    let app = spawn_app().await;
+    let issue = publish(&app, "Public issue", true).await;
+    publish(&app, "Private issue", false).await;
+
+    let response = get_feed(&app, "/feed.rss", &[]).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(response.headers()["content-type"], "application/rss+xml; charset=utf-8");
+    let rss = response.text().await.unwrap();
+    assert!(rss.contains("<title>Public issue</title>"));
+    assert!(rss.contains(&format!("urn:uuid:{}", issue["issue_id"].as_str().unwrap())));
+    assert!(rss.contains("&lt;p&gt;Newsletter body as HTML&lt;/p&gt;"));
+    assert!(!rss.contains("Private issue"));
+
+    let response = get_feed(&app, "/feed.atom", &[]).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_eq!(response.headers()["content-type"], "application/atom+xml; charset=utf-8");
+    let atom = response.text().await.unwrap();
+    assert!(atom.contains("<title>Public issue</title>"));
+    assert!(!atom.contains("Private issue"));
+}
+
+#[tokio::test]
+async fn unchanged_feeds_are_not_sent_again() {
+    let app = spawn_app().await;
+    publish(&app, "First issue", true).await;
+
+    let response = get_feed(&app, "/feed.rss", &[]).await;
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+    let last_modified = response.headers()["last-modified"].to_str().unwrap().to_string();
+
+    let response = get_feed(&app, "/feed.rss", &[("If-None-Match", &etag)]).await;
+    assert_eq!(304, response.status().as_u16());
+    assert!(response.text().await.unwrap().is_empty());
+
+    let response = get_feed(&app, "/feed.rss", &[("If-Modified-Since", &last_modified)]).await;
+    assert_eq!(304, response.status().as_u16());
+
+    publish(&app, "Second issue", true).await;
+
+    let response = get_feed(&app, "/feed.rss", &[("If-None-Match", &etag)]).await;
+    assert_eq!(200, response.status().as_u16());
+    assert_ne!(response.headers()["etag"].to_str().unwrap(), etag);
+    assert!(response.text().await.unwrap().contains("Second issue"));
+}
+
+#[tokio::test]
+async fn an_empty_archive_is_a_valid_empty_feed() {
+    let app = spawn_app().await;
+
+    let response = get_feed(&app, "/feed.atom", &[]).await;
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.headers().get("last-modified").is_none());
+    assert!(response.text().await.unwrap().contains("<updated>1970-01-01T00:00:00+00:00</updated>"));
+}
@@ -1,6 +1,8 @@
 use newsletter::startup::{get_connection_pool, Application};
 use newsletter::telemetry;
-use newsletter::configuration::{get_configuration, DatabaseSettings};
+use newsletter::configuration::{get_configuration, DatabaseSettings, Settings};
+use newsletter::authentication::create_admin;
+use secrecy::Secret;
 use sqlx::{PgPool, Executor, PgConnection, Connection};
 use uuid::Uuid;
 use once_cell::sync::Lazy;
@@ -11,6 +13,27 @@ pub struct TestApp {
     pub db_pool: PgPool,
     pub email_server: MockServer,
     pub port: u16,
+    pub test_user: TestUser,
+}
+
+pub struct TestUser {
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    async fn store(&self, pool: &PgPool) {
+        create_admin(pool, &self.username, Secret::new(self.password.clone()))
+            .await
+            .expect("failed to store test user");
+    }
 }
 
 pub struct ConfirmationLinks {
@@ -21,7 +44,7 @@ pub struct ConfirmationLinks {
 impl TestApp {
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions",&self.address))
+            .post(format!("{}/subscriptions",&self.address))
             .header("Content-Type","application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -32,13 +55,13 @@ impl TestApp {
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
-        let html = self.get_link(&body["html_body"].as_str().unwrap());
-        let plain_text = self.get_link(&body["text_body"].as_str().unwrap());
+        let html = self.get_link(body["html_body"].as_str().unwrap());
+        let plain_text = self.get_link(body["text_body"].as_str().unwrap());
 
         ConfirmationLinks { html, plain_text }
     }
 
-    fn get_link(&self, s: &str) -> reqwest::Url {
+    pub fn get_link(&self, s: &str) -> reqwest::Url {
         let links: Vec<_> = linkify::LinkFinder::new()
             .links(s)
             .filter(|l| *l.kind() == linkify::LinkKind::Url)
@@ -56,14 +79,77 @@ impl TestApp {
         confirmation_link
     }
 
+    /// The first link in `s` whose path starts with `path_prefix`, pointing at the test app.
+    pub fn find_link(&self, s: &str, path_prefix: &str) -> reqwest::Url {
+        let mut link = linkify::LinkFinder::new()
+            .links(s)
+            .filter(|l| *l.kind() == linkify::LinkKind::Url)
+            .filter_map(|l| reqwest::Url::parse(l.as_str()).ok())
+            .find(|l| l.path().starts_with(path_prefix))
+            .unwrap_or_else(|| panic!("no link to {} in {}", path_prefix, s));
+
+        link.set_port(Some(self.port)).unwrap();
+
+        link
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters",&self.address))
+            .post(format!("{}/newsletters",&self.address))
             .json(&body)
             .send()
             .await
             .expect("failed to execute request")
     }
+
+    pub async fn get_subscribers_export(&self, query: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/subscribers/export?{}",&self.address,query))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_subscribers_import(&self, query: &str, csv: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/subscribers/import?{}",&self.address,query))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Content-Type","text/csv")
+            .body(csv.to_string())
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_subscriber_consent(&self, subscriber_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/subscribers/{}/consent",&self.address,subscriber_id))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn get_data_subject_access(&self, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/data_subjects/access",&self.address))
+            .query(&[("email", email)])
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
+
+    pub async fn post_data_subject_erasure(&self, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/data_subjects/erasure",&self.address))
+            .json(&serde_json::json!({ "email": email }))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("failed to execute request")
+    }
 }
 
 static TRACING: Lazy<()> = Lazy::new(||{
@@ -81,6 +167,11 @@ static TRACING: Lazy<()> = Lazy::new(||{
 
 // Spawns an instance of the app. It binds to a random port.
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+// Spawns an instance of the app with changes to the test configuration.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let email_server = MockServer::start().await;
@@ -92,7 +183,9 @@ pub async fn spawn_app() -> TestApp {
         config.application.port = 0;
 
         config.email_client.base_url = email_server.uri();
-        
+
+        configure(&mut config);
+
         config
     };
 
@@ -106,17 +199,21 @@ pub async fn spawn_app() -> TestApp {
 
     let address = format!("http://localhost:{}",port);
 
-    let _ = tokio::spawn(app.run_until_stopped());
+    drop(tokio::spawn(app.run_until_stopped()));
 
     let db_pool = get_connection_pool(&config.database);
 
     println!("address is {}",address);
 
+    let test_user = TestUser::generate();
+    test_user.store(&db_pool).await;
+
     TestApp {
         port,
         address,
         db_pool,
         email_server,
+        test_user,
     }
 }
 
@@ -143,5 +240,5 @@ pub async fn configure_database(config: &DatabaseSettings) -> PgPool {
         .await
         .expect("failed to migrate.");
 
-    return connection_pool;
+    connection_pool
 }
\ No newline at end of file
@@ -0,0 +1,169 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::{spawn_app, TestApp};
+
+/// Signs up and confirms a subscriber whose browser asks for `accept_language`.
+async fn confirm_subscriber(app: &TestApp, email: &str, accept_language: &str) {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept-Language", accept_language)
+        .body(format!("name=reader&email={}", email))
+        .send()
+        .await
+        .expect("failed to execute request")
+        .error_for_status()
+        .unwrap();
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let confirmation_links = app.get_confirmation_links(&email_request);
+    reqwest::get(confirmation_links.html).await.unwrap().error_for_status().unwrap();
+}
+
+async fn mock_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+fn bilingual_issue() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Hello",
+        "content": { "text": "Hello in english", "html": "<p>Hello in english</p>" },
+        "variants": {
+            "FR": {
+                "title": "Bonjour",
+                "content": { "text": "Bonjour en français", "html": "<p>Bonjour en français</p>" }
+            }
+        }
+    })
+}
+
+/// The issue emails sent since the confirmations, by recipient.
+async fn issue_emails(app: &TestApp) -> Vec<(String, serde_json::Value)> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| serde_json::from_slice::<serde_json::Value>(&request.body).unwrap())
+        .filter(|email| email["subject"] != "Welcome!" && email["subject"] != "Bienvenue !")
+        .map(|email| (email["to"].as_str().unwrap().to_string(), email))
+        .collect()
+}
+
+#[tokio::test]
+async fn each_subscriber_gets_the_variant_of_their_locale() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    confirm_subscriber(&app, "anne%40example.com", "fr-FR, fr").await;
+    confirm_subscriber(&app, "bob%40example.com", "en-GB").await;
+
+    let response = app.post_newsletters(bilingual_issue()).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let emails = issue_emails(&app).await;
+    assert_eq!(emails.len(), 2);
+
+    for (to, email) in emails {
+        match to.as_str() {
+            "anne@example.com" => {
+                assert_eq!(email["subject"], "Bonjour");
+                assert!(email["html_body"].as_str().unwrap().contains("Bonjour en français"));
+            },
+            "bob@example.com" => {
+                assert_eq!(email["subject"], "Hello");
+                assert!(email["text_body"].as_str().unwrap().contains("Hello in english"));
+            },
+            other => panic!("unexpected recipient {}", other),
+        }
+    }
+
+    let locales: Vec<Option<String>> = sqlx::query!("SELECT locale FROM issue_deliveries ORDER BY locale")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.locale)
+        .collect();
+    assert_eq!(locales, vec![Some("fr".to_string()), None]);
+}
+
+#[tokio::test]
+async fn digest_subscribers_get_their_variant_in_the_digest() {
+    let app = spawn_app().await;
+    mock_email_server(&app).await;
+    confirm_subscriber(&app, "anne%40example.com", "fr").await;
+
+    sqlx::query!("UPDATE subscriptions SET frequency = 'weekly_digest'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    app.post_newsletters(bilingual_issue()).await.error_for_status().unwrap();
+
+    reqwest::Client::new()
+        .post(format!("{}/admin/digests/weekly", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request")
+        .error_for_status()
+        .unwrap();
+
+    let (_, digest) = issue_emails(&app).await.pop().unwrap();
+    assert!(digest["html_body"].as_str().unwrap().contains("Bonjour en français"));
+}
+
+#[tokio::test]
+async fn every_variant_needs_a_title_a_text_and_an_html_body() {
+    let app = spawn_app().await;
+
+    let test_cases = vec![
+        (
+            serde_json::json!({
+                "title": "Hello",
+                "content": { "text": "Hello", "html": "<p>Hello</p>" },
+                "variants": { "fr": { "title": "Bonjour", "content": { "text": "Bonjour", "html": " " } } }
+            }),
+            "a variant with an empty html body",
+        ),
+        (
+            serde_json::json!({
+                "title": "Hello",
+                "content": { "text": "Hello", "html": "<p>Hello</p>" },
+                "variants": { "fr": { "content": { "text": "Bonjour", "html": "<p>Bonjour</p>" } } }
+            }),
+            "a variant without a title",
+        ),
+        (
+            serde_json::json!({
+                "title": "",
+                "content": { "text": "Hello", "html": "<p>Hello</p>" }
+            }),
+            "an empty default title",
+        ),
+        (
+            serde_json::json!({
+                "title": "Hello",
+                "content": { "text": "Hello", "html": "<p>Hello</p>" },
+                "variants": { "not a locale": { "title": "Bonjour", "content": { "text": "Bonjour", "html": "<p>Bonjour</p>" } } }
+            }),
+            "a variant that is not keyed by a locale",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_newsletters(body).await;
+
+        assert_eq!(400, response.status().as_u16(), "the api accepted {}", description);
+    }
+
+    let issues = sqlx::query!(r#"SELECT count(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.count, 0);
+}
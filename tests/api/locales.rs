@@ -0,0 +1,114 @@
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+const BODY: &str = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+async fn subscribe(app: &TestApp, body: &str, accept_language: &str) -> serde_json::Value {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept-Language", accept_language)
+        .body(body.to_string())
+        .send()
+        .await
+        .expect("failed to execute request")
+        .error_for_status()
+        .unwrap();
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    serde_json::from_slice(&email_request.body).unwrap()
+}
+
+async fn stored_locale(app: &TestApp) -> String {
+    sqlx::query!("SELECT locale FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .locale
+}
+
+#[tokio::test]
+async fn the_browser_language_is_stored_and_used_for_the_confirmation() {
+    let app = spawn_app().await;
+
+    let email = subscribe(&app, BODY, "fr-CA, fr;q=0.9, en;q=0.8").await;
+
+    assert_eq!(email["subject"], "Bienvenue !");
+    assert!(email["text_body"].as_str().unwrap().contains("pour confirmer votre abonnement"));
+    assert_eq!(stored_locale(&app).await, "fr");
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    let page = reqwest::get(confirmation_links.html).await.unwrap().text().await.unwrap();
+    assert!(page.contains("Votre abonnement est confirmé"));
+}
+
+#[tokio::test]
+async fn the_form_locale_wins_over_the_browser() {
+    let app = spawn_app().await;
+
+    let email = subscribe(&app, &format!("{}&locale=fr", BODY), "en-US").await;
+
+    assert_eq!(email["subject"], "Bienvenue !");
+    assert_eq!(stored_locale(&app).await, "fr");
+}
+
+#[tokio::test]
+async fn unknown_locales_fall_back_to_english() {
+    let app = spawn_app().await;
+
+    let email = subscribe(&app, &format!("{}&locale=tlh", BODY), "ja").await;
+
+    assert_eq!(email["subject"], "Welcome!");
+    assert_eq!(stored_locale(&app).await, "en");
+}
+
+#[tokio::test]
+async fn reminders_are_sent_in_the_locale_of_the_subscriber() {
+    let app = spawn_app().await;
+    subscribe(&app, BODY, "fr").await;
+
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = $1", Utc::now() - Duration::days(4))
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    reqwest::Client::new()
+        .post(format!("{}/admin/subscribers/reminders", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request")
+        .error_for_status()
+        .unwrap();
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let email: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(email["subject"], "Merci de confirmer votre abonnement");
+}
+
+#[tokio::test]
+async fn extra_locales_are_read_from_the_locales_directory() {
+    let directory = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    std::fs::create_dir(&directory).unwrap();
+    std::fs::write(directory.join("de.json"), r#"{ "confirmation.subject": "Willkommen!" }"#).unwrap();
+    let locales_directory = directory.to_str().unwrap().to_string();
+    let app = spawn_app_with(|config| config.locales.directory = locales_directory).await;
+
+    let email = subscribe(&app, BODY, "de-DE").await;
+
+    assert_eq!(email["subject"], "Willkommen!");
+    // messages the catalog lacks are in english
+    assert!(email["text_body"].as_str().unwrap().contains("to confirm your subscription"));
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
@@ -2,4 +2,21 @@ mod helpers;
 mod health_check;
 mod subscriptions;
 mod subscriptions_confirm;
-mod newsletter;
\ No newline at end of file
+mod newsletter;
+mod admin_subscribers;
+mod admin_data_subjects;
+mod admin_analytics;
+mod sunset;
+mod attributes;
+mod segments;
+mod preferences;
+mod email_change;
+mod reminders;
+mod sequences;
+mod archive;
+mod feeds;
+mod feed_watcher;
+mod locales;
+mod issue_variants;
+mod throttle;
+mod circuit_breaker;
\ No newline at end of file
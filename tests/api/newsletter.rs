@@ -1,6 +1,9 @@
-use crate::helpers::{spawn_app, TestApp, ConfirmationLinks};
-use wiremock::matchers::{any, method, path};
-use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::{spawn_app, spawn_app_with, TestApp, ConfirmationLinks};
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use uuid::Uuid;
+use wiremock::matchers::{any, body_partial_json, method, path};
+use wiremock::{Mock, Request, ResponseTemplate};
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -87,7 +90,7 @@ async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
         .pop()
         .unwrap();
 
-    app.get_confirmation_links(&email_request)
+    app.get_confirmation_links(email_request)
 }
 
 async fn create_confirmed_subscriber(app: &TestApp) {
@@ -130,4 +133,588 @@ async fn newsletters_returns_400_for_invalid_data() {
             error_msg
         )
     }
-}
\ No newline at end of file
+}
+/// The html body of the last email sent through the mock email server.
+async fn last_html_body(app: &TestApp) -> String {
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+    body["html_body"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn opens_are_recorded_through_the_tracking_pixel() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })).await;
+
+    let html = last_html_body(&app).await;
+    let pixel_url = app.find_link(&html, "/o/");
+
+    for _ in 0..2 {
+        let response = reqwest::Client::new()
+            .get(pixel_url.clone())
+            .header("User-Agent", "Thunderbird")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["Content-Type"], "image/gif");
+    }
+
+    let delivery = sqlx::query!("SELECT sent_at, first_opened_at, open_count FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(delivery.sent_at.is_some());
+    assert!(delivery.first_opened_at.is_some());
+    assert_eq!(delivery.open_count, 2);
+
+    let opens = sqlx::query!("SELECT user_agent FROM delivery_opens")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(opens.len(), 2);
+    assert_eq!(opens[0].user_agent.as_deref(), Some("Thunderbird"));
+}
+
+#[tokio::test]
+async fn issues_can_be_sent_without_the_tracking_pixel() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "track_opens": false
+    })).await;
+
+    assert!(!last_html_body(&app).await.contains("/o/"));
+}
+
+#[tokio::test]
+async fn clicks_are_logged_and_redirected_to_the_original_url() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": r#"<p><a href="https://example.com/post?id=1&amp;ref=news">Read</a> or <a href="mailto:editor@example.com">write</a></p>"#,
+        },
+        "track_opens": false
+    })).await;
+
+    let html = last_html_body(&app).await;
+    assert!(html.contains(r#"href="mailto:editor@example.com""#));
+
+    let tracked_link = app.find_link(&html, "/r/");
+
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let response = client.get(tracked_link.clone()).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(response.headers()["Location"], "https://example.com/post?id=1&ref=news");
+
+    let click = sqlx::query!("SELECT url FROM link_clicks")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(click.url, "https://example.com/post?id=1&ref=news");
+
+    let mut tampered_link = tracked_link;
+    tampered_link.set_query(Some("url=https%3A%2F%2Fevil.example.com"));
+    let response = client.get(tampered_link).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn issue_stats_count_opens_clicks_and_unsubscribes() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": r#"<p><a href="https://example.com/post">Read</a></p>"#,
+        }
+    })).await;
+    let issue: serde_json::Value = response.json().await.unwrap();
+    let issue_id = issue["issue_id"].as_str().unwrap();
+
+    let html = last_html_body(&app).await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    for link in ["/o/", "/r/", "/r/"] {
+        client.get(app.find_link(&html, link)).send().await.unwrap().error_for_status().unwrap();
+    }
+    client.post(app.find_link(&html, "/unsubscribe/")).send().await.unwrap().error_for_status().unwrap();
+
+    let stats: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/admin/issues/{}/stats", &app.address, issue_id))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(stats["recipients"], 1);
+    assert_eq!(stats["sent"], 1);
+    assert_eq!(stats["failed"], 0);
+    assert_eq!(stats["unique_opens"], 1);
+    assert_eq!(stats["unique_clicks"], 1);
+    assert_eq!(stats["unsubscribes"], 1);
+    assert_eq!(stats["links"][0]["url"], "https://example.com/post");
+    assert_eq!(stats["links"][0]["clicks"], 2);
+
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "unsubscribed");
+
+    let csv = reqwest::Client::new()
+        .get(format!("{}/admin/issues/{}/stats.csv", &app.address, issue_id))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(csv.contains("unsubscribes,,1"));
+}
+
+#[tokio::test]
+async fn the_unsubscribe_link_asks_for_confirmation_and_one_click_posts_unsubscribe() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })).await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let unsubscribe_link = app.find_link(body["html_body"].as_str().unwrap(), "/unsubscribe/");
+    assert_eq!(body["headers"][0]["name"], "List-Unsubscribe");
+    let header = body["headers"][0]["value"].as_str().unwrap();
+    assert!(header.starts_with('<') && header.ends_with(&format!("{}>", unsubscribe_link.path())));
+    assert_eq!(body["headers"][1]["name"], "List-Unsubscribe-Post");
+    assert_eq!(body["headers"][1]["value"], "List-Unsubscribe=One-Click");
+
+    let page = reqwest::get(unsubscribe_link.clone()).await.unwrap();
+    assert_eq!(page.status().as_u16(), 200);
+    assert!(page.text().await.unwrap().contains(r#"<form method="post">"#));
+
+    let status = || async {
+        sqlx::query!("SELECT status FROM subscriptions")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .status
+    };
+    assert_eq!(status().await, "confirmed");
+
+    let response = reqwest::Client::new()
+        .post(unsubscribe_link)
+        .form(&[("List-Unsubscribe", "One-Click")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(status().await, "unsubscribed");
+}
+
+#[tokio::test]
+async fn postmark_bounces_and_complaints_are_counted_against_the_last_issue() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    // a second reader, whose address bounces for good
+    {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.post_subscriptions("name=nora&email=nora%40example.com".into()).await;
+        let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+        let confirmation_links = app.get_confirmation_links(&email_request);
+        reqwest::get(confirmation_links.html).await.unwrap().error_for_status().unwrap();
+    }
+
+    // both readers get the first issue, and nobody gets the second one
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })).await;
+    let issue: serde_json::Value = response.json().await.unwrap();
+    let issue_id = issue["issue_id"].as_str().unwrap();
+
+    let post_event = |event: serde_json::Value| {
+        reqwest::Client::new()
+            .post(format!("{}/webhooks/postmark", &app.address))
+            .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+            .json(&event)
+            .send()
+    };
+
+    let unauthenticated = reqwest::Client::new()
+        .post(format!("{}/webhooks/postmark", &app.address))
+        .json(&serde_json::json!({"RecordType": "Bounce", "Email": "ursula_le_guin@gmail.com"}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unauthenticated.status().as_u16(), 401);
+
+    for event in [
+        serde_json::json!({"RecordType": "Bounce", "Type": "SoftBounce", "Email": "Ursula_Le_Guin@gmail.com"}),
+        serde_json::json!({"RecordType": "SpamComplaint", "Email": "ursula_le_guin@gmail.com"}),
+        serde_json::json!({"RecordType": "Delivery", "Email": "ursula_le_guin@gmail.com"}),
+        serde_json::json!({"RecordType": "Bounce", "Type": "HardBounce", "Email": "Nora@example.com"}),
+    ] {
+        assert_eq!(post_event(event).await.unwrap().status().as_u16(), 200);
+    }
+
+    let stats: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/admin/issues/{}/stats", &app.address, issue_id))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(stats["bounced"], 2);
+    assert_eq!(stats["complaints"], 1);
+
+    let subscribers = sqlx::query!("SELECT email, status FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscribers[0].email, "nora@example.com");
+    assert_eq!(subscribers[0].status, "bounced");
+    assert_eq!(subscribers[1].status, "unsubscribed");
+
+    let response = app.post_newsletters(serde_json::json!({
+        "title": "Second newsletter",
+        "content": { "text": "text", "html": "<p>html</p>" }
+    })).await;
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["sent"], 0);
+}
+
+#[tokio::test]
+async fn failed_sends_are_recorded_against_the_delivery() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": { "text": "text", "html": "<p>html</p>" }
+    })).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["sent"], 0);
+    assert_eq!(report["failed"], 1);
+
+    let delivery = sqlx::query!("SELECT sent_at, failed_at FROM issue_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(delivery.sent_at.is_none());
+    assert!(delivery.failed_at.is_some());
+}
+
+/// Stores confirmed subscribers straight in the database.
+async fn insert_confirmed_subscribers(app: &TestApp, emails: &[&str]) {
+    for email in emails {
+        sqlx::query!(
+                "INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES ($1, $2, 'reader', $3, 'confirmed')",
+                Uuid::new_v4(), email, Utc::now()
+            )
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn one_failed_send_does_not_stop_the_others() {
+    let app = spawn_app().await;
+    insert_confirmed_subscribers(&app, &["a@example.com", "b@example.com", "c@example.com"]).await;
+
+    Mock::given(path("/email"))
+        .and(body_partial_json(serde_json::json!({ "to": "b@example.com" })))
+        .respond_with(ResponseTemplate::new(500))
+        .with_priority(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let report: serde_json::Value = app.post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "text", "html": "<p>html</p>" }
+        }))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(report["sent"], 2);
+    assert_eq!(report["failed"], 1);
+    assert_eq!(report["queued"], 0);
+
+    let failed = sqlx::query!(
+            r#"SELECT s.email AS "email!" FROM issue_deliveries d JOIN subscriptions s ON s.id = d.subscriber_id WHERE d.failed_at IS NOT NULL"#
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(failed.email, "b@example.com");
+}
+
+#[tokio::test]
+async fn issues_are_sent_to_several_subscribers_at_once() {
+    let app = spawn_app().await;
+    let emails: Vec<String> = (0..6).map(|i| format!("reader{}@example.com", i)).collect();
+    insert_confirmed_subscribers(&app, &emails.iter().map(String::as_str).collect::<Vec<_>>()).await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(500)))
+        .expect(6)
+        .mount(&app.email_server)
+        .await;
+
+    let start = Instant::now();
+    let response = app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": { "text": "text", "html": "<p>html</p>" }
+    })).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    // one after the other, the six sends would take three seconds
+    assert!(start.elapsed() < Duration::from_secs(2), "took {:?}", start.elapsed());
+}
+
+/// Answers a call to `/email/batch`, refusing the messages to `refused`
+/// while `refuse` says so for the batch.
+fn batch_response(request: &Request, refused: &str, refuse: impl Fn(usize) -> bool) -> ResponseTemplate {
+    let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+
+    let results: Vec<serde_json::Value> = messages
+        .iter()
+        .map(|message| match message["to"] == refused && refuse(messages.len()) {
+            true => serde_json::json!({ "ErrorCode": 429, "Message": "Rate limit exceeded" }),
+            false => serde_json::json!({ "ErrorCode": 0, "Message": "OK" }),
+        })
+        .collect();
+
+    ResponseTemplate::new(200).set_body_json(results)
+}
+
+async fn batch_messages(app: &TestApp) -> Vec<Vec<String>> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            messages.iter().map(|message| message["to"].as_str().unwrap().to_string()).collect()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn batches_only_send_the_failed_messages_again() {
+    let app = spawn_app_with(|config| config.email_client.batch = true).await;
+    insert_confirmed_subscribers(&app, &["a@example.com", "b@example.com", "c@example.com"]).await;
+
+    // b is refused in the first batch only
+    Mock::given(path("/email/batch"))
+        .respond_with(|request: &Request| batch_response(request, "b@example.com", |messages| messages > 1))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let report: serde_json::Value = app.post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "text", "html": "<p>html</p>" }
+        }))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(report["sent"], 3);
+    assert_eq!(report["failed"], 0);
+
+    let batches = batch_messages(&app).await;
+    assert_eq!(batches[0].len(), 3);
+    assert_eq!(batches[1], vec!["b@example.com"]);
+}
+
+#[tokio::test]
+async fn refused_messages_and_failed_batches_are_only_sent_again_when_it_may_help() {
+    let app = spawn_app_with(|config| config.email_client.batch = true).await;
+    insert_confirmed_subscribers(&app, &["a@example.com", "b@example.com"]).await;
+
+    // the first call fails as a whole, and b is an inactive recipient
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .respond_with(|request: &Request| {
+            let messages: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+            let results: Vec<serde_json::Value> = messages
+                .iter()
+                .map(|message| match message["to"] == "b@example.com" {
+                    true => serde_json::json!({ "ErrorCode": 406, "Message": "Inactive recipient" }),
+                    false => serde_json::json!({ "ErrorCode": 0, "Message": "OK" }),
+                })
+                .collect();
+            ResponseTemplate::new(200).set_body_json(results)
+        })
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let report: serde_json::Value = app.post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "text", "html": "<p>html</p>" }
+        }))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(report["sent"], 1);
+    assert_eq!(report["failed"], 1);
+    assert_eq!(batch_messages(&app).await.len(), 2);
+}
+
+#[tokio::test]
+async fn messages_refused_in_every_batch_are_recorded_as_failed() {
+    let app = spawn_app_with(|config| config.email_client.batch = true).await;
+    insert_confirmed_subscribers(&app, &["a@example.com", "b@example.com"]).await;
+
+    Mock::given(path("/email/batch"))
+        .respond_with(|request: &Request| batch_response(request, "b@example.com", |_| true))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    let report: serde_json::Value = app.post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "text", "html": "<p>html</p>" }
+        }))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(report["sent"], 1);
+    assert_eq!(report["failed"], 1);
+
+    let failed = sqlx::query!(
+            r#"SELECT s.email AS "email!" FROM issue_deliveries d JOIN subscriptions s ON s.id = d.subscriber_id WHERE d.failed_at IS NOT NULL"#
+        )
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].email, "b@example.com");
+}
@@ -0,0 +1,192 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn issue(title: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    })
+}
+
+/// Imports a confirmed subscriber, sends them an issue and returns the link
+/// to their preference page from its text body.
+pub async fn preferences_link(app: &TestApp) -> reqwest::Url {
+    app.post_subscribers_import("mode=confirmed&consent=imported", "email,name\nursula@example.com,Ursula\n").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(issue("Newsletter title")).await;
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+    app.find_link(body["text_body"].as_str().unwrap(), "/preferences/")
+}
+
+pub async fn post_form(link: &reqwest::Url, form: &[(&str, &str)]) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(link.clone())
+        .form(form)
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+async fn sent_emails(app: &TestApp) -> usize {
+    app.email_server.received_requests().await.unwrap().len()
+}
+
+#[tokio::test]
+async fn every_issue_links_to_the_preference_page() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(200, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains(r#"value="Ursula""#));
+}
+
+#[tokio::test]
+async fn preference_pages_with_a_wrong_signature_are_rejected() {
+    let app = spawn_app().await;
+    let mut link = preferences_link(&app).await;
+
+    let forged = link.path().rsplit_once('/').unwrap().0.to_string() + "/0000";
+    link.set_path(&forged);
+
+    assert_eq!(400, reqwest::get(link.clone()).await.unwrap().status().as_u16());
+    assert_eq!(400, post_form(&link, &[("action", "pause")]).await.status().as_u16());
+}
+
+#[tokio::test]
+async fn subscribers_can_change_their_name_and_topics() {
+    let app = spawn_app().await;
+
+    reqwest::Client::new()
+        .put(format!("{}/admin/topics/rust", &app.address))
+        .json(&serde_json::json!({ "label": "Rust news" }))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+
+    let link = preferences_link(&app).await;
+
+    let response = post_form(&link, &[("action", "save"), ("name", "Ursula K. Le Guin"), ("topic_rust", "on")]).await;
+    assert_eq!(200, response.status().as_u16());
+
+    let subscriber = sqlx::query!("SELECT id, name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.name, "Ursula K. Le Guin");
+
+    let tags = sqlx::query!("SELECT tag FROM subscriber_tags WHERE subscriber_id = $1", subscriber.id)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tags.len(), 1);
+    assert_eq!(tags[0].tag, "rust");
+
+    post_form(&link, &[("action", "save"), ("name", "Ursula K. Le Guin")]).await;
+
+    let tags = sqlx::query!("SELECT tag FROM subscriber_tags WHERE subscriber_id = $1", subscriber.id)
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(tags.is_empty());
+}
+
+#[tokio::test]
+async fn names_are_validated() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+
+    let response = post_form(&link, &[("action", "save"), ("name", "Ursula<script>")]).await;
+    assert_eq!(400, response.status().as_u16());
+
+    let subscriber = sqlx::query!("SELECT name FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.name, "Ursula");
+}
+
+#[tokio::test]
+async fn paused_subscribers_do_not_receive_issues_until_they_resume() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+    assert_eq!(sent_emails(&app).await, 1);
+
+    assert_eq!(200, post_form(&link, &[("action", "pause")]).await.status().as_u16());
+    app.post_newsletters(issue("While paused")).await;
+    assert_eq!(sent_emails(&app).await, 1);
+
+    post_form(&link, &[("action", "resume")]).await;
+    app.post_newsletters(issue("After the pause")).await;
+    assert_eq!(sent_emails(&app).await, 2);
+}
+
+#[tokio::test]
+async fn subscribers_can_unsubscribe_from_the_preference_page() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+
+    assert_eq!(200, post_form(&link, &[("action", "unsubscribe")]).await.status().as_u16());
+
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "unsubscribed");
+
+    // and cannot change anything afterwards
+    assert_eq!(400, post_form(&link, &[("action", "resume")]).await.status().as_u16());
+}
+
+#[tokio::test]
+async fn weekly_digest_subscribers_get_issues_together() {
+    let app = spawn_app().await;
+    let link = preferences_link(&app).await;
+
+    post_form(&link, &[("action", "save"), ("name", "Ursula"), ("frequency", "weekly_digest")]).await;
+
+    app.post_newsletters(issue("First issue")).await;
+    app.post_newsletters(issue("Second issue")).await;
+    assert_eq!(sent_emails(&app).await, 1);
+
+    let report: serde_json::Value = reqwest::Client::new()
+        .post(format!("{}/admin/digests/weekly", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(report["sent"], 1);
+    assert_eq!(report["issues"], 2);
+
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+
+    assert_eq!(body["subject"], "Your weekly digest: 2 issues");
+    let text = body["text_body"].as_str().unwrap();
+    assert!(text.contains("First issue") && text.contains("Second issue"));
+
+    let unsent = sqlx::query!(r#"SELECT count(*) AS "count!" FROM issue_deliveries WHERE sent_at IS NULL"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(unsent.count, 0);
+}
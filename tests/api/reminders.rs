@@ -0,0 +1,109 @@
+use chrono::{Duration, Utc};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::{spawn_app, TestApp};
+
+async fn post_reminders(app: &TestApp) -> serde_json::Value {
+    reqwest::Client::new()
+        .post(format!("{}/admin/subscribers/reminders", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request")
+        .json()
+        .await
+        .unwrap()
+}
+
+/// Signs up a pending subscriber and pretends it happened `days` days ago.
+async fn create_pending_subscriber(app: &TestApp, days: i64) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into()).await;
+
+    sqlx::query!("UPDATE subscriptions SET subscribed_at = $1", Utc::now() - Duration::days(days))
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+async fn reminder_count(app: &TestApp) -> i32 {
+    sqlx::query!("SELECT reminder_count FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .reminder_count
+}
+
+#[tokio::test]
+async fn pending_subscribers_are_reminded_with_a_link_that_confirms() {
+    let app = spawn_app().await;
+    create_pending_subscriber(&app, 4).await;
+
+    let report = post_reminders(&app).await;
+    assert_eq!(report["sent"], 1);
+    assert_eq!(reminder_count(&app).await, 1);
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 2);
+    let first = app.get_confirmation_links(&requests[0]);
+    let reminder = app.get_confirmation_links(&requests[1]);
+    assert_ne!(first.plain_text, reminder.plain_text);
+
+    reqwest::get(reminder.html).await.unwrap().error_for_status().unwrap();
+
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "confirmed");
+}
+
+#[tokio::test]
+async fn reminders_wait_for_the_interval() {
+    let app = spawn_app().await;
+    create_pending_subscriber(&app, 1).await;
+
+    let report = post_reminders(&app).await;
+
+    assert_eq!(report["sent"], 0);
+    assert_eq!(reminder_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn reminders_stop_at_the_limit() {
+    let app = spawn_app().await;
+    create_pending_subscriber(&app, 30).await;
+
+    for expected in [1, 1, 0] {
+        let report = post_reminders(&app).await;
+        assert_eq!(report["sent"], expected);
+
+        // the next reminder is due
+        sqlx::query!("UPDATE subscriptions SET last_reminded_at = $1", Utc::now() - Duration::days(4))
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+    }
+
+    assert_eq!(reminder_count(&app).await, 2);
+    assert_eq!(app.email_server.received_requests().await.unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_are_not_reminded() {
+    let app = spawn_app().await;
+    create_pending_subscriber(&app, 4).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html).await.unwrap().error_for_status().unwrap();
+
+    let report = post_reminders(&app).await;
+
+    assert_eq!(report["sent"], 0);
+}
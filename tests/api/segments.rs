@@ -0,0 +1,212 @@
+use crate::helpers::{spawn_app, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Imports confirmed subscribers and returns their ids, in the order given.
+async fn create_subscribers(app: &TestApp, emails: &[&str]) -> Vec<Uuid> {
+    let csv: String = std::iter::once("email,name".to_string())
+        .chain(emails.iter().map(|email| format!("{},Someone", email)))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    app.post_subscribers_import("mode=confirmed&consent=imported", &csv).await;
+
+    let mut ids = Vec::new();
+    for email in emails {
+        let id = sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .id;
+        ids.push(id);
+    }
+
+    ids
+}
+
+async fn tag(app: &TestApp, subscriber_id: Uuid, tag: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .put(format!("{}/admin/subscribers/{}/tags/{}", &app.address, subscriber_id, tag))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+async fn put_segment(app: &TestApp, name: &str, expression: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .put(format!("{}/admin/segments/{}", &app.address, name))
+        .json(&serde_json::json!({ "expression": expression }))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+async fn recipients(app: &TestApp) -> Vec<String> {
+    let mut recipients: Vec<String> = app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            body["to"].as_str().unwrap().to_string()
+        })
+        .collect();
+
+    recipients.sort();
+    recipients
+}
+
+fn issue(audience: serde_json::Value) -> serde_json::Value {
+    let mut body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+
+    body.as_object_mut().unwrap().extend(audience.as_object().unwrap().clone());
+    body
+}
+
+#[tokio::test]
+async fn subscribers_can_be_tagged() {
+    let app = spawn_app().await;
+    let ids = create_subscribers(&app, &["a@example.com", "b@example.com"]).await;
+
+    assert_eq!(204, tag(&app, ids[0], "Beta").await.status().as_u16());
+    assert_eq!(204, tag(&app, ids[0], "beta").await.status().as_u16());
+    assert_eq!(204, tag(&app, ids[1], "beta").await.status().as_u16());
+    assert_eq!(204, tag(&app, ids[1], "staff").await.status().as_u16());
+
+    let tags: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/admin/tags", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(
+        tags,
+        serde_json::json!([{ "tag": "beta", "subscribers": 2 }, { "tag": "staff", "subscribers": 1 }])
+    );
+}
+
+#[tokio::test]
+async fn tagging_rejects_invalid_tags_and_unknown_subscribers() {
+    let app = spawn_app().await;
+    let ids = create_subscribers(&app, &["a@example.com"]).await;
+
+    assert_eq!(400, tag(&app, ids[0], "beta%20testers").await.status().as_u16());
+    assert_eq!(404, tag(&app, Uuid::new_v4(), "beta").await.status().as_u16());
+}
+
+#[tokio::test]
+async fn invalid_segments_are_rejected() {
+    let app = spawn_app().await;
+
+    assert_eq!(400, put_segment(&app, "beta", "tag:beta AND").await.status().as_u16());
+    assert_eq!(400, put_segment(&app, "beta", "plan = pro").await.status().as_u16());
+}
+
+#[tokio::test]
+async fn saved_segments_report_their_recipients() {
+    let app = spawn_app().await;
+    let ids = create_subscribers(&app, &["a@example.com", "b@example.com"]).await;
+    tag(&app, ids[0], "beta").await;
+
+    let response = put_segment(&app, "beta", "tag:beta").await;
+    assert_eq!(200, response.status().as_u16());
+
+    let segment: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(segment["expression"], "tag:beta");
+    assert_eq!(segment["recipients"], 1);
+}
+
+#[tokio::test]
+async fn issues_sent_to_a_segment_only_reach_its_subscribers() {
+    let app = spawn_app().await;
+    let ids = create_subscribers(&app, &["a@example.com", "b@example.com", "c@example.com"]).await;
+    tag(&app, ids[0], "beta").await;
+    tag(&app, ids[1], "beta").await;
+    tag(&app, ids[1], "staff").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    put_segment(&app, "beta-customers", "confirmed AND tag:beta AND NOT tag:staff").await;
+
+    let response = app.post_newsletters(issue(serde_json::json!({ "segment": "beta-customers" }))).await;
+    assert_eq!(200, response.status().as_u16());
+
+    assert_eq!(recipients(&app).await, vec!["a@example.com"]);
+}
+
+#[tokio::test]
+async fn issues_can_be_sent_with_ad_hoc_filters_on_attributes() {
+    let app = spawn_app().await;
+    let ids = create_subscribers(&app, &["a@example.com", "b@example.com", "c@example.com"]).await;
+
+    reqwest::Client::new()
+        .post(format!("{}/admin/attributes", &app.address))
+        .json(&serde_json::json!({ "name": "seats", "type": "number" }))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    for (id, seats) in ids.iter().zip([5, 50, 500]) {
+        sqlx::query!("UPDATE subscriptions SET attributes = jsonb_build_object('seats', $2::int) WHERE id = $1", id, seats)
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+    }
+    tag(&app, ids[2], "staff").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(issue(serde_json::json!({
+        "include": "seats >= 10",
+        "exclude": "tag:staff",
+    }))).await;
+    assert_eq!(200, response.status().as_u16());
+
+    assert_eq!(recipients(&app).await, vec!["b@example.com"]);
+}
+
+#[tokio::test]
+async fn issues_sent_to_an_unknown_segment_are_rejected() {
+    let app = spawn_app().await;
+    create_subscribers(&app, &["a@example.com"]).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(issue(serde_json::json!({ "segment": "nobody" }))).await;
+    assert_eq!(400, response.status().as_u16());
+
+    let issues = sqlx::query!(r#"SELECT count(*) AS "count!" FROM newsletter_issues"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.count, 0);
+}
@@ -0,0 +1,181 @@
+use chrono::{Duration, Utc};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use newsletter::job_lock::{try_lock, Job};
+use crate::helpers::{spawn_app, TestApp};
+
+async fn put_step(app: &TestApp, day: i32, subject: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .put(format!("{}/admin/sequences/welcome/steps/{}", &app.address, day))
+        .json(&serde_json::json!({
+            "subject": subject,
+            "text": "Hello {{ name }}",
+            "html": "<p>Hello {{ name }}</p>",
+        }))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request")
+}
+
+async fn put_sequence(app: &TestApp, active: bool) {
+    let response = reqwest::Client::new()
+        .put(format!("{}/admin/sequences/welcome", &app.address))
+        .json(&serde_json::json!({ "active": active }))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    assert_eq!(204, response.status().as_u16());
+}
+
+async fn run_sequences(app: &TestApp) -> serde_json::Value {
+    reqwest::Client::new()
+        .post(format!("{}/admin/sequences/run", &app.address))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request")
+        .json()
+        .await
+        .unwrap()
+}
+
+/// Signs up and confirms a subscriber, as a subscriber would.
+async fn confirm_subscriber(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into()).await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html).await.unwrap().error_for_status().unwrap();
+}
+
+async fn last_email(app: &TestApp) -> serde_json::Value {
+    let email_request = app.email_server.received_requests().await.unwrap().pop().unwrap();
+    serde_json::from_slice(&email_request.body).unwrap()
+}
+
+async fn backdate_enrollment(app: &TestApp, days: i64) {
+    sqlx::query!("UPDATE sequence_enrollments SET enrolled_at = $1", Utc::now() - Duration::days(days))
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn confirmed_subscribers_get_each_step_when_it_is_due() {
+    let app = spawn_app().await;
+    put_sequence(&app, true).await;
+    put_step(&app, 0, "Welcome aboard").await;
+    put_step(&app, 3, "Our best issues").await;
+    confirm_subscriber(&app).await;
+
+    let report = run_sequences(&app).await;
+    assert_eq!(report["sent"], 1);
+    let email = last_email(&app).await;
+    assert_eq!(email["subject"], "Welcome aboard");
+    assert!(email["text_body"].as_str().unwrap().starts_with("Hello le guin"));
+    assert!(email["text_body"].as_str().unwrap().contains("/preferences/"));
+
+    // day 3 is not due yet
+    assert_eq!(run_sequences(&app).await["sent"], 0);
+
+    backdate_enrollment(&app, 3).await;
+    assert_eq!(run_sequences(&app).await["sent"], 1);
+    assert_eq!(last_email(&app).await["subject"], "Our best issues");
+
+    assert_eq!(run_sequences(&app).await["sent"], 0);
+}
+
+#[tokio::test]
+async fn a_run_skips_the_steps_while_another_run_is_under_way() {
+    let app = spawn_app().await;
+    put_sequence(&app, true).await;
+    put_step(&app, 0, "Welcome aboard").await;
+    confirm_subscriber(&app).await;
+
+    // as if another instance were sending the steps
+    let lock = try_lock(&app.db_pool, Job::Sequences).await.unwrap().unwrap();
+    assert!(try_lock(&app.db_pool, Job::Sequences).await.unwrap().is_none());
+    assert_eq!(run_sequences(&app).await["sent"], 0);
+
+    drop(lock);
+    assert_eq!(run_sequences(&app).await["sent"], 1);
+    assert_eq!(run_sequences(&app).await["sent"], 0);
+}
+
+#[tokio::test]
+async fn edited_steps_are_sent_without_a_redeploy() {
+    let app = spawn_app().await;
+    put_sequence(&app, true).await;
+    put_step(&app, 0, "Welcome aboard").await;
+    confirm_subscriber(&app).await;
+
+    assert_eq!(204, put_step(&app, 0, "Welcome to the newsletter").await.status().as_u16());
+    run_sequences(&app).await;
+
+    assert_eq!(last_email(&app).await["subject"], "Welcome to the newsletter");
+}
+
+#[tokio::test]
+async fn unsubscribing_stops_the_sequence() {
+    let app = spawn_app().await;
+    put_sequence(&app, true).await;
+    put_step(&app, 0, "Welcome aboard").await;
+    put_step(&app, 3, "Our best issues").await;
+    confirm_subscriber(&app).await;
+    run_sequences(&app).await;
+
+    let link = app.find_link(last_email(&app).await["text_body"].as_str().unwrap(), "/preferences/");
+    reqwest::Client::new()
+        .post(link)
+        .form(&[("action", "unsubscribe")])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    backdate_enrollment(&app, 3).await;
+    assert_eq!(run_sequences(&app).await["sent"], 0);
+
+    let enrollment = sqlx::query!("SELECT stopped_at FROM sequence_enrollments")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(enrollment.stopped_at.is_some());
+}
+
+#[tokio::test]
+async fn inactive_sequences_do_not_enroll_subscribers() {
+    let app = spawn_app().await;
+    put_sequence(&app, false).await;
+    put_step(&app, 0, "Welcome aboard").await;
+    confirm_subscriber(&app).await;
+
+    assert_eq!(run_sequences(&app).await["sent"], 0);
+
+    let enrollments = sqlx::query!(r#"SELECT count(*) AS "count!" FROM sequence_enrollments"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(enrollments.count, 0);
+}
+
+#[tokio::test]
+async fn steps_need_an_existing_sequence_and_a_valid_day() {
+    let app = spawn_app().await;
+
+    assert_eq!(404, put_step(&app, 0, "Welcome aboard").await.status().as_u16());
+
+    put_sequence(&app, true).await;
+    assert_eq!(400, put_step(&app, -1, "Welcome aboard").await.status().as_u16());
+    assert_eq!(400, put_step(&app, 0, " ").await.status().as_u16());
+}
@@ -1,4 +1,4 @@
-use crate::helpers::spawn_app;
+use crate::helpers::{spawn_app, spawn_app_with};
 use wiremock::matchers::{method,path};
 use wiremock::{Mock,ResponseTemplate};
 
@@ -116,7 +116,7 @@ async fn subscribe_persists_the_new_subscriber() {
         .mount(&app.email_server)
         .await;
 
-    app.post_subscriptions(body.into()).await;
+    app.post_subscriptions(body).await;
 
     let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
         .fetch_one(&app.db_pool)
@@ -144,4 +144,70 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
 
     // Assert
     assert_eq!(response.status().as_u16(), 500);
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn subscribe_records_how_the_subscriber_gave_consent() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("User-Agent", "Mozilla/5.0 (test)")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com&form=footer&form_version=3")
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    let saved = sqlx::query!(
+            "SELECT consent_at, consent_ip, consent_user_agent, signup_form, signup_form_version FROM subscriptions"
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to fetch saved subscription");
+
+    assert!(saved.consent_at.is_some());
+    assert_eq!(saved.consent_ip.as_deref(), Some("127.0.0.1"));
+    assert_eq!(saved.consent_user_agent.as_deref(), Some("Mozilla/5.0 (test)"));
+    assert_eq!(saved.signup_form.as_deref(), Some("footer"));
+    assert_eq!(saved.signup_form_version.as_deref(), Some("3"));
+}
+
+async fn consent_ip_of_signup_forwarded_for(app: &crate::helpers::TestApp, forwarded_for: &str) -> Option<String> {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("X-Forwarded-For", forwarded_for)
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("failed to execute request");
+
+    sqlx::query!("SELECT consent_ip FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("failed to fetch saved subscription")
+        .consent_ip
+}
+
+#[tokio::test]
+async fn subscribe_only_believes_x_forwarded_for_from_trusted_proxies() {
+    for (trusted_proxies, expected) in [(vec![], "127.0.0.1"), (vec!["127.0.0.1".parse().unwrap()], "198.51.100.1")] {
+        let app = spawn_app_with(|config| config.application.trusted_proxies = trusted_proxies).await;
+
+        Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&app.email_server)
+            .await;
+
+        let consent_ip = consent_ip_of_signup_forwarded_for(&app, "6.6.6.6, 198.51.100.1").await;
+
+        assert_eq!(consent_ip.as_deref(), Some(expected));
+    }
+}
@@ -1,7 +1,6 @@
 use crate::helpers::spawn_app;
 use wiremock::{Mock, ResponseTemplate};
 use wiremock::matchers::{path, method};
-use reqwest::Url;
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_400() {
@@ -50,7 +49,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
 
     app.post_subscriptions(body.into()).await;
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
-    let confirmation_links = app.get_confirmation_links(&email_request);
+    let confirmation_links = app.get_confirmation_links(email_request);
 
     // Act
     reqwest::get(confirmation_links.html)
@@ -68,4 +67,35 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.email, "ursula_le_guin@gmail.com");
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn confirming_records_when_and_where_the_subscription_was_confirmed() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into()).await;
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = app.get_confirmation_links(email_request);
+
+    reqwest::get(confirmation_links.html.clone()).await.unwrap().error_for_status().unwrap();
+    let first = sqlx::query!("SELECT confirmed_at, confirmed_ip FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    reqwest::get(confirmation_links.html).await.unwrap().error_for_status().unwrap();
+    let second = sqlx::query!("SELECT confirmed_at FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    assert!(first.confirmed_at.is_some());
+    assert_eq!(first.confirmed_ip.as_deref(), Some("127.0.0.1"));
+    assert_eq!(first.confirmed_at, second.confirmed_at);
+}
@@ -0,0 +1,172 @@
+use crate::helpers::{spawn_app, TestApp};
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Imports a confirmed subscriber who was sent `issues` issues and opened none.
+async fn create_unengaged_subscriber(app: &TestApp, issues: usize) -> Uuid {
+    app.post_subscribers_import(
+        "mode=confirmed&consent=double%20opt-in%20on%20the%20old%20site",
+        "email,name\nursula@example.com,Ursula\n",
+    ).await;
+
+    let subscriber = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    for i in 0..issues {
+        let issue_id = Uuid::new_v4();
+        sqlx::query!(
+                r#"
+                    INSERT INTO newsletter_issues (id, title, text_content, html_content, published_at, slug)
+                    VALUES ($1, $2, 'text', '<p>html</p>', now(), $3)
+                "#,
+                issue_id, format!("Issue #{}", i), format!("issue-{}", i)
+            )
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+        sqlx::query!(
+                r#"
+                    INSERT INTO issue_deliveries (id, newsletter_issue_id, subscriber_id, token, sent_at)
+                    VALUES ($1, $2, $3, $4, now())
+                "#,
+                Uuid::new_v4(), issue_id, subscriber.id, Uuid::new_v4().to_string()
+            )
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+    }
+
+    subscriber.id
+}
+
+async fn post_sunset(app: &TestApp, query: &str) -> serde_json::Value {
+    reqwest::Client::new()
+        .post(format!("{}/admin/subscribers/sunset?{}", &app.address, query))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .expect("failed to execute request")
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn unengaged_subscribers_get_a_reengagement_email_then_become_inactive() {
+    let app = spawn_app().await;
+    let subscriber_id = create_unengaged_subscriber(&app, 12).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let report = post_sunset(&app, "").await;
+    assert_eq!(report["reengagement_sent"], 1);
+    assert_eq!(report["deactivated"], 0);
+
+    sqlx::query!(
+            "UPDATE subscriptions SET reengagement_sent_at = $1",
+            Utc::now() - Duration::days(15)
+        )
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let report = post_sunset(&app, "").await;
+    assert_eq!(report["deactivated"], 1);
+
+    let subscriber = sqlx::query!("SELECT status FROM subscriptions WHERE id = $1", subscriber_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(subscriber.status, "inactive");
+}
+
+#[tokio::test]
+async fn subscribers_with_fewer_unengaged_issues_are_left_alone() {
+    let app = spawn_app().await;
+    create_unengaged_subscriber(&app, 11).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let report = post_sunset(&app, "").await;
+
+    assert_eq!(report["reengagement_sent"], 0);
+}
+
+#[tokio::test]
+async fn the_reengagement_link_keeps_the_subscriber_subscribed() {
+    let app = spawn_app().await;
+    let subscriber_id = create_unengaged_subscriber(&app, 12).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    post_sunset(&app, "").await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let stay_link = app.find_link(body["html_body"].as_str().unwrap(), "/subscriptions/stay/");
+    reqwest::get(stay_link).await.unwrap().error_for_status().unwrap();
+
+    let subscriber = sqlx::query!(
+            "SELECT last_engaged_at, reengagement_sent_at FROM subscriptions WHERE id = $1",
+            subscriber_id
+        )
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(subscriber.last_engaged_at.is_some());
+    assert!(subscriber.reengagement_sent_at.is_none());
+
+    let engagement: serde_json::Value = reqwest::Client::new()
+        .get(format!("{}/admin/subscribers/{}/engagement", &app.address, subscriber_id))
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(engagement["issues_since_engagement"], 0);
+    assert_eq!(engagement["score"], 0);
+}
+
+#[tokio::test]
+async fn inactive_subscribers_only_receive_issues_that_opt_in() {
+    let app = spawn_app().await;
+    create_unengaged_subscriber(&app, 0).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'inactive'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    for include_inactive in [false, true] {
+        app.post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "text", "html": "<p>html</p>" },
+            "include_inactive": include_inactive
+        })).await;
+    }
+}
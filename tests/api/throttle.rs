@@ -0,0 +1,125 @@
+use std::time::{Duration, Instant};
+use chrono::Utc;
+use uuid::Uuid;
+use wiremock::matchers::path;
+use wiremock::{Mock, ResponseTemplate};
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+async fn insert_confirmed_subscribers(app: &TestApp, count: usize) {
+    for i in 0..count {
+        sqlx::query!(
+                "INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES ($1, $2, 'reader', $3, 'confirmed')",
+                Uuid::new_v4(), format!("reader{}@example.com", i), Utc::now()
+            )
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+    }
+}
+
+async fn publish(app: &TestApp) -> serde_json::Value {
+    app.post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "text": "text", "html": "<p>html</p>" }
+        }))
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+async fn metrics(app: &TestApp) -> String {
+    reqwest::get(format!("{}/metrics", &app.address))
+        .await
+        .expect("failed to execute request")
+        .error_for_status()
+        .unwrap()
+        .text()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn sends_are_spread_out_to_stay_within_the_quota() {
+    let app = spawn_app_with(|config| config.email_client.max_per_second = 2).await;
+    insert_confirmed_subscribers(&app, 5).await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(5)
+        .mount(&app.email_server)
+        .await;
+
+    let start = Instant::now();
+    let report = publish(&app).await;
+
+    assert_eq!(report["sent"], 5);
+    // two go out at once, then one every half second
+    assert!(start.elapsed() >= Duration::from_millis(1400), "took only {:?}", start.elapsed());
+
+    let metrics = metrics(&app).await;
+    assert!(metrics.contains("email_throttle_limit{window=\"second\"} 2\n"), "{}", metrics);
+    assert!(!metrics.contains("email_throttle_waits_total 0\n"), "{}", metrics);
+}
+
+#[tokio::test]
+async fn a_429_pauses_sending_for_the_retry_after() {
+    let app = spawn_app().await;
+    insert_confirmed_subscribers(&app, 1).await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let start = Instant::now();
+    let report = publish(&app).await;
+
+    assert_eq!(report["sent"], 1);
+    assert_eq!(report["failed"], 0);
+    assert!(start.elapsed() >= Duration::from_secs(1), "took only {:?}", start.elapsed());
+    assert!(metrics(&app).await.contains("email_provider_rate_limited_total 1\n"));
+}
+
+#[tokio::test]
+async fn metrics_leave_out_the_quotas_that_are_not_set() {
+    let app = spawn_app().await;
+
+    let metrics = metrics(&app).await;
+
+    assert!(!metrics.contains("email_throttle_limit{"));
+    assert!(metrics.contains("email_throttle_paused_seconds 0.000\n"));
+}
+
+#[tokio::test]
+async fn signups_do_not_wait_out_a_long_rate_limit() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "3600"))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let start = Instant::now();
+    let response = app.post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(start.elapsed() < Duration::from_secs(5), "took {:?}", start.elapsed());
+
+    let queued = sqlx::query!("SELECT recipient FROM email_outbox")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(queued.len(), 1);
+    assert_eq!(queued[0].recipient, "ursula_le_guin@gmail.com");
+}